@@ -0,0 +1,984 @@
+//! Depth to mate tables for small material sets, generated by the `gen_tb` tool with retrograde analysis
+//! over every placement of the pieces, see `generate`.
+//!
+//! Table file:
+//! `b"CBTB"`, u8 version = 2, u8 name length, the material's name in ASCII, eg. `KBNK`,
+//! then one u8 per position index, see `Material::get_index`.
+//! Positions are mirrored so that the white king is in files a to d, and without pawns also in the a1-d1-d4
+//! triangle, which leaves 462 placements of the kings without pawns and 1806 with. The other pieces are indexed
+//! by the squares left to them, pawns only on their 48 squares, and two of the same piece by the pair of squares.
+//! The index is `(player with the turn * king pairs + king pair) * other placements + other placement`,
+//! with the other pieces in the order of `Material::get_pieces` and the earlier ones more significant.
+//! Values are 0 for a draw, `ILLEGAL` for positions which can't happen, otherwise plies to mate plus one,
+//! where an odd number of plies is a win for the player with the turn and an even number a loss.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, self};
+use super::super::game::entities::*;
+use super::super::game::coords::*;
+use super::super::game::board::*;
+#[cfg(not(target_arch = "wasm32"))]
+use super::super::game::move_list::*;
+#[cfg(not(target_arch = "wasm32"))]
+use super::super::game::check_handler::*;
+
+const MAGIC: &[u8; 4] = b"CBTB";
+const VERSION: u8 = 2;
+const ILLEGAL: u8 = 255;
+/// Longest mate which fits in a value
+const MAX_PLIES: usize = 253;
+
+/// Most pieces in a table, kings included. A table takes up to 11MB, see `Material::get_size`.
+pub const MAX_PIECES: usize = 4;
+
+/// Eval of a mate in 0 plies, in pawns. Each ply to mate takes a pawn off so that search goes for the fastest mate.
+pub const DTM_WIN_EVAL: f32 = 2000.;
+
+/// Order of pieces in material names and tables after the kings
+static NAME_ORDER: [Piece; 5] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::Pawn];
+
+#[derive(Clone, Debug)]
+pub enum DtmError {
+    BadMagic,
+    BadVersion(u8),
+    BadMaterial(String),
+    /// Expected and actual length in bytes
+    BadLength(usize, usize),
+    Io(String)
+}
+
+impl Display for DtmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            DtmError::BadMagic => write!(f, "Not a table file"),
+            DtmError::BadVersion(v) => write!(f, "Unsupported version {}", v),
+            DtmError::BadMaterial(name) => write!(f, "Unsupported material {}", name),
+            DtmError::BadLength(expected, actual) => write!(f, "Expected {} bytes but got {}", expected, actual),
+            DtmError::Io(e) => write!(f, "IO error - {}", e)
+        }
+    }
+}
+
+/// Outcome for the player with the turn, with the number of plies to mate
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dtm {
+    Win(u8),
+    Draw,
+    Loss(u8)
+}
+
+impl Dtm {
+
+    fn from_value(v: u8) -> Self {
+        match v {
+            0 => Dtm::Draw,
+            v if (v - 1) % 2 == 1 => Dtm::Win(v - 1),
+            v => Dtm::Loss(v - 1)
+        }
+    }
+
+    /// The outcome for the other player one ply earlier
+    fn before_move(self) -> Self {
+        match self {
+            Dtm::Win(plies) => Dtm::Loss(plies + 1),
+            Dtm::Draw => Dtm::Draw,
+            Dtm::Loss(plies) => Dtm::Win(plies + 1)
+        }
+    }
+
+    /// Faster wins, then draws, then slower losses
+    fn get_rank(self) -> i32 {
+        match self {
+            Dtm::Win(plies) => 1000 - plies as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(plies) => -1000 + plies as i32
+        }
+    }
+
+    /// For the player with the turn, in pawns
+    pub fn to_eval(self) -> f32 {
+        match self {
+            Dtm::Win(plies) => DTM_WIN_EVAL - plies as f32,
+            Dtm::Draw => 0.,
+            Dtm::Loss(plies) => -DTM_WIN_EVAL + plies as f32
+        }
+    }
+}
+
+impl Display for Dtm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Dtm::Win(plies) => write!(f, "win in {} plies", plies),
+            Dtm::Draw => write!(f, "draw"),
+            Dtm::Loss(plies) => write!(f, "loss in {} plies", plies)
+        }
+    }
+}
+
+/// Piece counts indexed by `Player` then `Piece`
+type MaterialKey = [[u8; 6]; 2];
+
+fn get_board_key(board: &Board) -> MaterialKey {
+    let score = board.get_score();
+    let mut key = [[0; 6]; 2];
+    for player in [Player::White, Player::Black].iter() {
+        for piece in PIECES.iter() {
+            key[*player as usize][*piece as usize] = score.get_count(*player, *piece);
+        }
+    }
+    key
+}
+
+/// Placements of the two kings up to mirroring, which come first in a table's index
+struct KingPairs {
+    /// White and black king squares
+    pairs: Vec<(usize, usize)>,
+    /// `[white king * 64 + black king]` to the index in `pairs`, for kings already mirrored by `Material::mirror`
+    indices: Vec<Option<u16>>
+}
+
+impl KingPairs {
+
+    fn new(has_pawns: bool) -> Self {
+        let mut pairs = Vec::new();
+        let mut indices = vec![None; 64 * 64];
+        for white in 0..64 {
+            let (wx, wy) = (white % 8, white / 8);
+            if wx > 3 || (!has_pawns && wy > wx) { continue; }
+            for black in 0..64 {
+                let (bx, by) = (black % 8, black / 8);
+                let is_adjacent = (wx as i32 - bx as i32).abs() <= 1 && (wy as i32 - by as i32).abs() <= 1;
+                // Mirroring along the diagonal keeps a white king on it, so the black king goes on or below it
+                if is_adjacent || (!has_pawns && wx == wy && by > bx) { continue; }
+                indices[white * 64 + black] = Some(pairs.len() as u16);
+                pairs.push((white, black));
+            }
+        }
+        Self { pairs, indices }
+    }
+}
+
+lazy_static! {
+    /// Without pawns, then with pawns
+    static ref KING_PAIRS: [KingPairs; 2] = [KingPairs::new(false), KingPairs::new(true)];
+}
+
+/// Pieces after the kings which are indexed together, one or two of the same
+#[derive(Clone)]
+struct Group {
+    /// Of its first piece in `Material::pieces`
+    start: usize,
+    len: usize,
+    is_pawn: bool,
+    /// Squares left for each of its pieces, at most
+    free: usize,
+    /// Placements of the group
+    size: usize
+}
+
+impl Group {
+
+    /// Order of `sq` among the squares the group's pieces can be on, not counting those of `earlier` pieces
+    #[inline]
+    fn get_order(&self, sq: usize, earlier: &[usize]) -> Option<usize> {
+        let is_allowed = |sq: usize| !self.is_pawn || (sq >= 8 && sq < 56);
+        if !is_allowed(sq) || earlier.contains(&sq) {
+            return None;
+        }
+        let below = earlier.iter().filter(|e| **e < sq && is_allowed(**e)).count();
+        let order = sq - if self.is_pawn { 8 } else { 0 } - below;
+        if order < self.free { Some(order) } else { None }
+    }
+
+    /// Inverse of `get_order`
+    #[inline]
+    fn get_square(&self, order: usize, earlier: &[usize]) -> Option<usize> {
+        let squares = if self.is_pawn { 8..56 } else { 0..64 };
+        squares.filter(|sq| !earlier.contains(sq)).nth(order)
+    }
+}
+
+/// The pieces of one table, eg. `KQK` or `KBNK`, white's before black's
+#[derive(Clone)]
+pub struct Material {
+    /// White king, black king, then white's and black's other pieces in `NAME_ORDER`
+    pieces: Vec<(Piece, Player)>,
+    groups: Vec<Group>,
+    has_pawns: bool
+}
+
+impl Material {
+
+    /// eg. `KPK` or `KRvK`, with white's pieces first
+    pub fn from_name(name: &str) -> Result<Self, DtmError> {
+        let bad = || DtmError::BadMaterial(String::from(name));
+        if name.len() < 2 || !name.is_ascii() {
+            return Err(bad());
+        }
+        let name = name.to_ascii_uppercase();
+        let (white, black) = match name.find('V') {
+            Some(i) => (&name[..i], &name[i + 1..]),
+            None => {
+                let i = name[1..].find('K').ok_or_else(bad)? + 1;
+                (&name[..i], &name[i..])
+            }
+        };
+
+        let mut key = [[0u8; 6]; 2];
+        for (player, side) in [(Player::White, white), (Player::Black, black)].iter() {
+            for c in side.chars() {
+                let piece = match c {
+                    'K' => Piece::King,
+                    'Q' => Piece::Queen,
+                    'R' => Piece::Rook,
+                    'B' => Piece::Bishop,
+                    'N' => Piece::Knight,
+                    'P' => Piece::Pawn,
+                    _ => return Err(bad())
+                };
+                key[*player as usize][piece as usize] += 1;
+            }
+        }
+        if key[0][Piece::King as usize] != 1 || key[1][Piece::King as usize] != 1 {
+            return Err(bad());
+        }
+
+        let material = Self::from_key(&key);
+        if material.pieces.len() > MAX_PIECES {
+            return Err(bad());
+        }
+        Ok(material)
+    }
+
+    fn from_key(key: &MaterialKey) -> Self {
+        let mut pieces = vec![(Piece::King, Player::White), (Piece::King, Player::Black)];
+        let mut groups: Vec<Group> = Vec::new();
+        for player in [Player::White, Player::Black].iter() {
+            for piece in NAME_ORDER.iter() {
+                let len = key[*player as usize][*piece as usize] as usize;
+                if len == 0 { continue; }
+
+                let is_pawn = *piece == Piece::Pawn;
+                // Earlier pawns always take pawn squares, other earlier pieces only sometimes
+                let earlier_pawns = pieces.iter().filter(|(p, _)| *p == Piece::Pawn).count();
+                let free = if is_pawn { 48 - earlier_pawns } else { 64 - pieces.len() };
+                let size = if len == 1 { free } else { free * (free - 1) / 2 };
+                groups.push(Group { start: pieces.len(), len, is_pawn, free, size });
+                pieces.extend((0..len).map(|_| (*piece, *player)));
+            }
+        }
+        let has_pawns = groups.iter().any(|group| group.is_pawn);
+        Self { pieces, groups, has_pawns }
+    }
+
+    fn get_key(&self) -> MaterialKey {
+        let mut key = [[0; 6]; 2];
+        for (piece, player) in self.pieces.iter() {
+            key[*player as usize][*piece as usize] += 1;
+        }
+        key
+    }
+
+    #[inline]
+    pub fn get_pieces(&self) -> &Vec<(Piece, Player)> {
+        &self.pieces
+    }
+
+    /// Positions in a table. Some are impossible, eg. with the player without the turn in check.
+    pub fn get_size(&self) -> usize {
+        let king_pairs = KING_PAIRS[self.has_pawns as usize].pairs.len();
+        self.groups.iter().fold(2 * king_pairs, |size, group| size * group.size)
+    }
+
+    /// Mirrors every square so that the white king is in files a to d, and without pawns also in the a1-d1-d4
+    /// triangle with the black king on or below its diagonal when the white king is on it
+    fn mirror(&self, squares: &mut [usize]) {
+        let mirror_all = |squares: &mut [usize], f: fn(usize) -> usize| {
+            for sq in squares.iter_mut() {
+                *sq = f(*sq);
+            }
+        };
+        if squares[0] % 8 > 3 {
+            mirror_all(squares, |sq| sq ^ 7);
+        }
+        if self.has_pawns {
+            return;
+        }
+        if squares[0] / 8 > 3 {
+            mirror_all(squares, |sq| sq ^ 56);
+        }
+        let (wx, wy, bx, by) = (squares[0] % 8, squares[0] / 8, squares[1] % 8, squares[1] / 8);
+        if wy > wx || (wy == wx && by > bx) {
+            mirror_all(squares, |sq| (sq % 8) * 8 + sq / 8);
+        }
+    }
+
+    /// Index in the table of the position with `squares` of the pieces in order, as `y * 8 + x` on `Board`.
+    /// `None` for placements which aren't stored, with the kings next to each other, pieces on the same square
+    /// or pawns on the first or last rank.
+    pub fn get_index(&self, player_with_turn: Player, squares: &[usize]) -> Option<usize> {
+        let n = self.pieces.len();
+        let mut mirrored = [0; MAX_PIECES];
+        mirrored[..n].copy_from_slice(&squares[..n]);
+        let squares = &mut mirrored[..n];
+        self.mirror(squares);
+        let index = self.get_mirrored_index(player_with_turn, squares)?;
+
+        // With both kings on the diagonal, mirroring along it is the same position at another index
+        let is_on_diagonal = |sq: usize| sq % 8 == sq / 8;
+        if !self.has_pawns && is_on_diagonal(squares[0]) && is_on_diagonal(squares[1]) {
+            for sq in squares.iter_mut() {
+                *sq = (*sq % 8) * 8 + *sq / 8;
+            }
+            return Some(index.min(self.get_mirrored_index(player_with_turn, squares)?));
+        }
+        Some(index)
+    }
+
+    fn get_mirrored_index(&self, player_with_turn: Player, squares: &[usize]) -> Option<usize> {
+        let king_pairs = &KING_PAIRS[self.has_pawns as usize];
+        let king_pair = king_pairs.indices[squares[0] * 64 + squares[1]]? as usize;
+        let mut index = player_with_turn as usize * king_pairs.pairs.len() + king_pair;
+
+        for group in self.groups.iter() {
+            let earlier = &squares[..group.start];
+            let first = group.get_order(squares[group.start], earlier)?;
+            let placement = if group.len == 1 {
+                first
+            } else {
+                // Either order of the same pieces is the same placement
+                let second = group.get_order(squares[group.start + 1], earlier)?;
+                let (low, high) = (first.min(second), first.max(second));
+                if low == high {
+                    return None;
+                }
+                high * (high - 1) / 2 + low
+            };
+            index = index * group.size + placement;
+        }
+        Some(index)
+    }
+
+    /// Inverse of `get_index`, returning the player with the turn. `None` for indices which are of no placement,
+    /// or of one which `get_index` puts at another index.
+    fn get_squares(&self, index: usize, squares: &mut [usize]) -> Option<Player> {
+        let mut placements = [0; MAX_PIECES];
+        let mut rest = index;
+        for (i, group) in self.groups.iter().enumerate().rev() {
+            placements[i] = rest % group.size;
+            rest /= group.size;
+        }
+
+        let king_pairs = &KING_PAIRS[self.has_pawns as usize];
+        let (white, black) = king_pairs.pairs[rest % king_pairs.pairs.len()];
+        squares[0] = white;
+        squares[1] = black;
+
+        for (group, placement) in self.groups.iter().zip(placements.iter()) {
+            let earlier = &squares[..group.start];
+            if group.len == 1 {
+                squares[group.start] = group.get_square(*placement, earlier)?;
+            } else {
+                let mut high = 1;
+                while (high + 1) * high / 2 <= *placement {
+                    high += 1;
+                }
+                let low = placement - high * (high - 1) / 2;
+                let (first, second) = (group.get_square(low, earlier)?, group.get_square(high, earlier)?);
+                squares[group.start] = first;
+                squares[group.start + 1] = second;
+            }
+        }
+        let player_with_turn = if rest / king_pairs.pairs.len() == 0 { Player::White } else { Player::Black };
+        if self.get_index(player_with_turn, &squares[..self.pieces.len()]) != Some(index) {
+            return None;
+        }
+        Some(player_with_turn)
+    }
+
+    /// Only the kings, which is always a draw and has no table
+    #[cfg(not(target_arch = "wasm32"))]
+    fn is_bare_kings(&self) -> bool {
+        self.pieces.len() == 2
+    }
+
+    /// Materials reachable by one capture or promotion
+    #[cfg(not(target_arch = "wasm32"))]
+    fn get_sub_materials(&self) -> Vec<Material> {
+        let key = self.get_key();
+        let mut subs: Vec<MaterialKey> = Vec::new();
+        for (piece, player) in self.pieces.iter().skip(2) {
+            let mut captured = key;
+            captured[*player as usize][*piece as usize] -= 1;
+            subs.push(captured);
+
+            if *piece == Piece::Pawn {
+                for promotion in [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight].iter() {
+                    let mut promoted = captured;
+                    promoted[*player as usize][*promotion as usize] += 1;
+                    subs.push(promoted);
+                }
+            }
+        }
+        subs.sort();
+        subs.dedup();
+        subs.iter().map(Self::from_key).collect()
+    }
+}
+
+impl Display for Material {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        for player in [Player::White, Player::Black].iter() {
+            write!(f, "K")?;
+            for (piece, piece_player) in self.pieces.iter().skip(2) {
+                if piece_player == player {
+                    write!(f, "{}", match piece {
+                        Piece::Queen => 'Q',
+                        Piece::Rook => 'R',
+                        Piece::Bishop => 'B',
+                        Piece::Knight => 'N',
+                        _ => 'P'
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct DtmTable {
+    material: Material,
+    values: Vec<u8>
+}
+
+impl DtmTable {
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DtmError> {
+        if bytes.len() < 6 {
+            return Err(DtmError::BadLength(6, bytes.len()));
+        }
+        if &bytes[0..4] != MAGIC {
+            return Err(DtmError::BadMagic);
+        }
+        if bytes[4] != VERSION {
+            return Err(DtmError::BadVersion(bytes[4]));
+        }
+
+        let name_end = 6 + bytes[5] as usize;
+        if bytes.len() < name_end {
+            return Err(DtmError::BadLength(name_end, bytes.len()));
+        }
+        let name = String::from_utf8_lossy(&bytes[6..name_end]);
+        let material = Material::from_name(&name)?;
+
+        let expected = name_end + material.get_size();
+        if bytes.len() != expected {
+            return Err(DtmError::BadLength(expected, bytes.len()));
+        }
+        Ok(Self { material, values: bytes[name_end..].to_vec() })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let name = self.material.to_string();
+        let mut bytes = Vec::with_capacity(6 + name.len() + self.values.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&self.values);
+        bytes
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str) -> Result<Self, DtmError> {
+        let bytes = std::fs::read(path).map_err(|e| DtmError::Io(e.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &str) -> Result<(), DtmError> {
+        std::fs::write(path, self.to_bytes()).map_err(|e| DtmError::Io(e.to_string()))
+    }
+
+    #[inline]
+    pub fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    /// Counts of wins, draws and losses for the player with the turn, and the longest mate in plies
+    pub fn get_stats(&self) -> (usize, usize, usize, u8) {
+        let (mut wins, mut draws, mut losses, mut longest) = (0, 0, 0, 0);
+        for v in self.values.iter().filter(|v| **v != ILLEGAL) {
+            match Dtm::from_value(*v) {
+                Dtm::Win(plies) => {
+                    wins += 1;
+                    longest = longest.max(plies);
+                },
+                Dtm::Draw => draws += 1,
+                Dtm::Loss(plies) => {
+                    losses += 1;
+                    longest = longest.max(plies);
+                }
+            };
+        }
+        (wins, draws, losses, longest)
+    }
+
+    /// `board` must have the table's material, or with the colours swapped when `flip`
+    fn probe(&self, board: &Board, flip: bool) -> Option<Dtm> {
+        let n = self.material.pieces.len();
+        let mut squares = [usize::MAX; MAX_PIECES];
+        for player in [Player::White, Player::Black].iter() {
+            for Coord(x, y) in board.get_player_state(*player).piece_locs.iter() {
+                let piece = match board.get_by_xy(*x, *y) {
+                    Square::Occupied(piece, _) => *piece,
+                    Square::Blank => continue
+                };
+                let (player, y) = if flip { (player.get_other_player(), 7 - *y) } else { (*player, *y) };
+                // Same pieces take the first free slot, either order has the same index
+                let slot = (0..n).find(|i| self.material.pieces[*i] == (piece, player) && squares[*i] == usize::MAX)?;
+                squares[slot] = y as usize * 8 + *x as usize;
+            }
+        }
+
+        let player_with_turn = if flip { board.get_player_with_turn().get_other_player() } else { board.get_player_with_turn() };
+        let index = self.material.get_index(player_with_turn, &squares[..n])?;
+        match self.values[index] {
+            ILLEGAL => None,
+            v => Some(Dtm::from_value(v))
+        }
+    }
+}
+
+/// A set of tables to probe positions with
+pub struct DtmTables {
+    tables: Vec<DtmTable>,
+    /// Both colourings of each table's material to its index and whether the colours are swapped
+    index: HashMap<MaterialKey, (usize, bool)>,
+    max_pieces: usize
+}
+
+impl DtmTables {
+
+    pub fn new() -> Self {
+        Self { tables: Vec::new(), index: HashMap::new(), max_pieces: 0 }
+    }
+
+    /// Replaces any table of the same material
+    pub fn insert(&mut self, table: DtmTable) {
+        let key = table.material.get_key();
+        let i = match self.index.get(&key).map(|(i, _)| *i) {
+            Some(i) => {
+                // Which may have had the colours swapped
+                self.tables[i] = table;
+                i
+            },
+            None => {
+                self.tables.push(table);
+                self.tables.len() - 1
+            }
+        };
+        self.max_pieces = self.max_pieces.max(self.tables[i].material.pieces.len());
+        self.index.insert([key[1], key[0]], (i, true));
+        self.index.insert(key, (i, false));
+    }
+
+    /// Reads every `.cbtb` file in `dir`, logging files which can't be read
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_dir(dir: &str) -> Result<Self, DtmError> {
+        let mut tables = Self::new();
+        for entry in std::fs::read_dir(dir).map_err(|e| DtmError::Io(e.to_string()))?.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().map_or(true, |ext| ext != "cbtb") { continue; }
+            match DtmTable::load(&path.to_string_lossy()) {
+                Ok(table) => tables.insert(table),
+                Err(e) => eprintln!("Can't load {} - {}", path.display(), e)
+            };
+        }
+        Ok(tables)
+    }
+
+    pub fn get_table_count(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn get_tables(&self) -> &Vec<DtmTable> {
+        &self.tables
+    }
+
+    /// Most pieces, kings included, in any table
+    pub fn get_max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// For the player with the turn. `None` without a table for the material, or when castling is possible.
+    pub fn probe(&self, board: &Board) -> Option<Dtm> {
+        let key = get_board_key(board);
+        let count: usize = key.iter().map(|side| side.iter().map(|n| *n as usize).sum::<usize>()).sum();
+        if count > self.max_pieces.max(2) || board.has_castling_rights() {
+            return None;
+        }
+        if count == 2 {
+            return Some(Dtm::Draw);
+        }
+        let (i, flip) = self.index.get(&key)?;
+        self.tables[*i].probe(board, *flip)
+    }
+}
+
+/// Builds the table of `material`, and every table it can reach by captures and promotions into `tables`
+/// unless already there. Each position is first set up on a `Board` to find its legal moves, then values
+/// spread back from mates one ply at a time by undoing moves.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn generate(material: &Material, threads: usize, tables: &mut DtmTables) {
+    for sub in material.get_sub_materials() {
+        if !sub.is_bare_kings() && !tables.index.contains_key(&sub.get_key()) {
+            generate(&sub, threads, tables);
+        }
+    }
+    let table = Generator::new(material, tables).run(threads);
+    tables.insert(table);
+}
+
+/// Per position state of `generate`
+#[cfg(not(target_arch = "wasm32"))]
+struct Generator<'a> {
+    material: &'a Material,
+    sub_tables: &'a DtmTables,
+    values: Vec<u8>,
+    /// Positions reached by moves which stay in the table and are not yet known to lose. Positions rather than
+    /// moves, since moves to mirrored positions share an index and are only found once by `get_predecessors`.
+    counts: Vec<u8>,
+    /// Best outcome of moves which leave the table, or `NO_EXIT`, as a value
+    exits: Vec<u8>
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const NO_EXIT: u8 = 254;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn to_value(dtm: Dtm) -> u8 {
+    match dtm {
+        Dtm::Draw => 0,
+        Dtm::Win(plies) | Dtm::Loss(plies) => plies + 1
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> Generator<'a> {
+
+    fn new(material: &'a Material, sub_tables: &'a DtmTables) -> Self {
+        Self { material, sub_tables, values: Vec::new(), counts: Vec::new(), exits: Vec::new() }
+    }
+
+    fn run(mut self, threads: usize) -> DtmTable {
+        let size = self.material.get_size();
+        let threads = threads.max(1);
+        let chunk = (size + threads - 1) / threads;
+
+        // Legal moves of every position, split over threads
+        let results: Vec<(Vec<u8>, Vec<u8>, Vec<u8>)> = std::thread::scope(|scope| {
+            let this = &self;
+            let handles: Vec<_> = (0..size).step_by(chunk).map(|start| {
+                scope.spawn(move || this.init_range(start, (start + chunk).min(size)))
+            }).collect();
+            handles.into_iter().map(|h| h.join().expect("Generator thread panicked")).collect()
+        });
+        for (values, counts, exits) in results {
+            self.values.extend(values);
+            self.counts.extend(counts);
+            self.exits.extend(exits);
+        }
+
+        self.spread();
+        DtmTable { material: self.material.clone(), values: self.values }
+    }
+
+    fn init_range(&self, start: usize, end: usize) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let mut values = vec![0; end - start];
+        let mut counts = vec![0; end - start];
+        let mut exits = vec![NO_EXIT; end - start];
+
+        let mut boards = [
+            Board::from_fen("8/8/8/8/8/8/8/8 w - - 0 1").unwrap(),
+            Board::from_fen("8/8/8/8/8/8/8/8 b - - 0 1").unwrap()
+        ];
+        let mut temp = MoveList::new(50);
+        let mut moves = MoveList::new(50);
+        let n = self.material.pieces.len();
+        let mut squares = [0; MAX_PIECES];
+        let mut successors = Vec::new();
+
+        for i in start..end {
+            let player_with_turn = match self.material.get_squares(i, &mut squares) {
+                Some(player) => player,
+                None => {
+                    values[i - start] = ILLEGAL;
+                    continue;
+                }
+            };
+            let squares = &squares[..n];
+
+            let board = &mut boards[player_with_turn as usize];
+            for (sq, (piece, player)) in squares.iter().zip(self.material.pieces.iter()) {
+                board.set_by_xy((sq % 8) as u8, (sq / 8) as u8, Square::Occupied(*piece, *player));
+            }
+
+            if is_checking(board, player_with_turn) {
+                values[i - start] = ILLEGAL;
+            } else {
+                moves.write_index = 0;
+                board.get_moves(&mut temp, &mut moves);
+
+                let mut best_exit: Option<Dtm> = None;
+                successors.clear();
+                for m in moves.get_v()[..moves.write_index].iter() {
+                    if !Self::is_exit(m) {
+                        successors.extend(self.get_successor(m, player_with_turn, squares));
+                        continue;
+                    }
+                    board.handle_move(m, true);
+                    let dtm = self.sub_tables.probe(board).unwrap_or(Dtm::Draw).before_move();
+                    board.handle_move(m, false);
+                    if best_exit.map_or(true, |best| dtm.get_rank() > best.get_rank()) {
+                        best_exit = Some(dtm);
+                    }
+                }
+
+                successors.sort_unstable();
+                successors.dedup();
+                counts[i - start] = successors.len() as u8;
+
+                if moves.write_index == 0 && is_checking(board, player_with_turn.get_other_player()) {
+                    values[i - start] = to_value(Dtm::Loss(0));
+                }
+                if let Some(best_exit) = best_exit {
+                    exits[i - start] = to_value(best_exit);
+                }
+            }
+
+            for sq in squares.iter() {
+                board.set_by_xy((sq % 8) as u8, (sq / 8) as u8, Square::Blank);
+            }
+        }
+        (values, counts, exits)
+    }
+
+    /// Index of the position after `m`, which isn't an exit
+    fn get_successor(&self, m: &MoveSnapshot, player_with_turn: Player, squares: &[usize]) -> Option<usize> {
+        let (Coord(src_x, src_y), Coord(dest_x, dest_y), _) = m.get_src_dest_promotion()?;
+        let mut after = [0; MAX_PIECES];
+        let after = &mut after[..squares.len()];
+        after.copy_from_slice(squares);
+        let src = src_y as usize * 8 + src_x as usize;
+        *after.iter_mut().find(|sq| **sq == src)? = dest_y as usize * 8 + dest_x as usize;
+        self.material.get_index(player_with_turn.get_other_player(), after)
+    }
+
+    /// Captures and promotions, which change the material
+    fn is_exit(m: &MoveSnapshot) -> bool {
+        match m.get_description() {
+            MoveDescription::Capture(_, _, _) => true,
+            MoveDescription::Move(_, _, _) => matches!(
+                (m.get_src_sq(), m.get_dest_sq()),
+                (Some((_, BeforeAfterSquares(Square::Occupied(Piece::Pawn, _), _))), Some((_, BeforeAfterSquares(_, Square::Occupied(after, _))))) if *after != Piece::Pawn
+            ),
+            _ => false
+        }
+    }
+
+    /// Resolves positions one ply count at a time. A position is won in `d + 1` plies when a move leads to
+    /// a loss in `d`, and lost in `d + 1` when its last move not known to lose turns out to lead to a win in `d`.
+    /// Positions with moves leaving the table wait in `pending` until their ply count comes up.
+    fn spread(&mut self) {
+        let n = self.material.pieces.len();
+        let mut pending: Vec<Vec<usize>> = vec![Vec::new(); MAX_PLIES + 2];
+        let mut frontier: Vec<usize> = Vec::new();
+
+        for i in 0..self.values.len() {
+            if self.values[i] == ILLEGAL { continue; }
+            if self.values[i] != 0 {
+                frontier.push(i);
+                continue;
+            }
+            if self.exits[i] == NO_EXIT { continue; }
+            match Dtm::from_value(self.exits[i]) {
+                Dtm::Win(plies) => pending[plies as usize].push(i),
+                Dtm::Loss(plies) => if self.counts[i] == 0 { pending[plies as usize].push(i) },
+                Dtm::Draw => ()
+            };
+        }
+
+        let mut squares = [0; MAX_PIECES];
+        let mut predecessors = Vec::new();
+        for d in 0..=MAX_PLIES {
+            for i in std::mem::take(&mut pending[d]) {
+                if self.values[i] == 0 {
+                    self.values[i] = d as u8 + 1;
+                    frontier.push(i);
+                }
+            }
+            if frontier.is_empty() && pending[d..].iter().all(|p| p.is_empty()) {
+                break;
+            }
+
+            let mut next = Vec::new();
+            for q in frontier.drain(..) {
+                let player_with_turn = match self.material.get_squares(q, &mut squares) {
+                    Some(player) => player,
+                    None => continue
+                };
+                predecessors.clear();
+                self.get_predecessors(player_with_turn, &squares[..n], &mut predecessors);
+                // Once per position like `counts`
+                predecessors.sort_unstable();
+                predecessors.dedup();
+
+                for p in predecessors.iter().copied() {
+                    if self.values[p] != 0 || d == MAX_PLIES { continue; }
+                    if d % 2 == 0 {
+                        self.values[p] = d as u8 + 2;
+                        next.push(p);
+                        continue;
+                    }
+
+                    self.counts[p] -= 1;
+                    if self.counts[p] > 0 { continue; }
+                    let exit_loss = match self.exits[p] {
+                        NO_EXIT => 0,
+                        v => match Dtm::from_value(v) {
+                            Dtm::Loss(plies) => plies as usize,
+                            // Can't lose with a move which wins or draws
+                            _ => continue
+                        }
+                    };
+                    if exit_loss <= d + 1 {
+                        self.values[p] = d as u8 + 2;
+                        next.push(p);
+                    } else {
+                        pending[exit_loss].push(p);
+                    }
+                }
+            }
+            frontier = next;
+        }
+    }
+
+    /// Positions where the player without the turn moved into the given one, without capturing or promoting
+    fn get_predecessors(&self, player_with_turn: Player, squares: &[usize], result: &mut Vec<usize>) {
+        const KING: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+        const KNIGHT: [(i32, i32); 8] = [(-2, -1), (-2, 1), (2, -1), (2, 1), (-1, -2), (1, -2), (-1, 2), (1, 2)];
+        const ROOK: [(i32, i32); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+        const BISHOP: [(i32, i32); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+
+        let mover = player_with_turn.get_other_player();
+        let is_empty = |sq: usize| !squares.contains(&sq);
+        let mut from = squares.to_vec();
+
+        for (j, (piece, player)) in self.material.pieces.iter().enumerate() {
+            if *player != mover { continue; }
+            let (x, y) = ((squares[j] % 8) as i32, (squares[j] / 8) as i32);
+            let mut origins: Vec<usize> = Vec::new();
+
+            let add_steps = |dirs: &[(i32, i32)], slide: bool, origins: &mut Vec<usize>| {
+                for (dx, dy) in dirs.iter() {
+                    let (mut ox, mut oy) = (x + dx, y + dy);
+                    while check_i32_xy(ox, oy).is_ok() && is_empty((oy * 8 + ox) as usize) {
+                        origins.push((oy * 8 + ox) as usize);
+                        if !slide { break; }
+                        ox += dx;
+                        oy += dy;
+                    }
+                }
+            };
+
+            match piece {
+                Piece::King => add_steps(&KING, false, &mut origins),
+                Piece::Knight => add_steps(&KNIGHT, false, &mut origins),
+                Piece::Rook => add_steps(&ROOK, true, &mut origins),
+                Piece::Bishop => add_steps(&BISHOP, true, &mut origins),
+                Piece::Queen => {
+                    add_steps(&ROOK, true, &mut origins);
+                    add_steps(&BISHOP, true, &mut origins);
+                },
+                Piece::Pawn => {
+                    // White pawns move towards y = 0
+                    let back = if mover == Player::White { 1 } else { -1 };
+                    let one = y + back;
+                    if one >= 1 && one <= 6 && is_empty((one * 8 + x) as usize) {
+                        origins.push((one * 8 + x) as usize);
+                        let two = one + back;
+                        if two == mover.get_first_row() as i32 - back && is_empty((two * 8 + x) as usize) {
+                            origins.push((two * 8 + x) as usize);
+                        }
+                    }
+                }
+            };
+
+            for origin in origins {
+                from[j] = origin;
+                match self.material.get_index(mover, &from) {
+                    Some(p) if self.values[p] != ILLEGAL => result.push(p),
+                    _ => ()
+                };
+            }
+            from[j] = squares[j];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(tables: &DtmTables, fen: &str) -> Option<Dtm> {
+        tables.probe(&Board::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn kqk() {
+        let mut tables = DtmTables::new();
+        generate(&Material::from_name("KQK").unwrap(), 1, &mut tables);
+        assert_eq!(tables.get_table_count(), 1);
+
+        assert_eq!(probe(&tables, "k7/8/1K6/8/8/8/7Q/8 w - - 0 1"), Some(Dtm::Win(1)));
+        assert_eq!(probe(&tables, "k6Q/8/1K6/8/8/8/8/8 b - - 0 1"), Some(Dtm::Loss(0)));
+        assert_eq!(probe(&tables, "K6q/8/1k6/8/8/8/8/8 w - - 0 1"), Some(Dtm::Loss(0)));
+        // Mate in 2, as the queen can't mate at once
+        assert_eq!(probe(&tables, "k7/8/2K5/8/8/8/8/6Q1 w - - 0 1"), Some(Dtm::Win(3)));
+        // Stalemate, and a queen left to be taken
+        assert_eq!(probe(&tables, "k7/8/1QK5/8/8/8/8/8 b - - 0 1"), Some(Dtm::Draw));
+        assert_eq!(probe(&tables, "8/8/8/8/8/8/1kQ5/7K b - - 0 1"), Some(Dtm::Draw));
+        // The player without the turn can't be in check
+        assert_eq!(probe(&tables, "k6Q/8/1K6/8/8/8/8/8 w - - 0 1"), None);
+
+        // The longest win is mate in 10
+        let (wins, draws, losses, longest) = tables.get_tables()[0].get_stats();
+        assert!(wins > 0 && draws > 0 && losses > 0);
+        assert_eq!(longest, 20);
+    }
+
+    #[test]
+    fn from_bytes() {
+        let mut tables = DtmTables::new();
+        generate(&Material::from_name("KQK").unwrap(), 1, &mut tables);
+        let bytes = tables.get_tables()[0].to_bytes();
+        let table = DtmTable::from_bytes(&bytes).unwrap();
+        assert_eq!(table.get_material().to_string(), "KQK");
+        assert_eq!(table.get_stats(), tables.get_tables()[0].get_stats());
+
+        let len = bytes.len();
+        assert!(matches!(DtmTable::from_bytes(&bytes[..len - 1]), Err(DtmError::BadLength(l, actual)) if l == len && actual == len - 1));
+        assert!(matches!(DtmTable::from_bytes(&[bytes.as_slice(), &[0]].concat()), Err(DtmError::BadLength(l, _)) if l == len));
+        assert!(matches!(DtmTable::from_bytes(&bytes[..8]), Err(DtmError::BadLength(_, 8))));
+        assert!(matches!(DtmTable::from_bytes(&bytes[..5]), Err(DtmError::BadLength(6, 5))));
+        assert!(matches!(DtmTable::from_bytes(&[]), Err(DtmError::BadLength(6, 0))));
+
+        let mut bad = bytes.clone();
+        bad[0] ^= 1;
+        assert!(matches!(DtmTable::from_bytes(&bad), Err(DtmError::BadMagic)));
+        let mut bad = bytes;
+        bad[4] = VERSION + 1;
+        assert!(matches!(DtmTable::from_bytes(&bad), Err(DtmError::BadVersion(_))));
+    }
+}
@@ -0,0 +1,407 @@
+//! Endgame knowledge which the general evaluation lacks, picked by the material of each player.
+//! Mating nets against a lone king, the bishop and knight mate, an exact KPK bitbase and scaling for drawish material.
+
+use super::super::game::entities::*;
+use super::super::game::coords::*;
+use super::super::game::board::*;
+use super::eval_params::*;
+
+/// Above any normal evaluation so that search heads for a known win, in pawns
+const KNOWN_WIN: f32 = 50.;
+
+/// Scale for pawnless positions where the extra material is at most a minor piece, eg. KRKB
+const DRAWISH_SCALE: f32 = 0.125;
+const OPPOSITE_BISHOPS_SCALE: f32 = 0.5;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Endgame {
+    /// Neither player can mate
+    InsufficientMaterial,
+    /// Enough material to mate against a lone king
+    Kxk,
+    /// Bishop and knight against a lone king, which is driven to a corner of the bishop's colour
+    Kbnk,
+    /// King and pawn against king, from the bitbase
+    Kpk,
+    /// The player ahead has no pawns and too little extra material to win
+    DrawishMaterial,
+    /// Only pawns and a bishop each, on opposite colours
+    OppositeBishops
+}
+
+impl Endgame {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Endgame::InsufficientMaterial => "insufficientMaterial",
+            Endgame::Kxk => "kxk",
+            Endgame::Kbnk => "kbnk",
+            Endgame::Kpk => "kpk",
+            Endgame::DrawishMaterial => "drawishMaterial",
+            Endgame::OppositeBishops => "oppositeBishops"
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum EndgameEval {
+    /// Replaces the evaluation, from white's point of view
+    Exact(f32),
+    /// Multiplies the evaluation when it favours the player
+    Scale(Player, f32)
+}
+
+impl EndgameEval {
+    /// `eval` from white's point of view
+    pub fn apply(&self, eval: f32) -> f32 {
+        match self {
+            EndgameEval::Exact(exact) => *exact,
+            EndgameEval::Scale(player, scale) => {
+                if eval * player.get_multiplier() > 0. { eval * scale } else { eval }
+            }
+        }
+    }
+}
+
+/// Piece counts of one player
+struct Material {
+    counts: [u8; 6],
+    /// Pieces other than pawns and the king, in pawns
+    non_pawn: f32
+}
+
+impl Material {
+
+    fn new(board: &Board, params: &EvalParams, player: Player) -> Self {
+        let score = board.get_score();
+        let mut counts = [0; 6];
+        for piece in PIECES.iter() {
+            counts[*piece as usize] = score.get_count(player, *piece);
+        }
+        let non_pawn = [Piece::Rook, Piece::Knight, Piece::Bishop, Piece::Queen].iter()
+            .map(|piece| counts[*piece as usize] as f32 * params.get_piece_value(*piece))
+            .sum();
+        Self { counts, non_pawn }
+    }
+
+    #[inline]
+    fn get(&self, piece: Piece) -> u8 {
+        self.counts[piece as usize]
+    }
+
+    fn get_total(&self, params: &EvalParams) -> f32 {
+        self.non_pawn + self.get(Piece::Pawn) as f32 * params.get_piece_value(Piece::Pawn)
+    }
+
+    fn is_lone_king(&self) -> bool {
+        self.non_pawn == 0. && self.get(Piece::Pawn) == 0
+    }
+
+    fn get_minors(&self) -> u8 {
+        self.get(Piece::Knight) + self.get(Piece::Bishop)
+    }
+
+    fn has_only(&self, pieces: &[(Piece, u8)]) -> bool {
+        [Piece::Pawn, Piece::Rook, Piece::Knight, Piece::Bishop, Piece::Queen].iter().all(|piece| {
+            self.get(*piece) == pieces.iter().find(|(p, _)| p == piece).map_or(0, |(_, n)| *n)
+        })
+    }
+}
+
+/// The endgame of `board` if it is one with special knowledge
+pub fn probe(board: &Board, params: &EvalParams) -> Option<(Endgame, EndgameEval)> {
+    let material = [Material::new(board, params, Player::White), Material::new(board, params, Player::Black)];
+
+    // Cheap exit for the middlegame
+    let has_lone_king = material[0].is_lone_king() || material[1].is_lone_king();
+    if !has_lone_king && material[0].non_pawn + material[1].non_pawn > 2. * params.get_piece_value(Piece::Rook) + params.get_piece_value(Piece::Bishop) {
+        return None;
+    }
+
+    let no_pawns = material[0].get(Piece::Pawn) == 0 && material[1].get(Piece::Pawn) == 0;
+    if no_pawns && material[0].get_minors() + material[1].get_minors() <= 1 && material[0].non_pawn + material[1].non_pawn <= params.get_piece_value(Piece::Bishop) {
+        return Some((Endgame::InsufficientMaterial, EndgameEval::Exact(0.)));
+    }
+
+    for strong in [Player::White, Player::Black].iter() {
+        let strong = *strong;
+        let weak = strong.get_other_player();
+        let (s, w) = (&material[strong as usize], &material[weak as usize]);
+
+        if w.is_lone_king() {
+            if s.has_only(&[(Piece::Pawn, 1)]) {
+                return Some((Endgame::Kpk, EndgameEval::Exact(evaluate_kpk(board, params, strong))));
+            }
+            if s.has_only(&[(Piece::Bishop, 1), (Piece::Knight, 1)]) {
+                return Some((Endgame::Kbnk, EndgameEval::Exact(evaluate_kbnk(board, params, strong))));
+            }
+            if s.has_only(&[(Piece::Knight, 2)]) {
+                return Some((Endgame::DrawishMaterial, EndgameEval::Scale(strong, 0.)));
+            }
+            if can_force_mate(board, s, strong) {
+                return Some((Endgame::Kxk, EndgameEval::Exact(evaluate_kxk(board, params, s, strong))));
+            }
+        }
+    }
+
+    // The player ahead by material, either when equal
+    let strong = if material[1].get_total(params) > material[0].get_total(params) { Player::Black } else { Player::White };
+    let (s, w) = (&material[strong as usize], &material[strong.get_other_player() as usize]);
+    if s.get(Piece::Pawn) == 0 && s.non_pawn - w.non_pawn <= params.get_piece_value(Piece::Bishop) {
+        let scale = if s.non_pawn < params.get_piece_value(Piece::Rook) { 0. } else { DRAWISH_SCALE };
+        return Some((Endgame::DrawishMaterial, EndgameEval::Scale(strong, scale)));
+    }
+
+    if s.has_only(&[(Piece::Pawn, s.get(Piece::Pawn)), (Piece::Bishop, 1)])
+        && w.has_only(&[(Piece::Pawn, w.get(Piece::Pawn)), (Piece::Bishop, 1)]) {
+        let colours = [get_bishop_colours(board, Player::White), get_bishop_colours(board, Player::Black)];
+        if colours[0] != colours[1] {
+            return Some((Endgame::OppositeBishops, EndgameEval::Scale(strong, OPPOSITE_BISHOPS_SCALE)));
+        }
+    }
+
+    None
+}
+
+/// Queen, rook, bishops on both colours, bishop and knight, or three knights.
+/// Pawns alone or with a single minor piece are left to the general evaluation.
+fn can_force_mate(board: &Board, s: &Material, strong: Player) -> bool {
+    let colours = get_bishop_colours(board, strong);
+    s.get(Piece::Queen) > 0
+        || s.get(Piece::Rook) > 0
+        || (colours.0 && colours.1)
+        || (s.get(Piece::Bishop) > 0 && s.get(Piece::Knight) > 0)
+        || s.get(Piece::Knight) >= 3
+}
+
+fn find_piece(board: &Board, player: Player, piece: Piece) -> Option<Coord> {
+    board.get_player_state(player).piece_locs.iter()
+        .find(|Coord(x, y)| *board.get_by_xy(*x, *y) == Square::Occupied(piece, player))
+        .cloned()
+}
+
+/// Whether `player` has a bishop on light squares, and on dark squares
+fn get_bishop_colours(board: &Board, player: Player) -> (bool, bool) {
+    let mut colours = (false, false);
+    for Coord(x, y) in board.get_player_state(player).piece_locs.iter() {
+        if *board.get_by_xy(*x, *y) == Square::Occupied(Piece::Bishop, player) {
+            if is_light_square(*x, *y) { colours.0 = true; } else { colours.1 = true; }
+        }
+    }
+    colours
+}
+
+/// a8, which is (0, 0), is light
+#[inline]
+fn is_light_square(x: u8, y: u8) -> bool {
+    (x + y) % 2 == 0
+}
+
+#[inline]
+fn get_distance(a: &Coord, b: &Coord) -> i32 {
+    (a.0 as i32 - b.0 as i32).abs().max((a.1 as i32 - b.1 as i32).abs())
+}
+
+/// 0 in the center to 6 in a corner
+#[inline]
+fn get_center_distance(c: &Coord) -> i32 {
+    let from_center = |v: u8| ((2 * v as i32 - 7).abs() - 1) / 2;
+    from_center(c.0) + from_center(c.1)
+}
+
+/// Sum of pieces plus bonuses for driving the lone king to the edge and the kings together, from white's point of view
+fn evaluate_kxk(board: &Board, params: &EvalParams, s: &Material, strong: Player) -> f32 {
+    let (king, lone_king) = match (find_piece(board, strong, Piece::King), find_piece(board, strong.get_other_player(), Piece::King)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return 0.
+    };
+    let eval = KNOWN_WIN
+        + s.get_total(params)
+        + 0.2 * get_center_distance(&lone_king) as f32
+        + 0.1 * (7 - get_distance(&king, &lone_king)) as f32;
+    round_eval(eval * strong.get_multiplier())
+}
+
+/// Only corners of the bishop's colour can be mated in, so the lone king is driven there instead of any edge
+fn evaluate_kbnk(board: &Board, params: &EvalParams, strong: Player) -> f32 {
+    let (king, lone_king) = match (find_piece(board, strong, Piece::King), find_piece(board, strong.get_other_player(), Piece::King)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return 0.
+    };
+    let corners = if get_bishop_colours(board, strong).0 { [Coord(0, 0), Coord(7, 7)] } else { [Coord(7, 0), Coord(0, 7)] };
+    let corner_distance = corners.iter()
+        .map(|c| (c.0 as i32 - lone_king.0 as i32).abs() + (c.1 as i32 - lone_king.1 as i32).abs())
+        .min()
+        .unwrap_or(0);
+    let eval = KNOWN_WIN
+        + params.get_piece_value(Piece::Bishop)
+        + params.get_piece_value(Piece::Knight)
+        + 0.2 * (14 - corner_distance) as f32
+        + 0.1 * (7 - get_distance(&king, &lone_king)) as f32;
+    round_eval(eval * strong.get_multiplier())
+}
+
+fn evaluate_kpk(board: &Board, params: &EvalParams, strong: Player) -> f32 {
+    let weak = strong.get_other_player();
+    let (king, lone_king, pawn) = match (find_piece(board, strong, Piece::King), find_piece(board, weak, Piece::King), find_piece(board, strong, Piece::Pawn)) {
+        (Some(a), Some(b), Some(c)) => (a, b, c),
+        _ => return 0.
+    };
+
+    // Bitbase squares are from a1 = 0 with the strong player as white and the pawn on files a to d
+    let mirror = pawn.0 >= 4;
+    let to_sq = |c: &Coord| -> usize {
+        let file = if mirror { 7 - c.0 } else { c.0 } as usize;
+        let rank = if strong == Player::White { 7 - c.1 } else { c.1 } as usize;
+        rank * 8 + file
+    };
+    let psq = to_sq(&pawn);
+    // Only reachable by setting up the board by hand, and outside the bitbase
+    if psq / 8 == 0 || psq / 8 == 7 {
+        return 0.;
+    }
+
+    if !kpk::is_win(board.get_player_with_turn() == strong, to_sq(&king), to_sq(&lone_king), psq) {
+        return 0.;
+    }
+    let eval = KNOWN_WIN + params.get_piece_value(Piece::Pawn) + 0.1 * (psq / 8) as f32;
+    round_eval(eval * strong.get_multiplier())
+}
+
+fn round_eval(v: f32) -> f32 {
+    (v * 100.).round() / 100.
+}
+
+/// Every king and pawn against king position, solved by retrograde analysis on first use.
+/// Squares are from a1 = 0 with white having the pawn on files a to d.
+mod kpk {
+
+    const SIZE: usize = 2 * 64 * 64 * 24;
+
+    // Bit flags so that successor results can be combined
+    const INVALID: u8 = 0;
+    const UNKNOWN: u8 = 1;
+    const DRAW: u8 = 2;
+    const WIN: u8 = 4;
+
+    lazy_static! {
+        /// Bit set for each index which white wins
+        static ref BITBASE: Vec<u64> = build();
+    }
+
+    /// Precondition: the position is legal with the pawn on files a to d and ranks 2 to 7
+    pub fn is_win(white_to_move: bool, white_king: usize, black_king: usize, pawn: usize) -> bool {
+        let i = get_index(white_to_move, white_king, black_king, pawn);
+        BITBASE[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    #[inline]
+    fn get_index(white_to_move: bool, white_king: usize, black_king: usize, pawn: usize) -> usize {
+        let side = if white_to_move { 0 } else { 1 };
+        side | black_king << 1 | white_king << 7 | (pawn % 8 + 4 * (pawn / 8 - 1)) << 13
+    }
+
+    #[inline]
+    fn get_distance(a: usize, b: usize) -> usize {
+        let files = (a % 8) as i32 - (b % 8) as i32;
+        let ranks = (a / 8) as i32 - (b / 8) as i32;
+        files.abs().max(ranks.abs()) as usize
+    }
+
+    #[inline]
+    fn is_pawn_attack(pawn: usize, sq: usize) -> bool {
+        sq / 8 == pawn / 8 + 1 && ((sq % 8) as i32 - (pawn % 8) as i32).abs() == 1
+    }
+
+    fn get_king_moves(sq: usize) -> impl Iterator<Item = usize> {
+        let (file, rank) = ((sq % 8) as i32, (sq / 8) as i32);
+        [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)].iter()
+            .map(move |(dx, dy)| (file + dx, rank + dy))
+            .filter(|(f, r)| *f >= 0 && *f < 8 && *r >= 0 && *r < 8)
+            .map(|(f, r)| (r * 8 + f) as usize)
+    }
+
+    fn classify_initial(white_to_move: bool, wk: usize, bk: usize, pawn: usize) -> u8 {
+        if get_distance(wk, bk) <= 1 || wk == pawn || bk == pawn || (white_to_move && is_pawn_attack(pawn, bk)) {
+            return INVALID;
+        }
+
+        if white_to_move {
+            // Promotes without being captured
+            let promotion = pawn + 8;
+            if pawn / 8 == 6 && wk != promotion && bk != promotion && (get_distance(bk, promotion) > 1 || get_distance(wk, promotion) == 1) {
+                return WIN;
+            }
+        } else {
+            let stalemate = get_king_moves(bk).all(|to| get_distance(to, wk) <= 1 || is_pawn_attack(pawn, to));
+            let captures = get_distance(bk, pawn) == 1 && get_distance(wk, pawn) > 1;
+            if stalemate || captures {
+                return DRAW;
+            }
+        }
+        UNKNOWN
+    }
+
+    fn classify(db: &[u8], white_to_move: bool, wk: usize, bk: usize, pawn: usize) -> u8 {
+        let mut r = INVALID;
+        if white_to_move {
+            for to in get_king_moves(wk) {
+                if get_distance(to, bk) > 1 && to != pawn {
+                    r |= db[get_index(false, to, bk, pawn)];
+                }
+            }
+            // Promotions are only counted when they win outright in `classify_initial`
+            let push = pawn + 8;
+            if pawn / 8 < 6 && push != wk && push != bk {
+                r |= db[get_index(false, wk, bk, push)];
+                let double_push = push + 8;
+                if pawn / 8 == 1 && double_push != wk && double_push != bk {
+                    r |= db[get_index(false, wk, bk, double_push)];
+                }
+            }
+            if r & WIN != 0 { WIN } else if r & UNKNOWN != 0 { UNKNOWN } else { DRAW }
+        } else {
+            for to in get_king_moves(bk) {
+                if get_distance(to, wk) > 1 && !is_pawn_attack(pawn, to) && to != pawn {
+                    r |= db[get_index(true, wk, to, pawn)];
+                }
+            }
+            if r & DRAW != 0 { DRAW } else if r & UNKNOWN != 0 { UNKNOWN } else { WIN }
+        }
+    }
+
+    fn decode(i: usize) -> (bool, usize, usize, usize) {
+        let p = i >> 13;
+        (i & 1 == 0, (i >> 7) & 63, (i >> 1) & 63, p % 4 + 8 * (p / 4 + 1))
+    }
+
+    fn build() -> Vec<u64> {
+        let mut db = vec![INVALID; SIZE];
+        for (i, v) in db.iter_mut().enumerate() {
+            let (white_to_move, wk, bk, pawn) = decode(i);
+            *v = classify_initial(white_to_move, wk, bk, pawn);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..SIZE {
+                if db[i] == UNKNOWN {
+                    let (white_to_move, wk, bk, pawn) = decode(i);
+                    let v = classify(&db, white_to_move, wk, bk, pawn);
+                    if v != UNKNOWN {
+                        db[i] = v;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let mut bits = vec![0u64; SIZE / 64];
+        for (i, v) in db.iter().enumerate() {
+            if *v == WIN {
+                bits[i / 64] |= 1 << (i % 64);
+            }
+        }
+        bits
+    }
+}
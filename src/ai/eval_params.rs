@@ -0,0 +1,292 @@
+//! Evaluation weights which can be changed at runtime, eg. for tuning.
+//! Piece square tables and move ordering values are still fixed, see `pst` and `evaluation::evaluate_piece`.
+
+use std::fmt::{Display, Formatter, self};
+use super::super::game::entities::*;
+
+#[derive(Clone, Debug)]
+pub enum ParamsError {
+    BadJson,
+    UnknownParam(String),
+    BadValue(String),
+    Io(String)
+}
+
+impl Display for ParamsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            ParamsError::BadJson => write!(f, "Expected a flat JSON object of numbers"),
+            ParamsError::UnknownParam(name) => write!(f, "Unknown param {}", name),
+            ParamsError::BadValue(value) => write!(f, "Bad value {}", value),
+            ParamsError::Io(e) => write!(f, "IO error - {}", e)
+        }
+    }
+}
+
+/// Declares the fields of `EvalParams` with their defaults, and access by name
+macro_rules! eval_params {
+    ($($(#[$doc:meta])* $name:ident: $default:expr),* $(,)?) => {
+        /// Weights in pawns. Pairs of `_mg` and `_eg` weights are for the middlegame and endgame.
+        #[derive(Clone, Debug)]
+        pub struct EvalParams {
+            $($(#[$doc])* pub $name: f32),*
+        }
+
+        impl Default for EvalParams {
+            fn default() -> Self {
+                Self { $($name: $default),* }
+            }
+        }
+
+        impl EvalParams {
+            /// In declaration order, which is also the order of `to_vec`
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($name)),*];
+
+            pub fn get(&self, name: &str) -> Option<f32> {
+                match name {
+                    $(stringify!($name) => Some(self.$name),)*
+                    _ => None
+                }
+            }
+
+            pub fn get_mut(&mut self, name: &str) -> Option<&mut f32> {
+                match name {
+                    $(stringify!($name) => Some(&mut self.$name),)*
+                    _ => None
+                }
+            }
+        }
+    }
+}
+
+eval_params! {
+    pawn_value: 1.,
+    rook_value: 5.,
+    knight_value: 3.,
+    bishop_value: 3.,
+    queen_value: 9.,
+
+    /// Multiplies the square control total
+    square_control: 0.2,
+    /// Worth of controlling a square on the player's own two ranks, and on the opponent's two ranks
+    square_worth_home: 0.1,
+    square_worth_far: 1.,
+    /// Worth of the middle ranks is `scale * (3.5 - distance from the center file + offset) / 4`
+    square_worth_center_scale: 1.5,
+    square_worth_center_offset: 0.4,
+
+    doubled_mg: -0.1,
+    doubled_eg: -0.25,
+    isolated_mg: -0.1,
+    isolated_eg: -0.15,
+    backward_mg: -0.08,
+    backward_eg: -0.12,
+    /// Defended by a pawn diagonally behind
+    chain_mg: 0.07,
+    chain_eg: 0.1,
+    /// Next to a pawn on the same rank
+    phalanx_mg: 0.05,
+    phalanx_eg: 0.05,
+    /// For each island after the first
+    island_mg: -0.05,
+    island_eg: -0.1,
+    /// Passed pawns by rank from the player's side
+    passed_2_mg: 0.05,
+    passed_2_eg: 0.1,
+    passed_3_mg: 0.1,
+    passed_3_eg: 0.2,
+    passed_4_mg: 0.15,
+    passed_4_eg: 0.35,
+    passed_5_mg: 0.3,
+    passed_5_eg: 0.6,
+    passed_6_mg: 0.5,
+    passed_6_eg: 1.,
+    passed_7_mg: 0.8,
+    passed_7_eg: 1.5,
+
+    /// King safety is middlegame only. Own pawn one and two squares in front, or neither, on the king's and adjacent files.
+    shield_near: 0.15,
+    shield_far: 0.08,
+    shield_missing: -0.1,
+    /// No own pawn on the king's or an adjacent file, or no pawns at all
+    king_semi_open_file: -0.15,
+    king_open_file: -0.25,
+    /// Closest enemy pawn in front on the king's and adjacent files, by distance
+    storm_1: -0.05,
+    storm_2: -0.2,
+    storm_3: -0.1,
+    /// Per square of the king zone a piece controls
+    king_attack_rook: 3.,
+    king_attack_knight: 2.,
+    king_attack_bishop: 2.,
+    king_attack_queen: 5.,
+    /// Danger grows quadratically with the attack weight, up to the cap
+    king_attack_scale: 0.005,
+    king_attack_max: 3.,
+
+    /// Per safe square above or below a typical count for the piece
+    mobility_rook_mg: 0.02,
+    mobility_rook_eg: 0.04,
+    mobility_knight_mg: 0.04,
+    mobility_knight_eg: 0.04,
+    mobility_bishop_mg: 0.05,
+    mobility_bishop_eg: 0.05,
+    mobility_queen_mg: 0.01,
+    mobility_queen_eg: 0.02,
+    /// A piece other than a pawn or king with no safe squares
+    trapped_piece_mg: -0.5,
+    trapped_piece_eg: -0.4,
+    bishop_pair_mg: 0.3,
+    bishop_pair_eg: 0.5,
+    rook_open_file_mg: 0.25,
+    rook_open_file_eg: 0.1,
+    rook_semi_open_file_mg: 0.1,
+    rook_semi_open_file_eg: 0.05,
+    rook_seventh_rank_mg: 0.2,
+    rook_seventh_rank_eg: 0.4,
+    /// Nothing between two rooks of the same player on a rank or file
+    connected_rooks_mg: 0.15,
+    connected_rooks_eg: 0.05,
+    /// Knight in the opponent's half, defended by a pawn and out of reach of the opponent's pawns
+    knight_outpost_mg: 0.25,
+    knight_outpost_eg: 0.15,
+}
+
+impl EvalParams {
+
+    /// The king has no material value since both players always have one
+    #[inline]
+    pub fn get_piece_value(&self, piece: Piece) -> f32 {
+        match piece {
+            Piece::Pawn => self.pawn_value,
+            Piece::Rook => self.rook_value,
+            Piece::Knight => self.knight_value,
+            Piece::Bishop => self.bishop_value,
+            Piece::Queen => self.queen_value,
+            Piece::King => 0.
+        }
+    }
+
+    /// `rank` from the player's side, 0 for the first rank
+    #[inline]
+    pub fn get_passed_pawn(&self, rank: usize) -> (f32, f32) {
+        match rank {
+            1 => (self.passed_2_mg, self.passed_2_eg),
+            2 => (self.passed_3_mg, self.passed_3_eg),
+            3 => (self.passed_4_mg, self.passed_4_eg),
+            4 => (self.passed_5_mg, self.passed_5_eg),
+            5 => (self.passed_6_mg, self.passed_6_eg),
+            6 => (self.passed_7_mg, self.passed_7_eg),
+            _ => (0., 0.)
+        }
+    }
+
+    /// 0 when there is no pawn within range
+    #[inline]
+    pub fn get_storm(&self, distance: usize) -> f32 {
+        match distance {
+            1 => self.storm_1,
+            2 => self.storm_2,
+            3 => self.storm_3,
+            _ => 0.
+        }
+    }
+
+    #[inline]
+    pub fn get_king_attack_weight(&self, piece: Piece) -> f32 {
+        match piece {
+            Piece::Rook => self.king_attack_rook,
+            Piece::Knight => self.king_attack_knight,
+            Piece::Bishop => self.king_attack_bishop,
+            Piece::Queen => self.king_attack_queen,
+            _ => 0.
+        }
+    }
+
+    #[inline]
+    pub fn get_mobility(&self, piece: Piece) -> (f32, f32) {
+        match piece {
+            Piece::Rook => (self.mobility_rook_mg, self.mobility_rook_eg),
+            Piece::Knight => (self.mobility_knight_mg, self.mobility_knight_eg),
+            Piece::Bishop => (self.mobility_bishop_mg, self.mobility_bishop_eg),
+            Piece::Queen => (self.mobility_queen_mg, self.mobility_queen_eg),
+            _ => (0., 0.)
+        }
+    }
+
+    /// Returns false if there is no such param
+    pub fn set(&mut self, name: &str, value: f32) -> bool {
+        match self.get_mut(name) {
+            Some(param) => {
+                *param = value;
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Values in the order of `NAMES`
+    pub fn to_vec(&self) -> Vec<f32> {
+        Self::NAMES.iter().map(|name| self.get(name).unwrap_or(0.)).collect()
+    }
+
+    /// Values in the order of `NAMES`, extra values are ignored and missing values are left as they are
+    pub fn set_from_slice(&mut self, values: &[f32]) {
+        for (name, value) in Self::NAMES.iter().zip(values.iter()) {
+            self.set(name, *value);
+        }
+    }
+
+    /// Flat object of every param, one per line
+    pub fn to_json(&self) -> String {
+        let fields: Vec<String> = Self::NAMES.iter().map(|name| format!("  \"{}\": {}", name, self.get(name).unwrap_or(0.))).collect();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+
+    /// Reads a flat object of numbers, params which are not in it keep their defaults
+    pub fn from_json(json: &str) -> Result<Self, ParamsError> {
+        let mut params = Self::default();
+        params.update_from_json(json)?;
+        Ok(params)
+    }
+
+    /// Like `from_json` but params which are not in `json` keep their current values.
+    /// On error, no params are changed.
+    pub fn update_from_json(&mut self, json: &str) -> Result<(), ParamsError> {
+        let json = json.trim();
+        if !json.starts_with('{') || !json.ends_with('}') {
+            return Err(ParamsError::BadJson);
+        }
+
+        let mut updated = self.clone();
+        for field in json[1..json.len() - 1].split(',') {
+            if field.trim().is_empty() { continue; }
+
+            let mut parts = field.splitn(2, ':');
+            let name = parts.next().ok_or(ParamsError::BadJson)?.trim();
+            let value = parts.next().ok_or(ParamsError::BadJson)?.trim();
+            if name.len() < 2 || !name.starts_with('"') || !name.ends_with('"') {
+                return Err(ParamsError::BadJson);
+            }
+            let name = &name[1..name.len() - 1];
+
+            let value = value.parse::<f32>().map_err(|_| ParamsError::BadValue(String::from(value)))?;
+            if !updated.set(name, value) {
+                return Err(ParamsError::UnknownParam(String::from(name)));
+            }
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str) -> Result<Self, ParamsError> {
+        let json = std::fs::read_to_string(path).map_err(|e| ParamsError::Io(e.to_string()))?;
+        Self::from_json(&json)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &str) -> Result<(), ParamsError> {
+        std::fs::write(path, self.to_json()).map_err(|e| ParamsError::Io(e.to_string()))
+    }
+}
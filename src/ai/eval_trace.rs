@@ -0,0 +1,83 @@
+use std::fmt::{Display, Formatter, self};
+use super::endgame::Endgame;
+
+/// Evaluation terms for one player from their point of view, in pawns, already tapered by the phase
+#[derive(Copy, Clone, Default)]
+pub struct SideTerms {
+    pub material: f32,
+    /// Piece square tables, which replaced the old pawn advance and development bonuses
+    pub placement: f32,
+    pub square_control: f32,
+    pub pawns: f32,
+    pub king_safety: f32,
+    pub mobility: f32,
+    pub pieces: f32
+}
+
+impl SideTerms {
+
+    pub fn get_total(&self) -> f32 {
+        self.material + self.placement + self.square_control + self.pawns + self.king_safety + self.mobility + self.pieces
+    }
+
+    fn get_named(&self) -> [(&'static str, f32); 7] {
+        [
+            ("material", self.material),
+            ("placement", self.placement),
+            ("squareControl", self.square_control),
+            ("pawns", self.pawns),
+            ("kingSafety", self.king_safety),
+            ("mobility", self.mobility),
+            ("pieces", self.pieces)
+        ]
+    }
+
+    fn to_json(&self) -> String {
+        let fields: Vec<String> = self.get_named().iter().map(|(name, v)| format!("\"{}\":{}", name, v)).collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// Why `evaluate` gives its score. `total` is the rounded white minus black after any endgame knowledge, as returned by `evaluate`.
+#[derive(Copy, Clone, Default)]
+pub struct EvalTrace {
+    pub white: SideTerms,
+    pub black: SideTerms,
+    /// From `pst::MAX_PHASE` for the middlegame to 0 for the endgame
+    pub phase: i32,
+    /// Replaced or scaled the total
+    pub endgame: Option<Endgame>,
+    pub total: f32
+}
+
+impl EvalTrace {
+
+    /// `{"white": {...}, "black": {...}, "phase", "endgame", "total"}` where `endgame` is a name or `null`
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"white\":{},\"black\":{},\"phase\":{},\"endgame\":{},\"total\":{}}}",
+            self.white.to_json(),
+            self.black.to_json(),
+            self.phase,
+            self.endgame.map_or(String::from("null"), |e| format!("\"{}\"", e.get_name())),
+            self.total
+        )
+    }
+}
+
+impl Display for EvalTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        writeln!(f, "{:>14} | {:>7} | {:>7} | {:>7}", "Term", "White", "Black", "Total")?;
+        writeln!(f, "{:-<14}-+-{:-<7}-+-{:-<7}-+-{:-<7}", "", "", "", "")?;
+        for ((name, w), (_, b)) in self.white.get_named().iter().zip(self.black.get_named().iter()) {
+            writeln!(f, "{:>14} | {:>7.2} | {:>7.2} | {:>7.2}", name, w, b, w - b)?;
+        }
+        writeln!(f, "{:-<14}-+-{:-<7}-+-{:-<7}-+-{:-<7}", "", "", "", "")?;
+        writeln!(f, "{:>14} | {:>7.2} | {:>7.2} | {:>7.2}", "total", self.white.get_total(), self.black.get_total(), self.total)?;
+        write!(f, "Phase {}", self.phase)?;
+        if let Some(endgame) = self.endgame {
+            write!(f, ", endgame {}", endgame.get_name())?;
+        }
+        Ok(())
+    }
+}
@@ -4,18 +4,36 @@ use super::super::game::board::*;
 use super::super::game::move_list::*;
 use super::super::game::move_test::*;
 use super::super::game::push_moves_handler::*;
+use super::pst;
+use super::pawns::*;
+use super::king_safety::*;
+use super::pieces::*;
+use super::eval_params::*;
+use super::eval_trace::*;
+use super::evaluator::*;
+use super::endgame::{self, Endgame, EndgameEval};
+use super::incremental::*;
 
 /// Must be bigger than all piece values
 const NO_CONTROL_VAL: f32 = 99.;
 
+/// Most squares a piece can control, indexed by `Piece`
+static MAX_CONTROLLED_SQUARES: [u32; 6] = [2, 14, 8, 13, 27, 8];
+
+/// Covers the rounding of `evaluate`
+const LAZY_EVAL_ROUNDING: f32 = 0.01;
+
+/// For move ordering and ranking square controllers, which rely on these being whole numbers.
+/// Material in `evaluate` uses `EvalParams`.
 static PIECE_VALUES: [i8; 6] = [
     1, 5, 3, 3, 9, 10
 ];
 
-static SOME_VALUES_1: [(f32, f32); 2] = [(7., -1.), (0., 1.)];
-
 struct SquareControlHandler<'a> {
-    temp_arr: &'a mut [f32; 64]
+    params: &'a EvalParams,
+    temp_arr: &'a mut [f32; 64],
+    king_attacks: KingAttacks,
+    mobility: Mobility
 }
 
 pub fn clear_square_control(a: &mut [f32; 64]) {
@@ -23,35 +41,46 @@ pub fn clear_square_control(a: &mut [f32; 64]) {
 }
 
 #[inline]
-pub fn get_square_worth_white(x: usize, y: usize) -> f32 {
-    if y <= 1 { 1.0 }
-    else if y >= 6 { 0.1 }
-    else { 1.5 * (3.5 - (3.5 - x as f32).abs() + 0.4) / 4. }
+fn get_square_worth_center(params: &EvalParams, x: usize) -> f32 {
+    params.square_worth_center_scale * (3.5 - (3.5 - x as f32).abs() + params.square_worth_center_offset) / 4.
+}
+
+#[inline]
+pub fn get_square_worth_white(params: &EvalParams, x: usize, y: usize) -> f32 {
+    if y <= 1 { params.square_worth_far }
+    else if y >= 6 { params.square_worth_home }
+    else { get_square_worth_center(params, x) }
 }
 
 #[inline]
-pub fn get_square_worth_black(x: usize, y: usize) -> f32 {
-    if y >= 6 { 1.0 }
-    else if y <= 1 { 0.1 }
-    else { 1.5 * (3.5 - (3.5 - x as f32).abs() + 0.4) / 4. }
+pub fn get_square_worth_black(params: &EvalParams, x: usize, y: usize) -> f32 {
+    if y >= 6 { params.square_worth_far }
+    else if y <= 1 { params.square_worth_home }
+    else { get_square_worth_center(params, x) }
 }
 
 /// Currently, returns at 4 for a center square, 3 for opponent side square
-pub fn get_white_square_control(a: &mut [f32; 64]) -> f32 {
-    let mut eval = 0.;
+pub fn get_white_square_control(params: &EvalParams, a: &mut [f32; 64]) -> f32 {
+    let control = get_square_control(params, a);
+    control[0] - control[1]
+}
+
+/// Worth of the squares each player controls alone, indexed by `Player`
+fn get_square_control(params: &EvalParams, a: &[f32; 64]) -> [f32; 2] {
+    let mut control = [0.; 2];
     for y in 0..8 {
         for x in 0..8 {
             let v = a[y * 8 + x];
             if v != NO_CONTROL_VAL && v.round() == v {
                 if v < 0. {
-                    eval -= get_square_worth_black(x, y);
+                    control[Player::Black as usize] += get_square_worth_black(params, x, y);
                 } else if v > 0. {
-                    eval += get_square_worth_white(x, y);
+                    control[Player::White as usize] += get_square_worth_white(params, x, y);
                 }
             }
         }
     }
-    eval
+    control
 }
 
 /// Precondition: `temp_arr` is cleared to a number > the highest piece value before move tests
@@ -68,6 +97,9 @@ impl <'a> MoveTestHandler for SquareControlHandler<'a> {
     ) -> bool {
         if !can_capture { return false; }
 
+        self.king_attacks.on_control(params.src_player, dest_x, dest_y);
+        self.mobility.on_control(params.src_player, moveable, dest_x, dest_y);
+
         let index = dest_y as usize * 8 + dest_x as usize;
 
         let mut lowest_controller_value_negpos = self.temp_arr[index];
@@ -97,26 +129,13 @@ pub fn evaluate_piece(piece: Piece) -> f32 {
     PIECE_VALUES[piece as usize] as f32
 }
 
-fn evaluate_player(board: &Board, handler: &mut SquareControlHandler, player: Player) -> f32 {
+/// Material and placement come from `Board::get_score` instead
+fn fill_player_control(board: &Board, handler: &mut SquareControlHandler, player: Player) {
 
     let ps = board.get_player_state(player);
-    let some_values_1 = SOME_VALUES_1[player as usize];
-
-    let mut value: f32 = 0.;
 
     for Coord(x, y) in ps.piece_locs.iter() {
-        let fy = *y as f32;
-
         if let Square::Occupied(piece, _) = board.get_by_xy(*x, *y) {
-            value += evaluate_piece(*piece);
-            if *piece == Piece::Pawn {
-                value += 0.3 * (some_values_1.0 + some_values_1.1 * fy);
-            } else {
-                if fy > 0. && fy < 7. {
-                    value += 0.75;
-                }
-            }
-
             fill_src(&MoveTestParams {
                 src_x: *x as i8,
                 src_y: *y as i8,
@@ -125,23 +144,212 @@ fn evaluate_player(board: &Board, handler: &mut SquareControlHandler, player: Pl
                 can_capture_king: true,
                 board: &board
             }, handler);
+            handler.king_attacks.end_piece(handler.params, player, *piece);
+            handler.mobility.end_piece(handler.params, player, *piece);
         }
     }
-    value * player.get_multiplier()
 }
 
+/// Bounds from the params on the terms other than material and placement, so that leaves whose incremental
+/// base score is further outside the window than the terms can move it skip the full evaluation
+struct LazyMargin {
+    /// Per controlled square, and for every square of the board
+    square_control: f32,
+    all_square_control: f32,
+    /// Indexed by `Piece`
+    per_piece: [f32; 6],
+    /// Whatever the pieces
+    per_player: f32
+}
+
+impl LazyMargin {
+
+    fn new(params: &EvalParams) -> Self {
+        let mut square_worth: f32 = 0.;
+        let mut all_square_worth = 0.;
+        for y in 0..8 {
+            for x in 0..8 {
+                let worth = get_square_worth_white(params, x, y).abs().max(get_square_worth_black(params, x, y).abs());
+                square_worth = square_worth.max(worth);
+                all_square_worth += worth;
+            }
+        }
+
+        let (per_pawn, islands) = get_max_pawn_terms(params);
+        let (mut per_piece, pieces) = get_max_piece_terms(params);
+        per_piece[Piece::Pawn as usize] = per_pawn;
+
+        Self {
+            square_control: params.square_control.abs() * square_worth,
+            all_square_control: params.square_control.abs() * all_square_worth,
+            per_piece,
+            per_player: islands + pieces + get_max_king_safety(params)
+        }
+    }
+
+    /// How far `evaluate` can be from `IncrementalScore::get_base_eval` at most, outside of special endgames
+    fn get(&self, score: &IncrementalScore) -> f32 {
+        let mut margin = 2. * self.per_player + LAZY_EVAL_ROUNDING;
+        let mut controlled = 0;
+        for player in [Player::White, Player::Black].iter() {
+            for piece in PIECES.iter() {
+                let count = score.get_count(*player, *piece);
+                margin += count as f32 * self.per_piece[*piece as usize];
+                controlled += count as u32 * MAX_CONTROLLED_SQUARES[*piece as usize];
+            }
+        }
+        margin + (controlled as f32 * self.square_control).min(self.all_square_control)
+    }
+}
+
+/// The hand written evaluation, with the buffers which `evaluate` needs. The default `Evaluator` of search,
+/// and also used outside of search, eg. for tuning.
+pub struct StaticEvaluator {
+    pub params: EvalParams,
+    temp_arr: [f32; 64],
+    temp_moves: MoveList,
+    pawn_table: PawnTable,
+    lazy_margin: LazyMargin,
+    /// `endgame::probe` of the board last passed to `get_lazy_eval` by hash, for the `evaluate` which follows
+    probed: Option<(u64, Option<(Endgame, EndgameEval)>)>
+}
+
+impl StaticEvaluator {
+
+    pub fn new(params: EvalParams) -> Self {
+        Self::with_pawn_table(params, STATIC_PAWN_TABLE_SIZE_LOG2)
+    }
+
+    /// The pawn table has `2 ^ size_log2` entries, and is kept between positions since entries only depend on the pawns
+    pub fn with_pawn_table(params: EvalParams, size_log2: u8) -> Self {
+        Self {
+            lazy_margin: LazyMargin::new(&params),
+            params,
+            temp_arr: [0.; 64],
+            temp_moves: MoveList::new(50),
+            pawn_table: PawnTable::new(size_log2),
+            probed: None
+        }
+    }
+
+    /// Must be called after changing `params` directly
+    pub fn clear_cache(&mut self) {
+        self.pawn_table.clear();
+        self.lazy_margin = LazyMargin::new(&self.params);
+        self.probed = None;
+    }
+
+    /// Pawn table hits since the last call
+    pub fn take_pawn_hits(&mut self) -> usize {
+        std::mem::replace(&mut self.pawn_table.hits, 0)
+    }
+
+    /// White's point of view
+    pub fn evaluate(&mut self, board: &Board) -> f32 {
+        let endgame = match self.probed.take() {
+            Some((hash, endgame)) if hash == board.get_hash() => endgame,
+            _ => endgame::probe(board, &self.params)
+        };
+        evaluate_probed(board, &self.params, endgame, &mut self.temp_arr, &mut self.pawn_table)
+    }
+
+    pub fn trace(&mut self, board: &Board) -> EvalTrace {
+        evaluate_trace(board, &self.params, &mut self.temp_arr, &mut self.pawn_table)
+    }
+}
+
+impl Evaluator for StaticEvaluator {
+
+    fn evaluate(&mut self, board: &Board) -> f32 {
+        StaticEvaluator::evaluate(self, board)
+    }
+
+    /// Endgame evaluations are far from the base score. The probe is kept for the following `evaluate`.
+    fn get_lazy_eval(&mut self, board: &Board) -> Option<(f32, f32)> {
+        let endgame = endgame::probe(board, &self.params);
+        self.probed = Some((board.get_hash(), endgame));
+        if endgame.is_some() {
+            return None;
+        }
+        let score = board.get_score();
+        Some((score.get_base_eval(&self.params), self.lazy_margin.get(score)))
+    }
+
+    fn add_move_hints(&mut self, board: &Board, moves: &mut MoveList, start: usize, end_exclusive: usize) {
+        add_aggression_to_evals(board, &self.params, moves, start, end_exclusive, &mut self.temp_arr, &mut self.temp_moves);
+    }
+}
+
+const STATIC_PAWN_TABLE_SIZE_LOG2: u8 = 10;
+
 fn round_eval(v: f32) -> f32 {
     (v * 100.).round() / 100.
 }
 
-pub fn evaluate(board: &Board, temp_arr: &mut [f32; 64]) -> f32 {
+/// `StaticEvaluator::evaluate` with every term
+pub fn evaluate_trace(board: &Board, params: &EvalParams, temp_arr: &mut [f32; 64], pawn_table: &mut PawnTable) -> EvalTrace {
+    evaluate_trace_probed(board, params, endgame::probe(board, params), temp_arr, pawn_table)
+}
+
+/// White's point of view, given the result of `endgame::probe` for `board`
+fn evaluate_probed(
+    board: &Board,
+    params: &EvalParams,
+    endgame: Option<(Endgame, EndgameEval)>,
+    temp_arr: &mut [f32; 64],
+    pawn_table: &mut PawnTable
+) -> f32 {
+    if let Some((_, EndgameEval::Exact(eval))) = endgame {
+        return eval;
+    }
+    evaluate_trace_probed(board, params, endgame, temp_arr, pawn_table).total
+}
+
+fn evaluate_trace_probed(
+    board: &Board,
+    params: &EvalParams,
+    endgame: Option<(Endgame, EndgameEval)>,
+    temp_arr: &mut [f32; 64],
+    pawn_table: &mut PawnTable
+) -> EvalTrace {
     clear_square_control(temp_arr);
 
-    let mut handler = SquareControlHandler { temp_arr };
-    let white_eval = evaluate_player(board, &mut handler, Player::White);
-    let black_eval = evaluate_player(board, &mut handler, Player::Black);
-    
-    round_eval(0.2 * get_white_square_control(handler.temp_arr) + white_eval + black_eval)
+    let files = get_pawn_files(board);
+    let mut handler = SquareControlHandler { params, temp_arr, king_attacks: KingAttacks::new(board), mobility: Mobility::new(&files) };
+    fill_player_control(board, &mut handler, Player::White);
+    fill_player_control(board, &mut handler, Player::Black);
+
+    let score = board.get_score();
+    let phase = score.get_phase();
+    let square_control = get_square_control(params, handler.temp_arr);
+    let pawn_evals = pawn_table.get(board, params);
+    let piece_evals = evaluate_pieces(board, params, &files);
+    let king_safety = evaluate_king_safety(params, &files, &handler.king_attacks);
+
+    let mut sides = [SideTerms::default(); 2];
+    for (i, side) in sides.iter_mut().enumerate() {
+        let player = if i == 0 { Player::White } else { Player::Black };
+        side.material = score.get_material(params, player);
+        side.placement = score.get_placement(player);
+        side.square_control = params.square_control * square_control[i];
+        side.pawns = pst::taper(pawn_evals[i].mg, pawn_evals[i].eg, phase);
+        side.king_safety = pst::taper(king_safety[i], 0., phase);
+        side.mobility = pst::taper(handler.mobility.mg[i], handler.mobility.eg[i], phase);
+        side.pieces = pst::taper(piece_evals[i].0, piece_evals[i].1, phase);
+    }
+
+    let mut total = round_eval(sides[0].get_total() - sides[1].get_total());
+    if let Some((_, endgame_eval)) = endgame {
+        total = round_eval(endgame_eval.apply(total));
+    }
+
+    EvalTrace {
+        white: sides[0],
+        black: sides[1],
+        phase,
+        endgame: endgame.map(|(e, _)| e),
+        total
+    }
 }
 
 pub fn add_captures_to_evals(
@@ -162,6 +370,7 @@ pub fn add_captures_to_evals(
 
 pub fn add_aggression_to_evals(
     board: &Board,
+    params: &EvalParams,
     m: &mut MoveList,
     start: usize,
     end_exclusive: usize,
@@ -169,7 +378,7 @@ pub fn add_aggression_to_evals(
     temp_ml: &mut MoveList
 ) {
     clear_square_control(temp_arr);
-    get_white_square_control(temp_arr);
+    get_white_square_control(params, temp_arr);
 
     let mut handler = PushToMoveListHandler { move_list: temp_ml };
     m.write_evals(start, end_exclusive, |m| {
@@ -205,3 +414,47 @@ pub fn add_aggression_to_evals(
         score
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FENS: [&str; 4] = [
+        STARTING_FEN,
+        "4R3/pP3k2/5P2/8/4K2p/3P1R2/2PB1N1P/8 b - - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "1Q4k1/5ppp/8/8/8/8/1q3PPP/1R4K1 b - - 0 1"
+    ];
+
+    fn check_lazy_margin(evaluator: &mut StaticEvaluator, board: &Board) {
+        if let Some((base_eval, margin)) = evaluator.get_lazy_eval(board) {
+            let eval = evaluator.evaluate(board);
+            assert!((eval - base_eval).abs() <= margin, "{} is {} from the base {} by more than {}", board.to_fen(), eval, base_eval, margin);
+        }
+    }
+
+    #[test]
+    fn lazy_margin_bounds_evaluate() {
+        let mut evaluator = StaticEvaluator::new(EvalParams::default());
+        let mut temp = MoveList::new(50);
+        let mut moves = MoveList::new(50);
+        // Fixed pseudo random playouts from each position
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+
+        for fen in FENS.iter() {
+            for _ in 0..8 {
+                let mut board = Board::from_fen(fen).unwrap();
+                for _ in 0..60 {
+                    check_lazy_margin(&mut evaluator, &board);
+
+                    moves.write_index = 0;
+                    board.get_moves(&mut temp, &mut moves);
+                    if moves.write_index == 0 { break; }
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    let m = moves.get_v()[(seed >> 33) as usize % moves.write_index].clone();
+                    board.handle_move(&m, true);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use super::super::game::board::*;
+use super::super::game::move_list::*;
+
+/// What search needs from an evaluation. `StaticEvaluator` with the hand written heuristics is the default,
+/// see `Ai::set_evaluator` to search with another.
+pub trait Evaluator {
+    /// In pawns, from white's point of view
+    fn evaluate(&mut self, board: &Board) -> f32;
+
+    /// A cheap eval from white's point of view and how far `evaluate` can be from it at most, both in pawns,
+    /// so that search can skip `evaluate` at leaves far outside the window. `evaluate` may follow with the same board.
+    fn get_lazy_eval(&mut self, _board: &Board) -> Option<(f32, f32)> {
+        None
+    }
+
+    /// Adds to the evals of `moves` in `start..end_exclusive`, which are then searched highest first.
+    /// Not called in quiescence, where capture order is enough.
+    fn add_move_hints(&mut self, _board: &Board, _moves: &mut MoveList, _start: usize, _end_exclusive: usize) {}
+
+    /// Called with the search board before searching, eg. to attach incremental state to it
+    fn prepare(&mut self, _board: &mut Board) {}
+
+    /// Called after every `Board::handle_move` on the search board with the same arguments
+    fn on_move(&mut self, _board: &Board, _m: &MoveSnapshot, _apply_or_undo: bool) {}
+}
@@ -0,0 +1,370 @@
+//! Post-game analysis. Every position of a game is searched to a fixed depth, and each move is classified by how much
+//! it lowers the mover's winning chances compared to the best move, with winning chances and accuracy
+//! from evals as in Lichess' published formulas.
+
+use std::fmt::{Display, Formatter, self};
+use super::super::game::entities::*;
+use super::super::game::board::*;
+use super::super::game::move_list::*;
+use super::super::game::check_handler::*;
+use super::super::game::pgn::*;
+use super::memo_table::*;
+use super::*;
+
+/// From the winning chances lost by a move, in percent, see `MoveClass::from_win_drop`
+const GOOD_WIN_DROP: f32 = 2.;
+const INACCURACY_WIN_DROP: f32 = 10.;
+const MISTAKE_WIN_DROP: f32 = 20.;
+const BLUNDER_WIN_DROP: f32 = 30.;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MoveClass {
+    Best,
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder
+}
+
+impl MoveClass {
+
+    fn from_win_drop(is_best_move: bool, win_drop: f32) -> Self {
+        if is_best_move || win_drop < GOOD_WIN_DROP {
+            MoveClass::Best
+        } else if win_drop < INACCURACY_WIN_DROP {
+            MoveClass::Good
+        } else if win_drop < MISTAKE_WIN_DROP {
+            MoveClass::Inaccuracy
+        } else if win_drop < BLUNDER_WIN_DROP {
+            MoveClass::Mistake
+        } else {
+            MoveClass::Blunder
+        }
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            MoveClass::Best => "best",
+            MoveClass::Good => "good",
+            MoveClass::Inaccuracy => "inaccuracy",
+            MoveClass::Mistake => "mistake",
+            MoveClass::Blunder => "blunder"
+        }
+    }
+
+    /// PGN move suffix
+    pub fn get_annotation(self) -> &'static str {
+        match self {
+            MoveClass::Best | MoveClass::Good => "",
+            MoveClass::Inaccuracy => "?!",
+            MoveClass::Mistake => "?",
+            MoveClass::Blunder => "??"
+        }
+    }
+}
+
+impl Display for MoveClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+/// Evals are in pawns from the point of view of the player who moved
+pub struct MoveAnalysis {
+    pub player: Player,
+    pub played: MoveSnapshot,
+    pub san: String,
+    pub class: MoveClass,
+    /// Eval of the best move
+    pub best_eval: f32,
+    /// Eval of the position after the played move
+    pub played_eval: f32,
+    /// From 0 to 100
+    pub accuracy: f32,
+    pub best_move: MoveSnapshot,
+    /// Line starting with the best move, in SAN
+    pub best_line: Vec<String>
+}
+
+impl MoveAnalysis {
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"move\":\"{}\",\"san\":\"{}\",\"class\":\"{}\",\"bestEval\":{},\"playedEval\":{},\"accuracy\":{},\"bestMove\":\"{}\",\"bestLine\":[{}]}}",
+            self.played.to_long_algebraic(),
+            self.san,
+            self.class,
+            self.best_eval,
+            self.played_eval,
+            self.accuracy,
+            self.best_move.to_long_algebraic(),
+            self.best_line.iter().map(|san| format!("\"{}\"", san)).collect::<Vec<String>>().join(",")
+        )
+    }
+}
+
+pub struct GameAnalysis {
+    /// `None` for the standard starting position
+    pub start_fen: Option<String>,
+    pub start_player: Player,
+    /// Fewer than the game's moves if the analysis was stopped
+    pub moves: Vec<MoveAnalysis>,
+    /// Checkmate or stalemate at the end of the game, otherwise unknown
+    pub result: GameResult
+}
+
+impl GameAnalysis {
+
+    /// Mean accuracy of `player`'s moves, NaN if they have none
+    pub fn get_accuracy(&self, player: Player) -> f32 {
+        let accuracies: Vec<f32> = self.moves.iter().filter(|a| a.player == player).map(|a| a.accuracy).collect();
+        accuracies.iter().sum::<f32>() / accuracies.len() as f32
+    }
+
+    /// Number of `player`'s moves in `class`
+    pub fn get_count(&self, player: Player, class: MoveClass) -> usize {
+        self.moves.iter().filter(|a| a.player == player && a.class == class).count()
+    }
+
+    /// `{"white": {"accuracy", "inaccuracies", "mistakes", "blunders"}, "black": {...}, "result", "moves": [...]}`,
+    /// with accuracy `null` for a side without moves
+    pub fn to_json(&self) -> String {
+        let side_json = |player: Player| {
+            let accuracy = self.get_accuracy(player);
+            format!(
+                "{{\"accuracy\":{},\"inaccuracies\":{},\"mistakes\":{},\"blunders\":{}}}",
+                if accuracy.is_nan() { String::from("null") } else { accuracy.to_string() },
+                self.get_count(player, MoveClass::Inaccuracy),
+                self.get_count(player, MoveClass::Mistake),
+                self.get_count(player, MoveClass::Blunder)
+            )
+        };
+        format!(
+            "{{\"white\":{},\"black\":{},\"result\":\"{}\",\"moves\":[{}]}}",
+            side_json(Player::White),
+            side_json(Player::Black),
+            self.result.to_token(),
+            self.moves.iter().map(|a| a.to_json()).collect::<Vec<String>>().join(",")
+        )
+    }
+
+    /// The game with `[%eval]` comments from White's point of view, `?!`, `?` and `??` on inaccuracies, mistakes
+    /// and blunders, and the best line as a variation for those
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        pgn.push_str("[Event \"Game analysis\"]\n");
+        pgn.push_str("[Annotator \"chess_bs\"]\n");
+        pgn.push_str(&format!("[Result \"{}\"]\n", self.result.to_token()));
+        if let Some(fen) = &self.start_fen {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{}\"]\n", fen));
+        }
+        pgn.push('\n');
+
+        let format_accuracy = |player: Player| {
+            let accuracy = self.get_accuracy(player);
+            if accuracy.is_nan() { String::from("-") } else { format!("{:.1}", accuracy) }
+        };
+        let mut tokens: Vec<String> = vec![format!(
+            "{{ Accuracy: White {}, Black {} }}",
+            format_accuracy(Player::White),
+            format_accuracy(Player::Black)
+        )];
+
+        // Every move has a comment after it, so Black's moves are also numbered
+        for (i, a) in self.moves.iter().enumerate() {
+            let number = get_move_number(self.start_player, i);
+            if a.player == Player::White {
+                tokens.push(format!("{}.", number));
+            } else {
+                tokens.push(format!("{}...", number));
+            }
+            tokens.push(format!("{}{}", a.san, a.class.get_annotation()));
+
+            let white_eval = a.played_eval * a.player.get_multiplier();
+            let mut comment = format!("{{ [%eval {:.2}]", white_eval);
+            let is_bad = !a.class.get_annotation().is_empty();
+            if is_bad {
+                comment.push_str(&format!(" {}. {} was best.", capitalize(a.class.get_name()), a.best_line[0]));
+            }
+            comment.push_str(" }");
+            tokens.push(comment);
+
+            if is_bad {
+                let mut variation = Vec::new();
+                for (j, san) in a.best_line.iter().enumerate() {
+                    let player = if j % 2 == 0 { a.player } else { a.player.get_other_player() };
+                    let number = get_move_number(self.start_player, i + j);
+                    if player == Player::White {
+                        variation.push(format!("{}.", number));
+                    } else if j == 0 {
+                        variation.push(format!("{}...", number));
+                    }
+                    variation.push(san.clone());
+                }
+                tokens.push(format!("({})", variation.join(" ")));
+            }
+        }
+        tokens.push(String::from(self.result.to_token()));
+
+        // Lines of at most 80 characters
+        let mut line_len = 0;
+        for token in tokens.iter() {
+            if line_len > 0 && line_len + 1 + token.len() > 80 {
+                pgn.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                pgn.push(' ');
+                line_len += 1;
+            }
+            pgn.push_str(token);
+            line_len += token.len();
+        }
+        pgn.push('\n');
+        pgn
+    }
+}
+
+fn get_move_number(start_player: Player, ply: usize) -> usize {
+    (ply + if start_player == Player::White { 0 } else { 1 }) / 2 + 1
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars.next().map_or(String::new(), |c| c.to_uppercase().chain(chars).collect())
+}
+
+/// Lichess' winning chances from 0 to 100 for an eval in pawns
+fn get_win_percent(eval: f32) -> f32 {
+    let cp = (eval * 100.).max(-1000.).min(1000.);
+    50. + 50. * (2. / (1. + (-0.00368208 * cp).exp()) - 1.)
+}
+
+/// Lichess' accuracy of a move from 0 to 100, given the winning chances before and after it
+fn get_move_accuracy(win_before: f32, win_after: f32) -> f32 {
+    let drop = (win_before - win_after).max(0.);
+    (103.1668 * (-0.04354 * drop).exp() - 3.1669).max(0.).min(100.)
+}
+
+impl <M: MemoTable + 'static> Ai<M> {
+
+    /// Eval and best line of `board` for the player with the turn, or the game over eval if there are no moves.
+    /// `None` if stopped before finishing depth 1.
+    fn analyze_position(&mut self, board: &mut Board, depth: u8) -> Option<(f32, Vec<MoveSnapshot>)> {
+        let mut temp = MoveList::new(50);
+        let mut moves = MoveList::new(50);
+        board.get_moves(&mut temp, &mut moves);
+        if moves.write_index == 0 {
+            let is_checkmate = is_checking(board, board.get_player_with_turn().get_other_player());
+            return Some((if is_checkmate { -MAX_EVAL } else { 0. }, Vec::new()));
+        }
+
+        let mut lines = self.search(depth, board, 1);
+        if lines.is_empty() || lines[0].moves.is_empty() {
+            return None;
+        }
+        let line = lines.swap_remove(0);
+        Some((line.eval, line.moves))
+    }
+
+    /// Eval of root move `m` of `board` searched to `depth` with every other root move excluded, so that it is
+    /// comparable with the eval of the best move. `None` if stopped or if `m` is mated.
+    fn search_root_move(&mut self, board: &Board, depth: u8, m: &MoveSnapshot) -> Option<f32> {
+        self.begin_search(board);
+        let mut temp = MoveList::new(50);
+        let mut moves = MoveList::new(50);
+        self.test_board.get_moves(&mut temp, &mut moves);
+        for other in moves.get_v()[..moves.write_index].iter() {
+            if other.to_long_algebraic() == m.to_long_algebraic() { continue; }
+            self.handle_test_move(other, true);
+            self.excluded_root_moves.push(self.test_board.get_hash());
+            self.handle_test_move(other, false);
+        }
+
+        // A stopped iteration leaves the eval of an earlier one
+        let last_depth = depth - (depth + 1) % 2;
+        let eval = match self.iterative_deepening(1, depth) {
+            Some((_, eval, d)) if d == last_depth => Some(eval),
+            _ => None
+        };
+        self.end_search();
+        eval
+    }
+
+    /// Searches each position of the game played from `start` by `moves` to `depth`, and `node_limit` nodes per
+    /// iteration if set. Stops early at the first position the search is stopped in, see `get_stop_handle`.
+    pub fn analyze_game(&mut self, start: &Board, moves: &[MoveSnapshot], depth: u8, node_limit: Option<u32>) -> GameAnalysis {
+        let prev_node_limit = self.node_limit;
+        self.node_limit = node_limit;
+        let depth = depth.max(1);
+
+        let mut board = start.clone();
+        let mut analysis = GameAnalysis {
+            start_fen: if start.get_hash() == Board::new().get_hash() { None } else { Some(start.to_fen()) },
+            start_player: start.get_player_with_turn(),
+            moves: Vec::with_capacity(moves.len()),
+            result: GameResult::Unknown
+        };
+
+        // Each position's eval is also the eval of the move before it, from the other side
+        let mut current = self.analyze_position(&mut board, depth);
+        for m in moves.iter() {
+            let (best_eval, best_moves) = match current.take() {
+                Some((eval, line)) if !line.is_empty() => (eval, line),
+                _ => break
+            };
+            let player = board.get_player_with_turn();
+            let san = to_san(&mut board, m);
+
+            let mut line_board = board.clone();
+            let best_line: Vec<String> = best_moves.iter().map(|best| {
+                let best_san = to_san(&mut line_board, best);
+                line_board.handle_move(best, true);
+                best_san
+            }).collect();
+
+            let is_best_move = best_moves[0].to_long_algebraic() == m.to_long_algebraic();
+            let played_root_eval = if is_best_move { Some(best_eval) } else { self.search_root_move(&board, depth, m) };
+
+            board.handle_move(m, true);
+            current = self.analyze_position(&mut board, depth);
+            let played_eval = match (played_root_eval, &current) {
+                (Some(eval), _) => eval,
+                (None, Some((eval, _))) => -eval,
+                (None, None) => break
+            };
+            // A move searched alone can find more than the best move's search did
+            let played_eval = played_eval.min(best_eval);
+            let (win_before, win_after) = (get_win_percent(best_eval), get_win_percent(played_eval));
+
+            analysis.moves.push(MoveAnalysis {
+                player,
+                played: m.clone(),
+                san,
+                class: MoveClass::from_win_drop(is_best_move, win_before - win_after),
+                best_eval,
+                played_eval,
+                accuracy: get_move_accuracy(win_before, win_after),
+                best_move: best_moves[0].clone(),
+                best_line
+            });
+        }
+
+        if analysis.moves.len() == moves.len() {
+            if let Some((eval, line)) = &current {
+                if line.is_empty() {
+                    analysis.result = if *eval == 0. {
+                        GameResult::Draw
+                    } else if board.get_player_with_turn() == Player::White {
+                        GameResult::BlackWins
+                    } else {
+                        GameResult::WhiteWins
+                    };
+                }
+            }
+        }
+
+        self.node_limit = prev_node_limit;
+        analysis
+    }
+}
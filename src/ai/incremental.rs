@@ -0,0 +1,65 @@
+use super::super::game::entities::*;
+use super::eval_params::*;
+use super::pst;
+
+/// Piece counts and piece square table sums, kept up to date by `Board::set_by_xy` as moves are applied and undone,
+/// so that evaluation starts from them without scanning the pieces. Integers so that undoing restores them exactly.
+#[derive(Clone, Default)]
+pub struct IncrementalScore {
+    /// Indexed by `Player` then `Piece`
+    counts: [[u8; 6]; 2],
+    /// Centipawns, indexed by `Player`
+    mg_pst: [i32; 2],
+    eg_pst: [i32; 2],
+    phase: i32
+}
+
+impl IncrementalScore {
+
+    #[inline]
+    pub fn add(&mut self, piece: Piece, player: Player, x: u8, y: u8) {
+        let i = player as usize;
+        self.counts[i][piece as usize] += 1;
+        self.mg_pst[i] += pst::get_mg_centipawns(piece, player, x, y);
+        self.eg_pst[i] += pst::get_eg_centipawns(piece, player, x, y);
+        self.phase += pst::get_phase_weight(piece);
+    }
+
+    #[inline]
+    pub fn remove(&mut self, piece: Piece, player: Player, x: u8, y: u8) {
+        let i = player as usize;
+        self.counts[i][piece as usize] -= 1;
+        self.mg_pst[i] -= pst::get_mg_centipawns(piece, player, x, y);
+        self.eg_pst[i] -= pst::get_eg_centipawns(piece, player, x, y);
+        self.phase -= pst::get_phase_weight(piece);
+    }
+
+    #[inline]
+    pub fn get_count(&self, player: Player, piece: Piece) -> u8 {
+        self.counts[player as usize][piece as usize]
+    }
+
+    /// From `pst::MAX_PHASE` for the middlegame to 0 for the endgame
+    #[inline]
+    pub fn get_phase(&self) -> i32 {
+        self.phase.min(pst::MAX_PHASE)
+    }
+
+    /// From `player`'s point of view
+    pub fn get_material(&self, params: &EvalParams, player: Player) -> f32 {
+        PIECES.iter().map(|piece| self.get_count(player, *piece) as f32 * params.get_piece_value(*piece)).sum()
+    }
+
+    /// Tapered piece square tables in pawns, from `player`'s point of view
+    #[inline]
+    pub fn get_placement(&self, player: Player) -> f32 {
+        let i = player as usize;
+        pst::taper(self.mg_pst[i] as f32 / 100., self.eg_pst[i] as f32 / 100., self.get_phase())
+    }
+
+    /// Material and placement from white's point of view, for a quick estimate of `evaluate`
+    pub fn get_base_eval(&self, params: &EvalParams) -> f32 {
+        self.get_material(params, Player::White) - self.get_material(params, Player::Black)
+            + self.get_placement(Player::White) - self.get_placement(Player::Black)
+    }
+}
@@ -0,0 +1,130 @@
+use super::super::game::entities::*;
+use super::super::game::coords::*;
+use super::super::game::board::*;
+use super::pawns::*;
+use super::eval_params::*;
+
+// King safety is middlegame only so it tapers off with the material
+
+/// Furthest enemy pawn counted as a pawn storm
+const STORM_RANGE: i8 = 3;
+
+/// Attacks on each king zone collected during the square control pass, indexed by the defending `Player`
+pub struct KingAttacks {
+    kings: [Option<Coord>; 2],
+    /// Bit y * 8 + x is set for squares around the king and two in front of it
+    zones: [u64; 2],
+    pub attackers: [u8; 2],
+    pub weight: [f32; 2],
+    piece_hits: u32
+}
+
+impl KingAttacks {
+
+    pub fn new(board: &Board) -> Self {
+        let mut kings = [None; 2];
+        let mut zones = [0; 2];
+        for player in [Player::White, Player::Black].iter() {
+            let king = board.get_player_state(*player).piece_locs.iter()
+                .find(|Coord(x, y)| *board.get_by_xy(*x, *y) == Square::Occupied(Piece::King, *player))
+                .cloned();
+            if let Some(Coord(kx, ky)) = king {
+                zones[*player as usize] = get_king_zone(*player, kx as i8, ky as i8);
+            }
+            kings[*player as usize] = king;
+        }
+
+        Self { kings, zones, attackers: [0; 2], weight: [0.; 2], piece_hits: 0 }
+    }
+
+    /// For each square controlled by the piece currently being filled
+    #[inline]
+    pub fn on_control(&mut self, attacker: Player, x: u8, y: u8) {
+        if self.zones[attacker.get_other_player() as usize] & (1 << (y as u64 * 8 + x as u64)) != 0 {
+            self.piece_hits += 1;
+        }
+    }
+
+    /// After all squares of one piece have been controlled
+    pub fn end_piece(&mut self, params: &EvalParams, attacker: Player, piece: Piece) {
+        let piece_weight = params.get_king_attack_weight(piece);
+        if self.piece_hits > 0 && piece_weight > 0. {
+            let defender = attacker.get_other_player() as usize;
+            self.attackers[defender] += 1;
+            self.weight[defender] += piece_weight * self.piece_hits as f32;
+        }
+        self.piece_hits = 0;
+    }
+}
+
+fn get_king_zone(player: Player, kx: i8, ky: i8) -> u64 {
+    let forward: i8 = if player == Player::White { -1 } else { 1 };
+    let mut zone = 0u64;
+    for x in kx - 1..=kx + 1 {
+        for y in [ky - 1, ky, ky + 1, ky + 2 * forward].iter() {
+            if x >= 0 && x <= 7 && *y >= 0 && *y <= 7 {
+                zone |= 1 << (*y as u64 * 8 + x as u64);
+            }
+        }
+    }
+    zone
+}
+
+/// Middlegame king safety from each player's point of view, indexed by `Player`
+pub fn evaluate_king_safety(params: &EvalParams, files: &[PawnFiles; 2], attacks: &KingAttacks) -> [f32; 2] {
+    let mut evals = [0.; 2];
+    for player in [Player::White, Player::Black].iter() {
+        if let Some(Coord(kx, ky)) = attacks.kings[*player as usize] {
+            let own = &files[*player as usize];
+            let other = &files[player.get_other_player() as usize];
+            evals[*player as usize] = evaluate_shelter(params, *player, kx as i8, ky as i8, own, other) - evaluate_attacks(params, attacks, *player);
+        }
+    }
+    evals
+}
+
+fn evaluate_shelter(params: &EvalParams, player: Player, kx: i8, ky: i8, own: &PawnFiles, other: &PawnFiles) -> f32 {
+    let forward: i8 = if player == Player::White { -1 } else { 1 };
+    let mut value = 0.;
+
+    for x in kx - 1..=kx + 1 {
+        if x < 0 || x > 7 { continue; }
+
+        if has_pawn(own, x, ky + forward) {
+            value += params.shield_near;
+        } else if has_pawn(own, x, ky + 2 * forward) {
+            value += params.shield_far;
+        } else {
+            value += params.shield_missing;
+        }
+
+        if get_file(own, x) == 0 {
+            value += if get_file(other, x) == 0 { params.king_open_file } else { params.king_semi_open_file };
+        }
+
+        for distance in 1..=STORM_RANGE {
+            if has_pawn(other, x, ky + distance * forward) {
+                value += params.get_storm(distance as usize);
+                break;
+            }
+        }
+    }
+    value
+}
+
+/// A single attacker is not dangerous unless it is the queen
+fn evaluate_attacks(params: &EvalParams, attacks: &KingAttacks, player: Player) -> f32 {
+    let weight = attacks.weight[player as usize];
+    if attacks.attackers[player as usize] < 2 && weight < params.king_attack_queen * 2. {
+        return 0.;
+    }
+    (params.king_attack_scale * weight * weight).min(params.king_attack_max)
+}
+
+/// Most that king safety can add up to either way for one player, with a non-negative `king_attack_scale`
+pub fn get_max_king_safety(params: &EvalParams) -> f32 {
+    let shield = params.shield_near.abs().max(params.shield_far.abs()).max(params.shield_missing.abs());
+    let open_file = params.king_open_file.abs().max(params.king_semi_open_file.abs());
+    let storm = (1..=STORM_RANGE).map(|distance| params.get_storm(distance as usize).abs()).fold(0., f32::max);
+    3. * (shield + open_file + storm) + params.king_attack_max.abs()
+}
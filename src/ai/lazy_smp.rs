@@ -0,0 +1,261 @@
+//! Native only multithreaded search. Workers search the same root at varied depths,
+//! sharing results only through a lock-free memo, then vote on the best move.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use super::super::game::move_list::*;
+use super::super::game::board::*;
+use super::super::extern_funcs::now;
+use super::memo_table::*;
+use super::eval_params::*;
+use super::nnue::*;
+use super::dtm::*;
+#[cfg(feature = "syzygy")]
+use super::syzygy::*;
+use super::*;
+use crate::{console_log};
+
+const MEMO_TYPE_EMPTY: u64 = 0;
+const MEMO_TYPE_LOW: u64 = 1;
+const MEMO_TYPE_EXACT: u64 = 2;
+const MEMO_TYPE_HIGH: u64 = 3;
+
+/// (hash ^ data, data) so that a torn write between the two is detected as a miss
+struct SharedMemoEntry(AtomicU64, AtomicU64);
+
+/// Fixed size, always replacing memo which any number of threads can read and write without locks.
+/// Moves are stored packed by `MoveSnapshot::to_compact` and rebuilt from the board when read.
+#[derive(Clone)]
+pub struct SharedMemo {
+    entries: Arc<Vec<SharedMemoEntry>>,
+    mask: usize
+}
+
+impl SharedMemo {
+
+    pub fn new(size_log2: u8) -> Self {
+        let len = 1usize << size_log2;
+        let mut entries = Vec::with_capacity(len);
+        for _ in 0..len {
+            entries.push(SharedMemoEntry(AtomicU64::new(0), AtomicU64::new(0)));
+        }
+        Self {
+            entries: Arc::new(entries),
+            mask: len - 1
+        }
+    }
+
+    #[inline]
+    fn get_entry(&self, hash: u64) -> &SharedMemoEntry {
+        &self.entries[hash as usize & self.mask]
+    }
+
+    /// eval bits 0-31, depth 32-39, type 40-41, move 42-56
+    fn pack(data: &MemoData) -> u64 {
+        let MemoData(eval, depth, t) = data;
+        let (type_bits, compact) = match t {
+            MemoType::Low => (MEMO_TYPE_LOW, 0),
+            MemoType::Exact(m) => (MEMO_TYPE_EXACT, m.to_compact()),
+            MemoType::High(m) => (MEMO_TYPE_HIGH, m.to_compact())
+        };
+        eval.to_bits() as u64 | ((*depth as u64) << 32) | (type_bits << 40) | ((compact as u64) << 42)
+    }
+
+    fn unpack(packed: u64, board: &Board) -> Option<MemoData> {
+        let eval = f32::from_bits(packed as u32);
+        let depth = (packed >> 32) as u8;
+        let compact = (packed >> 42) as u16;
+        let t = match (packed >> 40) & 3 {
+            MEMO_TYPE_LOW => MemoType::Low,
+            MEMO_TYPE_EXACT => MemoType::Exact(board.get_move_from_compact(compact)?),
+            MEMO_TYPE_HIGH => MemoType::High(board.get_move_from_compact(compact)?),
+            _ => return None
+        };
+        Some(MemoData(eval, depth, t))
+    }
+}
+
+impl MemoTable for SharedMemo {
+
+    fn get(&self, hash: u64, board: &Board) -> Option<MemoData> {
+        let entry = self.get_entry(hash);
+        let key = entry.0.load(Ordering::Relaxed);
+        let data = entry.1.load(Ordering::Relaxed);
+        if key ^ data != hash || (data >> 40) & 3 == MEMO_TYPE_EMPTY {
+            return None;
+        }
+        Self::unpack(data, board)
+    }
+
+    fn insert(&mut self, hash: u64, data: MemoData) {
+        let entry = self.get_entry(hash);
+        let packed = Self::pack(&data);
+        entry.0.store(hash ^ packed, Ordering::Relaxed);
+        entry.1.store(packed, Ordering::Relaxed);
+    }
+
+    fn remove(&mut self, hash: u64) {
+        let entry = self.get_entry(hash);
+        if entry.0.load(Ordering::Relaxed) ^ entry.1.load(Ordering::Relaxed) == hash {
+            entry.0.store(0, Ordering::Relaxed);
+            entry.1.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn clear(&mut self) {
+        for entry in self.entries.iter() {
+            entry.0.store(0, Ordering::Relaxed);
+            entry.1.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.iter().filter(|entry| (entry.1.load(Ordering::Relaxed) >> 40) & 3 != MEMO_TYPE_EMPTY).count()
+    }
+}
+
+pub struct LazySmp {
+    threads: usize,
+    memo: SharedMemo,
+    stop: Arc<AtomicBool>,
+    eval_params: EvalParams,
+    nnue: Option<Arc<Network>>,
+    dtm_tables: Option<Arc<DtmTables>>,
+    #[cfg(feature = "syzygy")]
+    tablebases: Option<Arc<Tablebases>>
+}
+
+impl LazySmp {
+
+    /// The memo has `2 ^ memo_size_log2` entries of 16 bytes
+    pub fn new(threads: usize, memo_size_log2: u8) -> Self {
+        Self {
+            threads: threads.max(1),
+            memo: SharedMemo::new(memo_size_log2),
+            stop: Arc::new(AtomicBool::new(false)),
+            eval_params: EvalParams::default(),
+            nnue: None,
+            dtm_tables: None,
+            #[cfg(feature = "syzygy")]
+            tablebases: None
+        }
+    }
+
+    /// Applies from the next `search`
+    pub fn set_eval_params(&mut self, params: EvalParams) {
+        self.eval_params = params;
+    }
+
+    /// Applies from the next `search`, each worker evaluates with its own `NnueEvaluator` for the network
+    pub fn set_nnue(&mut self, net: Option<Arc<Network>>) {
+        self.nnue = net;
+    }
+
+    /// Applies from the next `search`
+    pub fn set_dtm_tables(&mut self, dtm_tables: Option<Arc<DtmTables>>) {
+        self.dtm_tables = dtm_tables;
+    }
+
+    /// Applies from the next `search`, which then takes root moves from the tablebases when it can
+    #[cfg(feature = "syzygy")]
+    pub fn set_tablebases(&mut self, tablebases: Option<Arc<Tablebases>>) {
+        self.tablebases = tablebases;
+    }
+
+    /// Setting the returned flag stops the current search, which then returns the votes of the finished iterations.
+    /// It keeps future searches from running until it is cleared, and is left set when a search finishes.
+    pub fn get_stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn set_stop_handle(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    /// The first worker searches to `depth`, every other helper searches the even depths up to one deeper.
+    /// When the first worker finishes, the helpers are stopped. Returns the voted move with its eval.
+    /// Clear the stop flag first, see `get_stop_handle`.
+    pub fn search(&mut self, depth: u8, board: &Board) -> Option<(MoveSnapshot, f32)> {
+        self.memo.clear();
+
+        #[cfg(feature = "syzygy")]
+        {
+            if let Some(tablebases) = self.tablebases.as_ref() {
+                let mut board = board.clone();
+                if let Some(best) = tablebases.probe_root(&mut board).and_then(|root_moves| root_moves.into_iter().next()) {
+                    return Some((best.m, best.wdl.to_eval()));
+                }
+            }
+        }
+
+        let start_ms = now();
+        let results: Vec<(Option<(MoveSnapshot, f32, u8)>, u32)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.threads).map(|i| {
+                let memo = self.memo.clone();
+                let stop = self.stop.clone();
+                let eval_params = self.eval_params.clone();
+                let nnue = self.nnue.clone();
+                let dtm_tables = self.dtm_tables.clone();
+                #[cfg(feature = "syzygy")]
+                let tablebases = self.tablebases.clone();
+                scope.spawn(move || {
+                    let offset = (i % 2) as u8;
+                    let mut ai = Ai::with_memo(memo);
+                    ai.set_stop_handle(stop.clone());
+                    ai.set_eval_params(eval_params);
+                    if let Some(net) = nnue {
+                        ai.set_evaluator(Some(Box::new(NnueEvaluator::new(net))));
+                    }
+                    ai.set_dtm_tables(dtm_tables);
+                    #[cfg(feature = "syzygy")]
+                    ai.set_tablebases(tablebases);
+                    ai.set_test_board(board);
+
+                    let result = ai.iterative_deepening(1 + offset, depth + offset);
+                    if i == 0 {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    (result, ai.node_counter)
+                })
+            }).collect();
+            handles.into_iter().map(|h| h.join().unwrap_or((None, 0))).collect()
+        });
+
+        let nodes: u32 = results.iter().map(|(_, n)| *n).sum();
+        console_log!("Lazy SMP - {} threads, NPS - {}", self.threads, (nodes as f64 / ((now() - start_ms) as f64 / 1000.)).round());
+
+        // Moves read back from the shared memo are only pseudo-legal, so a root move has to be checked before it is played
+        let mut board = board.clone();
+        let mut temp = MoveList::new(50);
+        let mut legal_moves = MoveList::new(50);
+        board.get_moves(&mut temp, &mut legal_moves);
+        let legal_moves: Vec<u16> = legal_moves.get_v()[..legal_moves.write_index].iter().map(|m| m.to_compact()).collect();
+
+        Self::vote(results.into_iter().filter_map(|(r, _)| r).filter(|(m, _, _)| legal_moves.contains(&m.to_compact())).collect())
+    }
+
+    /// Each finished result votes for its move, weighted by how much better its eval is than the worst eval and by depth
+    fn vote(results: Vec<(MoveSnapshot, f32, u8)>) -> Option<(MoveSnapshot, f32)> {
+        let min_eval = results.iter().map(|r| r.1).fold(f32::INFINITY, f32::min);
+
+        let mut votes: HashMap<u16, f32> = HashMap::new();
+        for (m, eval, depth) in results.iter() {
+            *votes.entry(m.to_compact()).or_insert(0.) += (eval - min_eval + 1.) * *depth as f32;
+        }
+
+        let mut best: Option<&(MoveSnapshot, f32, u8)> = None;
+        for r in results.iter() {
+            console_log!("Vote {} depth {} - {}", r.0, r.2, votes[&r.0.to_compact()]);
+            best = match best {
+                Some(b) => {
+                    let (r_votes, b_votes) = (votes[&r.0.to_compact()], votes[&b.0.to_compact()]);
+                    if r_votes > b_votes || (r_votes == b_votes && r.2 > b.2) { Some(r) } else { Some(b) }
+                },
+                None => Some(r)
+            };
+        }
+        best.map(|(m, eval, _)| (m.clone(), *eval))
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use super::super::game::move_list::*;
+use super::super::game::board::*;
+
+#[derive(Clone)]
+pub enum MemoType { Low, Exact(MoveSnapshot), High(MoveSnapshot) }
+
+/// (eval, remaining depth, type)
+#[derive(Clone)]
+pub struct MemoData(pub f32, pub u8, pub MemoType);
+
+/// Storage for search results by board hash, so that search does not care whether it owns the table or shares it
+pub trait MemoTable {
+    /// `board` must be the board whose hash is `hash`, for tables which need it to rebuild moves
+    fn get(&self, hash: u64, board: &Board) -> Option<MemoData>;
+    fn insert(&mut self, hash: u64, data: MemoData);
+    fn remove(&mut self, hash: u64);
+    fn clear(&mut self);
+    fn len(&self) -> usize;
+}
+
+impl MemoTable for HashMap<u64, MemoData> {
+
+    #[inline]
+    fn get(&self, hash: u64, _: &Board) -> Option<MemoData> {
+        HashMap::get(self, &hash).cloned()
+    }
+
+    #[inline]
+    fn insert(&mut self, hash: u64, data: MemoData) {
+        HashMap::insert(self, hash, data);
+    }
+
+    fn remove(&mut self, hash: u64) {
+        HashMap::remove(self, &hash);
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
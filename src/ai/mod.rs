@@ -1,59 +1,272 @@
 mod evaluation;
+mod pst;
+mod pawns;
+mod king_safety;
+mod pieces;
+pub mod memo_table;
+pub mod eval_params;
+pub mod eval_trace;
+pub mod incremental;
+pub mod endgame;
+pub mod dtm;
+pub mod book;
+pub mod evaluator;
+pub mod nnue;
+pub mod skill;
+pub mod game_analysis;
+pub mod puzzles;
+#[cfg(all(feature = "lazy_smp", not(target_arch = "wasm32")))]
+pub mod lazy_smp;
+#[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+pub mod syzygy;
 
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use super::game::move_list::*;
 use super::game::board::*;
 use super::game::entities::*;
 use super::game::check_handler::*;
 use super::extern_funcs::now;
 use crate::{console_log};
+use memo_table::*;
+use eval_params::*;
+use evaluator::*;
 
-pub struct Ai {
+pub use evaluation::StaticEvaluator;
+
+pub struct Ai<M: MemoTable = HashMap<u64, MemoData>> {
     moves_buf: MoveList,
     test_board: Board,
     temp_moves: MoveList,
-    eval_temp_arr: [f32; 64],
-    memo: HashMap<u64, MemoData>,
+    /// Hand written evaluation, which is searched with unless `evaluator` is set
+    static_evaluator: StaticEvaluator,
+    evaluator: Option<Box<dyn Evaluator + Send>>,
+    memo: M,
     q_memo: HashMap<u64, MemoData>,
+    /// Set by `keep_memo_once` to skip clearing the memo at the next `end_search`
+    keep_memo: bool,
     memo_hits: usize,
     fast_found_hits: usize,
+    aspiration_researches: usize,
+    /// Leaves decided by the incremental base score alone
+    lazy_exits: usize,
     show_tree_left_side: bool,
-    node_counter: u32
+    node_counter: u32,
+    /// Distance from the root of the position on `test_board`
+    ply: u8,
+    /// Hashes of the positions after root moves which earlier MultiPV lines already reported
+    excluded_root_moves: Vec<u64>,
+    /// Best root move and eval of this searcher, which may differ from the memo when the memo is shared
+    root_best: Option<(MoveSnapshot, f32)>,
+    /// When set, search unwinds as soon as possible and discards the unfinished iteration
+    stop: Arc<AtomicBool>,
+    search_start_ms: u32,
+    /// Nodes per root search, ie. per iteration set or per MultiPV line, before stopping
+    node_limit: Option<u32>,
+    node_limit_base: u32,
+    /// Exact evals from generated depth to mate tables, probed before anything else below the root
+    dtm_tables: Option<Arc<dtm::DtmTables>>,
+    dtm_hits: usize,
+    /// Probed at the root and for WDL cutoffs once few enough pieces are left
+    #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+    tablebases: Option<Arc<syzygy::Tablebases>>,
+    #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+    tb_hits: usize
 }
 
-enum SingleMoveResult { NewAlpha(f32), BetaCutOff(f32), NoEffect }
-
+/// Progress after one iteration of `search_depth`, statistics are totals since `begin_search`.
+/// `eval` is from the point of view of the player with the turn.
 #[derive(Clone)]
-enum MemoType { Low, Exact(MoveSnapshot), High(MoveSnapshot) }
+pub struct SearchInfo {
+    pub depth: u8,
+    pub eval: f32,
+    pub nodes: u32,
+    pub memo_hits: usize,
+    pub fast_found_hits: usize,
+    pub aspiration_researches: usize,
+    pub elapsed_ms: u32,
+    pub pv: Vec<MoveSnapshot>
+}
 
-#[derive(Clone)]
-struct MemoData(f32, u8, MemoType);
+/// One MultiPV result, `eval` is from the point of view of the player with the turn at the root
+pub struct PvLine {
+    pub eval: f32,
+    /// Deepest finished iteration
+    pub depth: u8,
+    pub moves: Vec<MoveSnapshot>
+}
+
+enum SingleMoveResult { NewAlpha(f32), BetaCutOff(f32), NoEffect }
 
+/// Mates are this less the plies from the root to mate, so that shorter mates are preferred
 static MAX_EVAL: f32 = 9000.;
+/// Evals within this many plies of `MAX_EVAL` are mates
+const MAX_MATE_PLIES: f32 = 256.;
 
-impl Ai {
+/// 16k entries
+const PAWN_TABLE_SIZE_LOG2: u8 = 14;
+
+/// Half width of the first aspiration window around the previous iteration's score
+static ASPIRATION_WINDOW: f32 = 0.5;
 
+/// Plies to mate of a mate eval, positive if the player it is for mates and negative if they are mated
+pub fn get_mate_plies(eval: f32) -> Option<i32> {
+    if eval.abs() <= MAX_EVAL - MAX_MATE_PLIES {
+        return None;
+    }
+    let plies = (MAX_EVAL - eval.abs()).round() as i32;
+    Some(if eval > 0. { plies } else { -plies })
+}
+
+/// Mate evals count plies from the root while searching but from the position itself in the memo,
+/// so that they still hold when the position is reached at another ply
+fn to_memo_eval(eval: f32, ply: u8) -> f32 {
+    if eval > MAX_EVAL - MAX_MATE_PLIES {
+        eval + ply as f32
+    } else if eval < -MAX_EVAL + MAX_MATE_PLIES {
+        eval - ply as f32
+    } else {
+        eval
+    }
+}
+
+fn from_memo_eval(eval: f32, ply: u8) -> f32 {
+    if eval > MAX_EVAL - MAX_MATE_PLIES {
+        eval - ply as f32
+    } else if eval < -MAX_EVAL + MAX_MATE_PLIES {
+        eval + ply as f32
+    } else {
+        eval
+    }
+}
+
+impl Ai {
     pub fn new() -> Self {
+        Self::with_memo(HashMap::new())
+    }
+}
+
+impl <M: MemoTable + 'static> Ai<M> {
+
+    pub fn with_memo(memo: M) -> Self {
         console_log!("AI init");
         Self {
             moves_buf: MoveList::new(1000),
             test_board: Board::new(),
             temp_moves: MoveList::new(50),
-            eval_temp_arr: [0.; 64],
-            memo: HashMap::new(),
+            static_evaluator: StaticEvaluator::with_pawn_table(EvalParams::default(), PAWN_TABLE_SIZE_LOG2),
+            evaluator: None,
+            memo,
             q_memo: HashMap::new(),
             memo_hits: 0,
             fast_found_hits: 0,
+            aspiration_researches: 0,
+            lazy_exits: 0,
+            keep_memo: false,
             show_tree_left_side: false,
-            node_counter: 0
+            node_counter: 0,
+            ply: 0,
+            excluded_root_moves: Vec::new(),
+            root_best: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            search_start_ms: 0,
+            node_limit: None,
+            node_limit_base: 0,
+            dtm_tables: None,
+            dtm_hits: 0,
+            #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+            tablebases: None,
+            #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+            tb_hits: 0
         }
     }
 
-    fn get_leading_move(&self) -> Option<(&MoveSnapshot, f32)> {
-        match self.memo.get(&self.test_board.get_hash()) {
+    /// Setting the returned flag stops the current search, and keeps future searches from running until it is cleared
+    pub fn get_stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn set_stop_handle(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    pub fn set_node_limit(&mut self, node_limit: Option<u32>) {
+        self.node_limit = node_limit;
+    }
+
+    /// Params of the hand written evaluation, which are kept while another evaluator is set
+    pub fn get_eval_params(&self) -> &EvalParams {
+        &self.static_evaluator.params
+    }
+
+    pub fn set_eval_params(&mut self, params: EvalParams) {
+        self.static_evaluator.params = params;
+        self.static_evaluator.clear_cache();
+    }
+
+    /// Applies from the next search, `None` goes back to the hand written evaluation
+    pub fn set_evaluator(&mut self, evaluator: Option<Box<dyn Evaluator + Send>>) {
+        self.evaluator = evaluator;
+    }
+
+    /// Keeps the memo of the next search for the search after it, eg. a hint followed by the move it hinted at.
+    /// The search after clears it as usual.
+    pub fn keep_memo_once(&mut self) {
+        self.keep_memo = true;
+    }
+
+    pub fn has_evaluator(&self) -> bool {
+        self.evaluator.is_some()
+    }
+
+    /// Applies from the next search, `None` stops probing
+    pub fn set_dtm_tables(&mut self, dtm_tables: Option<Arc<dtm::DtmTables>>) {
+        self.dtm_tables = dtm_tables;
+    }
+
+    /// Applies from the next search, `None` stops probing
+    #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+    pub fn set_tablebases(&mut self, tablebases: Option<Arc<syzygy::Tablebases>>) {
+        self.tablebases = tablebases;
+    }
+
+    /// Every term of the hand written evaluation of `board`
+    pub fn trace_eval(&mut self, board: &Board) -> eval_trace::EvalTrace {
+        self.static_evaluator.trace(board)
+    }
+
+    /// Split from `self` so that the search board can be borrowed alongside
+    #[inline]
+    fn get_evaluator<'a>(static_evaluator: &'a mut StaticEvaluator, evaluator: &'a mut Option<Box<dyn Evaluator + Send>>) -> &'a mut dyn Evaluator {
+        match evaluator {
+            Some(evaluator) => evaluator.as_mut(),
+            None => static_evaluator
+        }
+    }
+
+    fn set_test_board(&mut self, board: &Board) {
+        self.test_board.clone_from(board);
+        Self::get_evaluator(&mut self.static_evaluator, &mut self.evaluator).prepare(&mut self.test_board);
+    }
+
+    /// Every move on the search board goes through here for `Evaluator::on_move`
+    #[inline]
+    fn handle_test_move(&mut self, m: &MoveSnapshot, apply_or_undo: bool) {
+        self.test_board.handle_move(m, apply_or_undo);
+        Self::get_evaluator(&mut self.static_evaluator, &mut self.evaluator).on_move(&self.test_board, m, apply_or_undo);
+    }
+
+    #[inline]
+    fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) || self.node_limit.map_or(false, |limit| self.node_counter - self.node_limit_base >= limit)
+    }
+
+    fn get_leading_move(&self) -> Option<(MoveSnapshot, f32)> {
+        match self.memo.get(self.test_board.get_hash(), &self.test_board) {
             // In this context, fail high means checkmate
             Some(MemoData(eval, _, MemoType::High(best_move) | MemoType::Exact(best_move))) => {
-                Some((best_move, *eval))
+                Some((best_move, eval))
             },
             _ => {
                 None
@@ -62,15 +275,172 @@ impl Ai {
     }
 
     pub fn make_move(&mut self, depth: u8, real_board: &mut Board) {
+        let lines = self.search(depth, real_board, 1);
+        if let Some(m) = lines.first().and_then(|line| line.moves.first()) {
+            console_log!("Making move: {} ({})", m, lines[0].eval);
+            real_board.handle_move(m, true);
+        } else {
+            console_log!("No move");
+        }
+    }
+
+    /// MultiPV search. Finds up to `multi_pv` lines, best first, where each line is searched
+    /// again from scratch at the root with the first moves of all previous lines excluded.
+    pub fn search(&mut self, depth: u8, real_board: &Board, multi_pv: usize) -> Vec<PvLine> {
+
+        self.begin_search(real_board);
+
+        #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+        {
+            if let Some(lines) = self.probe_root_tablebases(depth, multi_pv) {
+                self.end_search();
+                return lines;
+            }
+        }
+
+        let mut lines: Vec<PvLine> = Vec::with_capacity(multi_pv);
+        for pv_i in 0..multi_pv {
+            if let Some(prev_move) = lines.last().map(|line| line.moves[0].clone()) {
+                console_log!("\nMultiPV line {}", pv_i + 1);
+                self.begin_next_line(&prev_move);
+            }
+
+            // Running out of the line's own node limit still leaves the iterations it finished
+            let (best_move, eval, completed_depth) = match self.iterative_deepening(1, depth) {
+                Some(completed) => completed,
+                None => break
+            };
+
+            // An iteration stopped while re-searching leaves a root entry without a move, so the line may be just the move
+            let mut moves = self.get_pv(completed_depth as usize);
+            if moves.first().map_or(true, |m| m.to_long_algebraic() != best_move.to_long_algebraic()) {
+                moves = vec![best_move];
+            }
+            lines.push(PvLine { eval, depth: completed_depth, moves });
+            if self.stop.load(Ordering::Relaxed) { break; }
+        }
+
+        self.end_search();
+        lines
+    }
+
+    /// Root moves ranked by the tablebases instead of searched, each line being just the move
+    #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+    fn probe_root_tablebases(&mut self, depth: u8, multi_pv: usize) -> Option<Vec<PvLine>> {
+        let root_moves = self.tablebases.as_ref()?.probe_root(&mut self.test_board)?;
+        if let Some(best) = root_moves.first() {
+            console_log!("Tablebase root - {} is a {}, DTZ {}", best.m, best.wdl, best.dtz);
+        }
+        Some(root_moves.into_iter().take(multi_pv).map(|r| PvLine {
+            eval: r.wdl.to_eval(),
+            depth,
+            moves: vec![r.m]
+        }).collect())
+    }
+
+    /// From the point of view of the player with the turn, `None` if the tables don't cover the position
+    #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+    fn probe_wdl_tablebases(&mut self) -> Option<f32> {
+        let tablebases = self.tablebases.as_ref()?;
+        if !tablebases.can_probe(&self.test_board) {
+            return None;
+        }
+        let wdl = tablebases.probe_wdl(&mut self.test_board)?;
+        self.tb_hits += 1;
+        Some(wdl.to_eval())
+    }
+
+    /// Begins a search done one iteration at a time, for callers which must yield in between,
+    /// eg. a Web Worker posting progress. Follow with `search_depth` calls, then `end_search`.
+    pub fn begin_search(&mut self, real_board: &Board) {
+        self.set_test_board(real_board);
+
+        // A root entry kept from the last search would be a memo hit before any root move is searched,
+        // so only its move is kept, to be searched first
+        let root_hash = self.test_board.get_hash();
+        if let Some(MemoData(eval, _, t)) = self.memo.get(root_hash, &self.test_board) {
+            self.memo.insert(root_hash, MemoData(eval, 0, t));
+        }
+        self.search_start_ms = now();
+        self.node_limit_base = self.node_counter;
+    }
+
+    /// Starts another MultiPV line of the search from `begin_search`, with `prev_move`, the first move of the previous
+    /// line, excluded at the root as well as those of earlier lines. The node limit applies to each line on its own.
+    pub fn begin_next_line(&mut self, prev_move: &MoveSnapshot) {
+        self.handle_test_move(prev_move, true);
+        self.excluded_root_moves.push(self.test_board.get_hash());
+        self.handle_test_move(prev_move, false);
+
+        // Root entry was scored with a different set of excluded moves
+        self.memo.remove(self.test_board.get_hash());
+        self.node_limit_base = self.node_counter;
+    }
+
+    /// Searches the board from `begin_search` to `depth`, with an aspiration window around `prev_eval` if given.
+    /// Returns `None` if stopped or if there are no moves.
+    pub fn search_depth(&mut self, depth: u8, prev_eval: Option<f32>) -> Option<SearchInfo> {
+        console_log!("\nBegin depth {}", depth);
+        self.aspiration_search(depth, prev_eval)?;
+        let (_, eval) = self.get_leading_move()?;
+        Some(SearchInfo {
+            depth,
+            eval,
+            nodes: self.node_counter,
+            memo_hits: self.memo_hits,
+            fast_found_hits: self.fast_found_hits,
+            aspiration_researches: self.aspiration_researches,
+            elapsed_ms: now() - self.search_start_ms,
+            pv: self.get_pv(depth as usize)
+        })
+    }
+
+    /// Logs statistics and clears all per search state
+    pub fn end_search(&mut self) {
+        let c_hash = self.test_board.calculate_hash();
+        debug_assert_eq!(c_hash, self.test_board.get_hash());
+        debug_assert_eq!(self.test_board.calculate_pawn_hash(), self.test_board.get_pawn_hash());
+        debug_assert!(self.test_board.get_nnue().map_or(true, |a| *a == nnue::Accumulator::new(a.get_network().clone(), &self.test_board)));
 
-        self.test_board.clone_from(real_board);
+        console_log!("Memo hits - {}, size - {} / q - {}, fast found - {}, re-searches - {}, pawn hits - {}, lazy exits - {}, dtm hits - {}", self.memo_hits, self.memo.len(), self.q_memo.len(), self.fast_found_hits, self.aspiration_researches, self.static_evaluator.take_pawn_hits(), self.lazy_exits, self.dtm_hits);
+        console_log!("NPS - {}", (self.node_counter as f64 / ((now() - self.search_start_ms) as f64 / 1000.)).round());
 
-        let start_ms = now();
-        for d in (1..=depth).step_by(2) {
+        self.node_counter = 0;
+        self.memo_hits = 0;
+        self.fast_found_hits = 0;
+        self.aspiration_researches = 0;
+        self.lazy_exits = 0;
+        self.dtm_hits = 0;
+        #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+        {
+            console_log!("Tablebase hits - {}", self.tb_hits);
+            self.tb_hits = 0;
+        }
+        self.excluded_root_moves.clear();
+        if self.keep_memo {
+            self.keep_memo = false;
+        } else {
+            self.memo.clear();
+        }
+        self.q_memo.clear();
+    }
+
+    /// Returns the root move, eval and depth of the last iteration which was not stopped
+    fn iterative_deepening(&mut self, start_depth: u8, depth: u8) -> Option<(MoveSnapshot, f32, u8)> {
+        let mut prev_eval: Option<f32> = None;
+        let mut completed: Option<(MoveSnapshot, f32, u8)> = None;
+        for d in (start_depth..=depth).step_by(2) {
             console_log!("\nBegin depth {}", d);
-            self.show_tree_left_side = true;
-            unsafe {
-                self.negamax(d, false, -MAX_EVAL, MAX_EVAL, 0);
+            self.root_best = None;
+            prev_eval = match self.aspiration_search(d, prev_eval) {
+                Some(e) => Some(e),
+                None => {
+                    console_log!("Stopped");
+                    break;
+                }
+            };
+            if let Some((m, e)) = self.root_best.take() {
+                completed = Some((m, e, d));
             }
 
             let leading_move = self.get_leading_move();
@@ -80,25 +450,72 @@ impl Ai {
                 console_log!("No leading move");
             }
         }
+        completed
+    }
 
-        let c_hash = self.test_board.calculate_hash();
-        debug_assert_eq!(c_hash, self.test_board.get_hash());
+    /// Follows memoized best moves from the current test board, at most `max_len` of them
+    fn get_pv(&mut self, max_len: usize) -> Vec<MoveSnapshot> {
+        let mut pv: Vec<MoveSnapshot> = Vec::new();
+        let mut visited: Vec<u64> = Vec::new();
 
-        let leading_move = self.get_leading_move();
-        if let Some((m, e)) = leading_move {
-            console_log!("Making move: {} ({})", m, e);
-            real_board.handle_move(m, true);
-        } else {
-            console_log!("No move");
+        while pv.len() < max_len {
+            let hash = self.test_board.get_hash();
+            if visited.contains(&hash) { break; }
+            visited.push(hash);
+
+            let m = match self.memo.get(hash, &self.test_board) {
+                Some(MemoData(_, _, MemoType::High(m) | MemoType::Exact(m))) => m,
+                _ => break
+            };
+            self.handle_test_move(&m, true);
+            pv.push(m);
         }
-        console_log!("Memo hits - {}, size - {} / q - {}, fast found - {}", self.memo_hits, self.memo.len(), self.q_memo.len(), self.fast_found_hits);
-        console_log!("NPS - {}", (self.node_counter as f64 / ((now() - start_ms) as f64 / 1000.)).round());
 
-        self.node_counter = 0;
-        self.memo_hits = 0;
-        self.fast_found_hits = 0;
-        self.memo.clear();
-        self.q_memo.clear();
+        for m in pv.iter().rev() {
+            self.handle_test_move(m, false);
+        }
+        pv
+    }
+
+    /// Searches the root with a window centred on `prev_eval`, widening it and re-searching on fail low or fail high.
+    /// Each attempt logs its own share of the search statistics, so the cost of re-searches can be compared.
+    /// Returns `None` if stopped.
+    fn aspiration_search(&mut self, depth: u8, prev_eval: Option<f32>) -> Option<f32> {
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = match prev_eval {
+            Some(e) => ((e - delta).max(-MAX_EVAL), (e + delta).min(MAX_EVAL)),
+            None => (-MAX_EVAL, MAX_EVAL)
+        };
+
+        loop {
+            let (nodes_before, memo_hits_before, fast_found_before) = (self.node_counter, self.memo_hits, self.fast_found_hits);
+
+            self.show_tree_left_side = true;
+            let r = unsafe {
+                self.negamax(depth, false, alpha, beta, 0)
+            };
+            if self.is_stopped() { return None; }
+
+            console_log!(
+                "Window [{}, {}] = {} - nodes {}, memo hits {}, fast found {}",
+                alpha, beta, r,
+                self.node_counter - nodes_before,
+                self.memo_hits - memo_hits_before,
+                self.fast_found_hits - fast_found_before
+            );
+
+            // Fail hard, so a result on the window edge means the true score may lie beyond it
+            if r <= alpha && alpha > -MAX_EVAL {
+                delta *= 4.;
+                alpha = (r - delta).max(-MAX_EVAL);
+            } else if r >= beta && beta < MAX_EVAL {
+                delta *= 4.;
+                beta = (r + delta).min(MAX_EVAL);
+            } else {
+                return Some(r);
+            }
+            self.aspiration_researches += 1;
+        }
     }
 
     /// Will assume ownership over all move list elements from `moves_start`
@@ -112,15 +529,46 @@ impl Ai {
         moves_start: usize
     ) -> f32 {
         self.node_counter += 1;
+        if self.is_stopped() { return alpha; }
+
+        if !quiescence && self.ply > 0 {
+            if let Some(dtm) = self.dtm_tables.as_ref().and_then(|tables| tables.probe(&self.test_board)) {
+                self.dtm_hits += 1;
+                self.show_tree_left_side = false;
+                return Self::cap(dtm.to_eval(), alpha, beta);
+            }
+        }
+
+        #[cfg(all(feature = "syzygy", not(target_arch = "wasm32")))]
+        {
+            if !quiescence && self.ply > 0 {
+                if let Some(eval) = self.probe_wdl_tablebases() {
+                    self.show_tree_left_side = false;
+                    return Self::cap(eval, alpha, beta);
+                }
+            }
+        }
 
         if remaining_depth <= 0 {
+            let multiplier = self.test_board.get_player_with_turn().get_multiplier();
+            let evaluator = Self::get_evaluator(&mut self.static_evaluator, &mut self.evaluator);
+            if let Some((base_eval, margin)) = evaluator.get_lazy_eval(&self.test_board) {
+                let base_eval = multiplier * base_eval;
+                if base_eval - margin >= beta {
+                    self.lazy_exits += 1;
+                    return beta;
+                }
+                if quiescence && base_eval + margin <= alpha {
+                    self.lazy_exits += 1;
+                    return alpha;
+                }
+            }
+
+            let eval = multiplier * evaluator.evaluate(&self.test_board);
             if quiescence {
                 self.show_tree_left_side = false;
-                let eval = evaluation::evaluate(&self.test_board, &mut self.eval_temp_arr);
-                return Self::cap(self.test_board.get_player_with_turn().get_multiplier() * eval, alpha, beta);
+                return Self::cap(eval, alpha, beta);
             } else {
-                let mut eval = evaluation::evaluate(&self.test_board, &mut self.eval_temp_arr);
-                eval = self.test_board.get_player_with_turn().get_multiplier() * eval;
 
                 // Typical quiescence pruning (TODO review)
                 if eval >= beta { return beta; }
@@ -130,7 +578,7 @@ impl Ai {
             }
         }
 
-        let resolved_memo: *mut HashMap<u64, MemoData> = if quiescence { &mut self.q_memo } else { &mut self.memo };
+        let resolved_memo: *mut dyn MemoTable = if quiescence { &mut self.q_memo } else { &mut self.memo };
 
         const NEW_ALPHA_I_NEVER_SET: i32 = -1;
         const NEW_ALPHA_I_HASH_MOVE: i32 = -2;
@@ -139,16 +587,13 @@ impl Ai {
         let mut hash_move: Option<MoveSnapshot> = None;
 
         {
-            let memo = {
-                let _memo: Option<&MemoData> = (*resolved_memo).get(&self.test_board.get_hash());
-                _memo.map(|x| x.clone())
-            };
+            let memo = (*resolved_memo).get(self.test_board.get_hash(), &self.test_board);
 
             if let Some(MemoData(saved_num, saved_depth, t)) = memo {
 
                 // Using the memo, try to completely avoid any computation for this call
                 if saved_depth >= remaining_depth {
-                    let r = saved_num;
+                    let r = from_memo_eval(saved_num, self.ply);
                     match t {
                         MemoType::Low => {
                             if r <= alpha {
@@ -205,13 +650,11 @@ impl Ai {
                             &m,
                             moves_start
                         );
+                        if self.is_stopped() { return alpha; }
 
                         match r {
                             SingleMoveResult::BetaCutOff(max_this) => {
-                                (*resolved_memo).insert(
-                                    self.test_board.get_hash(),
-                                    MemoData(max_this, remaining_depth, MemoType::High(m))
-                                );
+                                self.memoize(resolved_memo, MemoData(max_this, remaining_depth, MemoType::High(m)));
                                 self.show_tree_left_side = false;
                                 return beta;
                             },
@@ -236,22 +679,22 @@ impl Ai {
         self.test_board.get_moves(&mut self.temp_moves, &mut self.moves_buf);
         let moves_end_exclusive = self.moves_buf.write_index;
 
-        // Order by memoized evaluations, then by aggression heuristic
+        // Order by memoized evaluations, then by the evaluator's hints
         for i in moves_start..moves_end_exclusive {
 
-            let m = self.moves_buf.get_mutable_snapshot(i);
-            self.test_board.handle_move(&m, true);
-            let memo: Option<&MemoData> = (*resolved_memo).get(&self.test_board.get_hash());
+            let m: *mut MoveSnapshot = self.moves_buf.get_mutable_snapshot(i);
+            self.handle_test_move(&*m, true);
+            let memo = (*resolved_memo).get(self.test_board.get_hash(), &self.test_board);
 
             const BIG_NUMBER: f32 = 100.;
             const EVAL_UPPER_BOUND: f32 = 999.;
             let r = if let Some(MemoData(opponent_max_this, _, MemoType::Exact(_))) = memo {
-                -*opponent_max_this * BIG_NUMBER
+                -opponent_max_this * BIG_NUMBER
             } else {
                 -EVAL_UPPER_BOUND * BIG_NUMBER
             };
 
-            self.test_board.handle_move(&m, false);
+            self.handle_test_move(&*m, false);
             (*m).1 = r;
         }
 
@@ -261,7 +704,8 @@ impl Ai {
                 self.show_tree_left_side = false;
                 return self.get_no_moves_eval(alpha, beta);
             }
-            evaluation::add_aggression_to_evals(&self.test_board, &mut self.moves_buf, moves_start, moves_end_exclusive, &mut self.eval_temp_arr, &mut self.temp_moves);
+            Self::get_evaluator(&mut self.static_evaluator, &mut self.evaluator)
+                .add_move_hints(&self.test_board, &mut self.moves_buf, moves_start, moves_end_exclusive);
         }
         evaluation::add_captures_to_evals(&mut self.moves_buf, moves_start, moves_end_exclusive);
         self.moves_buf.sort_subset_by_eval(moves_start, moves_end_exclusive);
@@ -298,15 +742,13 @@ impl Ai {
                 m,
                 moves_end_exclusive
             );
+            if self.is_stopped() { return alpha; }
 
             if let SingleMoveResult::NewAlpha(max_this) = r {
                 alpha = max_this;
                 new_alpha_i = i as i32;
             } else if let SingleMoveResult::BetaCutOff(max_this) = r {
-                (*resolved_memo).insert(
-                    self.test_board.get_hash(),
-                    MemoData(max_this, remaining_depth, MemoType::High((*m).clone()))
-                );
+                self.memoize(resolved_memo, MemoData(max_this, remaining_depth, MemoType::High((*m).clone())));
                 self.show_tree_left_side = false;
                 return beta;
             }
@@ -318,21 +760,26 @@ impl Ai {
         }
 
         if new_alpha_i == NEW_ALPHA_I_HASH_MOVE {
-            (*resolved_memo).insert(
-                self.test_board.get_hash(),
-                MemoData(alpha, remaining_depth, MemoType::Exact(hash_move.unwrap()))
-            );
+            self.memoize(resolved_memo, MemoData(alpha, remaining_depth, MemoType::Exact(hash_move.unwrap())));
         } else if new_alpha_i >= 0 {
-            (*resolved_memo).insert(
-                self.test_board.get_hash(),
-                MemoData(alpha, remaining_depth, MemoType::Exact(self.moves_buf.get_v()[new_alpha_i as usize].clone()))
-            );
+            self.memoize(resolved_memo, MemoData(alpha, remaining_depth, MemoType::Exact(self.moves_buf.get_v()[new_alpha_i as usize].clone())));
         } else {
-            (*resolved_memo).insert(self.test_board.get_hash(), MemoData(alpha, remaining_depth, MemoType::Low));
+            self.memoize(resolved_memo, MemoData(alpha, remaining_depth, MemoType::Low));
         }
         alpha
     }
 
+    /// Memoizes for the current test board, and also keeps the result if the test board is at the root
+    unsafe fn memoize(&mut self, resolved_memo: *mut dyn MemoTable, data: MemoData) {
+        if self.ply == 0 {
+            if let MemoData(eval, _, MemoType::Exact(ref m) | MemoType::High(ref m)) = data {
+                self.root_best = Some((m.clone(), eval));
+            }
+        }
+        let MemoData(eval, depth, t) = data;
+        (*resolved_memo).insert(self.test_board.get_hash(), MemoData(to_memo_eval(eval, self.ply), depth, t));
+    }
+
     unsafe fn negamax_try_move(
         // Unsafe to allow `m` and `self` be aliases
         &mut self,
@@ -344,7 +791,13 @@ impl Ai {
         m: *const MoveSnapshot,
         moves_start: usize
     ) -> SingleMoveResult {
-        self.test_board.handle_move(&*m, true);
+        self.handle_test_move(&*m, true);
+
+        if self.ply == 0 && self.excluded_root_moves.contains(&self.test_board.get_hash()) {
+            self.handle_test_move(&*m, false);
+            return SingleMoveResult::NoEffect;
+        }
+        self.ply += 1;
 
         let mut fast_found_max_this = 0.0f32;
         let mut fast_found = false;
@@ -365,7 +818,8 @@ impl Ai {
             -self.negamax(remaining_depth - 1, quiescence, -beta, -alpha, moves_start)
         };
 
-        self.test_board.handle_move(&*m, false);
+        self.ply -= 1;
+        self.handle_test_move(&*m, false);
 
         if max_this >= beta {
             SingleMoveResult::BetaCutOff(max_this)
@@ -397,7 +851,7 @@ impl Ai {
     fn get_no_moves_eval(&mut self, alpha: f32, beta: f32) -> f32 {
         let checking_player = self.test_board.get_player_with_turn().get_other_player();
         if is_checking(&mut self.test_board, checking_player) {
-            return alpha;
+            return Self::cap(-MAX_EVAL + self.ply as f32, alpha, beta);
         } else {
             return Self::cap(0.0, alpha, beta);
         }
@@ -0,0 +1,243 @@
+//! Efficiently updatable neural network evaluation. 768 inputs, one per player, piece and square, are seen
+//! from both players' sides and summed into an accumulator of `hidden_size` per side, which `Board` keeps up to date
+//! as pieces move. The output layer reads both halves, the side to move first. Integer only, without SIMD, so
+//! it behaves the same in wasm.
+//!
+//! Weights file, little endian:
+//! `b"CBNN"`, u32 version = 1, u32 hidden size H,
+//! i16 feature weights `[768][H]`, i16 feature biases `[H]`, i16 output weights `[2 * H]`, i32 output bias.
+//!
+//! Input index for a perspective is `own or other * 384 + piece * 64 + square`, with pieces ordered
+//! pawn, knight, bishop, rook, queen, king and squares from a1 = 0 to h8 = 63, flipped vertically for black.
+//! Quantized by `QA` for the accumulator and `QB` for the output weights, the output is in centipawns after `SCALE`.
+
+use std::fmt::{Display, Formatter, self};
+use std::sync::Arc;
+use super::super::game::entities::*;
+use super::super::game::coords::*;
+use super::super::game::board::*;
+use super::evaluator::*;
+
+pub const INPUTS: usize = 768;
+const MAGIC: &[u8; 4] = b"CBNN";
+const VERSION: u32 = 1;
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i64 = 400;
+/// Far above any useful network, so a corrupt size fails before allocating
+const MAX_HIDDEN_SIZE: usize = 1 << 16;
+
+/// Input order of each `Piece`
+static PIECE_INPUT_ORDER: [usize; 6] = [0, 3, 1, 2, 4, 5];
+
+#[derive(Clone, Debug)]
+pub enum NnueError {
+    BadMagic,
+    BadVersion(u32),
+    BadHiddenSize(u32),
+    /// Expected and actual length in bytes
+    BadLength(usize, usize),
+    Io(String)
+}
+
+impl Display for NnueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            NnueError::BadMagic => write!(f, "Not a network file"),
+            NnueError::BadVersion(v) => write!(f, "Unsupported version {}", v),
+            NnueError::BadHiddenSize(size) => write!(f, "Unsupported hidden size {}", size),
+            NnueError::BadLength(expected, actual) => write!(f, "Expected {} bytes but got {}", expected, actual),
+            NnueError::Io(e) => write!(f, "IO error - {}", e)
+        }
+    }
+}
+
+pub struct Network {
+    hidden_size: usize,
+    /// `[INPUTS][hidden_size]`
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    /// `[2 * hidden_size]`, side to move half first
+    output_weights: Vec<i16>,
+    output_bias: i32
+}
+
+struct Reader<'a>(&'a [u8], usize);
+
+impl<'a> Reader<'a> {
+    fn read_u32(&mut self) -> u32 {
+        let v = u32::from_le_bytes([self.0[self.1], self.0[self.1 + 1], self.0[self.1 + 2], self.0[self.1 + 3]]);
+        self.1 += 4;
+        v
+    }
+
+    fn read_i16s(&mut self, len: usize) -> Vec<i16> {
+        let v = self.0[self.1..self.1 + len * 2].chunks(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+        self.1 += len * 2;
+        v
+    }
+}
+
+impl Network {
+
+    /// eg. `Network::from_bytes(include_bytes!("my.nnue"))` to embed a network in the binary
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NnueError> {
+        if bytes.len() < 12 {
+            return Err(NnueError::BadLength(12, bytes.len()));
+        }
+        if &bytes[0..4] != MAGIC {
+            return Err(NnueError::BadMagic);
+        }
+
+        let mut reader = Reader(bytes, 4);
+        let version = reader.read_u32();
+        if version != VERSION {
+            return Err(NnueError::BadVersion(version));
+        }
+        let hidden_size_u32 = reader.read_u32();
+        let hidden_size = hidden_size_u32 as usize;
+        if hidden_size == 0 || hidden_size > MAX_HIDDEN_SIZE {
+            return Err(NnueError::BadHiddenSize(hidden_size_u32));
+        }
+
+        let expected = (INPUTS + 3).checked_mul(hidden_size)
+            .and_then(|weights| weights.checked_mul(2))
+            .and_then(|weight_bytes| weight_bytes.checked_add(12 + 4))
+            .ok_or(NnueError::BadHiddenSize(hidden_size_u32))?;
+        if bytes.len() != expected {
+            return Err(NnueError::BadLength(expected, bytes.len()));
+        }
+
+        let feature_weights = reader.read_i16s(INPUTS * hidden_size);
+        let feature_biases = reader.read_i16s(hidden_size);
+        let output_weights = reader.read_i16s(2 * hidden_size);
+        let output_bias = reader.read_u32() as i32;
+
+        Ok(Self { hidden_size, feature_weights, feature_biases, output_weights, output_bias })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str) -> Result<Self, NnueError> {
+        let bytes = std::fs::read(path).map_err(|e| NnueError::Io(e.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    #[inline]
+    pub fn get_hidden_size(&self) -> usize {
+        self.hidden_size
+    }
+
+    #[inline]
+    fn get_input(perspective: Player, piece: Piece, player: Player, x: u8, y: u8) -> usize {
+        let side = if player == perspective { 0 } else { 1 };
+        // Board rows start from rank 8
+        let rank = if perspective == Player::White { 7 - y } else { y };
+        side * 384 + PIECE_INPUT_ORDER[piece as usize] * 64 + rank as usize * 8 + x as usize
+    }
+
+    /// In pawns, from white's point of view
+    pub fn evaluate(&self, accumulator: &Accumulator, player_with_turn: Player) -> f32 {
+        let h = self.hidden_size;
+        let halves = [&accumulator.values[player_with_turn as usize], &accumulator.values[player_with_turn.get_other_player() as usize]];
+
+        // i64 since each product can reach `QA * i16::MAX`
+        let mut sum: i64 = 0;
+        for (half_i, half) in halves.iter().enumerate() {
+            let weights = &self.output_weights[half_i * h..(half_i + 1) * h];
+            for (v, w) in half.iter().zip(weights.iter()) {
+                sum += ((*v as i32).max(0).min(QA) * *w as i32) as i64;
+            }
+        }
+
+        let centipawns = (sum + self.output_bias as i64) * SCALE / (QA * QB) as i64;
+        centipawns as f32 / 100. * player_with_turn.get_multiplier()
+    }
+}
+
+/// Hidden layer sums from each player's side, indexed by `Player`
+#[derive(Clone)]
+pub struct Accumulator {
+    net: Arc<Network>,
+    values: [Vec<i16>; 2]
+}
+
+impl PartialEq for Accumulator {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.net, &other.net) && self.values == other.values
+    }
+}
+
+impl Accumulator {
+
+    pub fn new(net: Arc<Network>, board: &Board) -> Self {
+        let mut accumulator = Self {
+            values: [net.feature_biases.clone(), net.feature_biases.clone()],
+            net
+        };
+        for player in [Player::White, Player::Black].iter() {
+            for Coord(x, y) in board.get_player_state(*player).piece_locs.iter() {
+                if let Square::Occupied(piece, _) = board.get_by_xy(*x, *y) {
+                    accumulator.add(*piece, *player, *x, *y);
+                }
+            }
+        }
+        accumulator
+    }
+
+    #[inline]
+    pub fn get_network(&self) -> &Arc<Network> {
+        &self.net
+    }
+
+    #[inline]
+    pub fn add(&mut self, piece: Piece, player: Player, x: u8, y: u8) {
+        self.update(piece, player, x, y, 1);
+    }
+
+    #[inline]
+    pub fn remove(&mut self, piece: Piece, player: Player, x: u8, y: u8) {
+        self.update(piece, player, x, y, -1);
+    }
+
+    #[inline]
+    fn update(&mut self, piece: Piece, player: Player, x: u8, y: u8, sign: i16) {
+        let h = self.net.hidden_size;
+        for perspective in [Player::White, Player::Black].iter() {
+            let input = Network::get_input(*perspective, piece, player, x, y);
+            let weights = &self.net.feature_weights[input * h..(input + 1) * h];
+            for (v, w) in self.values[*perspective as usize].iter_mut().zip(weights.iter()) {
+                *v = v.wrapping_add(sign.wrapping_mul(*w));
+            }
+        }
+    }
+}
+
+/// Uses the accumulator on the board when it is for the same network, otherwise builds one
+pub struct NnueEvaluator {
+    net: Arc<Network>
+}
+
+impl NnueEvaluator {
+    pub fn new(net: Arc<Network>) -> Self {
+        Self { net }
+    }
+}
+
+impl Evaluator for NnueEvaluator {
+
+    fn evaluate(&mut self, board: &Board) -> f32 {
+        match board.get_nnue() {
+            Some(accumulator) if Arc::ptr_eq(accumulator.get_network(), &self.net) => {
+                self.net.evaluate(accumulator, board.get_player_with_turn())
+            },
+            _ => {
+                let accumulator = Accumulator::new(self.net.clone(), board);
+                self.net.evaluate(&accumulator, board.get_player_with_turn())
+            }
+        }
+    }
+
+    fn prepare(&mut self, board: &mut Board) {
+        board.set_nnue(Some(self.net.clone()));
+    }
+}
@@ -0,0 +1,186 @@
+use super::super::game::entities::*;
+use super::super::game::coords::*;
+use super::super::game::board::*;
+use super::eval_params::*;
+use super::pst;
+
+/// Middlegame and endgame pawn structure scores from one player's point of view
+#[derive(Copy, Clone, Default)]
+pub struct PawnEval {
+    pub mg: f32,
+    pub eg: f32
+}
+
+impl PawnEval {
+    #[inline]
+    fn add(&mut self, term: (f32, f32), count: f32) {
+        self.mg += term.0 * count;
+        self.eg += term.1 * count;
+    }
+}
+
+#[derive(Copy, Clone)]
+struct PawnEntry(u64, [PawnEval; 2]);
+
+/// Fixed size, always replacing cache of `evaluate_pawns` by `Board::get_pawn_hash`.
+/// Pawn structures change rarely during search so even a small table hits nearly always.
+pub struct PawnTable {
+    entries: Vec<Option<PawnEntry>>,
+    mask: usize,
+    pub hits: usize
+}
+
+impl PawnTable {
+
+    pub fn new(size_log2: u8) -> Self {
+        let len = 1usize << size_log2;
+        Self {
+            entries: vec![None; len],
+            mask: len - 1,
+            hits: 0
+        }
+    }
+
+    /// Must be cleared whenever the params passed to `get` change
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+
+    /// Indexed by `Player`
+    pub fn get(&mut self, board: &Board, params: &EvalParams) -> [PawnEval; 2] {
+        let key = board.get_pawn_hash();
+        let index = key as usize & self.mask;
+        if let Some(PawnEntry(entry_key, eval)) = self.entries[index] {
+            if entry_key == key {
+                self.hits += 1;
+                return eval;
+            }
+        }
+
+        let eval = evaluate_pawns(board, params);
+        self.entries[index] = Some(PawnEntry(key, eval));
+        eval
+    }
+}
+
+/// Bit y of `files[x]` is set when there is a pawn at (x, y)
+pub type PawnFiles = [u8; 8];
+
+#[inline]
+pub fn get_file(files: &PawnFiles, x: i8) -> u8 {
+    if x < 0 || x > 7 { 0 } else { files[x as usize] }
+}
+
+#[inline]
+pub fn has_pawn(files: &PawnFiles, x: i8, y: i8) -> bool {
+    y >= 0 && y <= 7 && get_file(files, x) & (1 << y) != 0
+}
+
+/// Rows strictly in front of `y` from `player`'s point of view
+#[inline]
+fn get_ahead_mask(player: Player, y: u8) -> u8 {
+    if player == Player::White {
+        ((1u16 << y) - 1) as u8
+    } else {
+        !((1u16 << (y + 1)) - 1) as u8
+    }
+}
+
+/// Indexed by `Player`
+pub fn get_pawn_files(board: &Board) -> [PawnFiles; 2] {
+    let mut files: [PawnFiles; 2] = [[0; 8]; 2];
+    for player in [Player::White, Player::Black].iter() {
+        for Coord(x, y) in board.get_player_state(*player).piece_locs.iter() {
+            if let Square::Occupied(Piece::Pawn, _) = board.get_by_xy(*x, *y) {
+                files[*player as usize][*x as usize] |= 1 << y;
+            }
+        }
+    }
+    files
+}
+
+/// Indexed by `Player`
+pub fn evaluate_pawns(board: &Board, params: &EvalParams) -> [PawnEval; 2] {
+    let files = get_pawn_files(board);
+
+    let mut evals = [PawnEval::default(); 2];
+    for player in [Player::White, Player::Black].iter() {
+        evaluate_player_pawns(&mut evals[*player as usize], params, *player, &files[*player as usize], &files[player.get_other_player() as usize]);
+    }
+    evals
+}
+
+fn evaluate_player_pawns(eval: &mut PawnEval, params: &EvalParams, player: Player, own: &PawnFiles, other: &PawnFiles) {
+    let forward: i8 = if player == Player::White { -1 } else { 1 };
+
+    let mut islands = 0;
+    let mut in_island = false;
+
+    for x in 0..8i8 {
+        let file = own[x as usize];
+        if file == 0 {
+            in_island = false;
+            continue;
+        }
+        if !in_island {
+            islands += 1;
+            in_island = true;
+        }
+
+        let count = file.count_ones();
+        if count > 1 {
+            eval.add((params.doubled_mg, params.doubled_eg), (count - 1) as f32);
+        }
+
+        let isolated = get_file(own, x - 1) == 0 && get_file(own, x + 1) == 0;
+
+        for y in 0..8i8 {
+            if file & (1 << y) == 0 { continue; }
+
+            if isolated {
+                eval.add((params.isolated_mg, params.isolated_eg), 1.);
+            }
+
+            let ahead = get_ahead_mask(player, y as u8);
+            let is_passed = (get_file(other, x - 1) | get_file(other, x) | get_file(other, x + 1)) & ahead == 0 && file & ahead == 0;
+            if is_passed {
+                let rank = if player == Player::White { 7 - y } else { y } as usize;
+                eval.add(params.get_passed_pawn(rank), 1.);
+            }
+
+            if has_pawn(own, x - 1, y - forward) || has_pawn(own, x + 1, y - forward) {
+                eval.add((params.chain_mg, params.chain_eg), 1.);
+            }
+            if has_pawn(own, x - 1, y) || has_pawn(own, x + 1, y) {
+                eval.add((params.phalanx_mg, params.phalanx_eg), 1.);
+            }
+
+            // Backward if no neighbour is level or behind to support it, and the square in front is controlled by a pawn
+            if !isolated && !is_passed {
+                let level_or_behind = !ahead;
+                let supportable = (get_file(own, x - 1) | get_file(own, x + 1)) & level_or_behind != 0;
+                let stop_y = y + forward;
+                if !supportable && (has_pawn(other, x - 1, stop_y + forward) || has_pawn(other, x + 1, stop_y + forward)) {
+                    eval.add((params.backward_mg, params.backward_eg), 1.);
+                }
+            }
+        }
+    }
+
+    if islands > 1 {
+        eval.add((params.island_mg, params.island_eg), (islands - 1) as f32);
+    }
+}
+
+/// Most that the terms of one pawn can add up to either way, and of the islands of one player
+pub fn get_max_pawn_terms(params: &EvalParams) -> (f32, f32) {
+    let passed = (1..7).map(|rank| pst::get_taper_bound(params.get_passed_pawn(rank))).fold(0., f32::max);
+    let per_pawn = passed
+        + pst::get_taper_bound((params.doubled_mg, params.doubled_eg))
+        + pst::get_taper_bound((params.isolated_mg, params.isolated_eg))
+        + pst::get_taper_bound((params.backward_mg, params.backward_eg))
+        + pst::get_taper_bound((params.chain_mg, params.chain_eg))
+        + pst::get_taper_bound((params.phalanx_mg, params.phalanx_eg));
+    // 8 files fit at most 4 islands
+    (per_pawn, 3. * pst::get_taper_bound((params.island_mg, params.island_eg)))
+}
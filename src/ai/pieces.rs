@@ -0,0 +1,177 @@
+use super::super::game::entities::*;
+use super::super::game::coords::*;
+use super::super::game::board::*;
+use super::pawns::*;
+use super::eval_params::*;
+use super::pst;
+
+/// Typical safe square counts, indexed by `Piece`
+static MOBILITY_BASE: [u32; 6] = [0, 6, 4, 6, 12, 0];
+
+/// Most squares a piece can move to, indexed by `Piece`
+static MOBILITY_MAX: [u32; 6] = [0, 14, 8, 13, 27, 0];
+
+/// Safe mobility collected during the square control pass, from each player's point of view
+pub struct Mobility {
+    /// Bit y * 8 + x is set for squares attacked by a pawn of the player, indexed by `Player`
+    pawn_attacks: [u64; 2],
+    piece_squares: u32,
+    /// Indexed by `Player`
+    pub mg: [f32; 2],
+    pub eg: [f32; 2]
+}
+
+impl Mobility {
+
+    pub fn new(files: &[PawnFiles; 2]) -> Self {
+        let mut pawn_attacks = [0; 2];
+        for player in [Player::White, Player::Black].iter() {
+            let forward: i8 = if *player == Player::White { -1 } else { 1 };
+            for x in 0..8i8 {
+                for y in 0..8i8 {
+                    if !has_pawn(&files[*player as usize], x, y) { continue; }
+                    for ax in [x - 1, x + 1].iter() {
+                        let ay = y + forward;
+                        if *ax >= 0 && *ax <= 7 && ay >= 0 && ay <= 7 {
+                            pawn_attacks[*player as usize] |= 1 << (ay as u64 * 8 + *ax as u64);
+                        }
+                    }
+                }
+            }
+        }
+
+        Self { pawn_attacks, piece_squares: 0, mg: [0.; 2], eg: [0.; 2] }
+    }
+
+    /// For each square the piece currently being filled could move to or capture on
+    #[inline]
+    pub fn on_control(&mut self, player: Player, moveable: bool, x: u8, y: u8) {
+        if moveable && self.pawn_attacks[player.get_other_player() as usize] & (1 << (y as u64 * 8 + x as u64)) == 0 {
+            self.piece_squares += 1;
+        }
+    }
+
+    /// After all squares of one piece have been controlled
+    pub fn end_piece(&mut self, params: &EvalParams, player: Player, piece: Piece) {
+        if piece != Piece::Pawn && piece != Piece::King {
+            let i = player as usize;
+            let (mg, eg) = params.get_mobility(piece);
+            let squares = self.piece_squares as f32 - MOBILITY_BASE[piece as usize] as f32;
+            self.mg[i] += mg * squares;
+            self.eg[i] += eg * squares;
+
+            if self.piece_squares == 0 {
+                self.mg[i] += params.trapped_piece_mg;
+                self.eg[i] += params.trapped_piece_eg;
+            }
+        }
+        self.piece_squares = 0;
+    }
+}
+
+/// Placement terms which do not depend on mobility, (middlegame, endgame) from each player's point of view, indexed by `Player`
+pub fn evaluate_pieces(board: &Board, params: &EvalParams, files: &[PawnFiles; 2]) -> [(f32, f32); 2] {
+    [
+        evaluate_player_pieces(board, params, Player::White, files),
+        evaluate_player_pieces(board, params, Player::Black, files)
+    ]
+}
+
+fn evaluate_player_pieces(board: &Board, params: &EvalParams, player: Player, files: &[PawnFiles; 2]) -> (f32, f32) {
+    let own = &files[player as usize];
+    let other = &files[player.get_other_player() as usize];
+    let forward: i8 = if player == Player::White { -1 } else { 1 };
+    let seventh_rank: u8 = if player == Player::White { 1 } else { 6 };
+
+    let mut terms = (0., 0.);
+    let mut bishops = 0;
+    let mut rooks: [Option<Coord>; 2] = [None; 2];
+
+    for Coord(x, y) in board.get_player_state(player).piece_locs.iter() {
+        let (x, y) = (*x, *y);
+        if let Square::Occupied(piece, _) = board.get_by_xy(x, y) {
+            match piece {
+                Piece::Bishop => bishops += 1,
+                Piece::Rook => {
+                    if get_file(own, x as i8) == 0 {
+                        add_term(&mut terms, if get_file(other, x as i8) == 0 { (params.rook_open_file_mg, params.rook_open_file_eg) } else { (params.rook_semi_open_file_mg, params.rook_semi_open_file_eg) });
+                    }
+                    if y == seventh_rank {
+                        add_term(&mut terms, (params.rook_seventh_rank_mg, params.rook_seventh_rank_eg));
+                    }
+                    if rooks[0].is_none() { rooks[0] = Some(Coord(x, y)); } else { rooks[1] = Some(Coord(x, y)); }
+                },
+                Piece::Knight => {
+                    if is_outpost(player, x as i8, y as i8, forward, own, other) {
+                        add_term(&mut terms, (params.knight_outpost_mg, params.knight_outpost_eg));
+                    }
+                },
+                _ => ()
+            }
+        }
+    }
+
+    if bishops >= 2 {
+        add_term(&mut terms, (params.bishop_pair_mg, params.bishop_pair_eg));
+    }
+    if let [Some(a), Some(b)] = rooks {
+        if are_connected(board, a, b) {
+            add_term(&mut terms, (params.connected_rooks_mg, params.connected_rooks_eg));
+        }
+    }
+
+    terms
+}
+
+#[inline]
+fn add_term(sum: &mut (f32, f32), term: (f32, f32)) {
+    sum.0 += term.0;
+    sum.1 += term.1;
+}
+
+fn is_outpost(player: Player, x: i8, y: i8, forward: i8, own: &PawnFiles, other: &PawnFiles) -> bool {
+    let in_opponent_half = if player == Player::White { y <= 3 } else { y >= 4 };
+    if !in_opponent_half { return false; }
+
+    let defended = has_pawn(own, x - 1, y - forward) || has_pawn(own, x + 1, y - forward);
+    if !defended { return false; }
+
+    // No opponent pawn on an adjacent file which could still advance to attack the square
+    let mut ay = y + forward;
+    while ay >= 0 && ay <= 7 {
+        if has_pawn(other, x - 1, ay) || has_pawn(other, x + 1, ay) { return false; }
+        ay += forward;
+    }
+    true
+}
+
+fn are_connected(board: &Board, Coord(ax, ay): Coord, Coord(bx, by): Coord) -> bool {
+    if ax == bx {
+        (ay.min(by) + 1..ay.max(by)).all(|y| *board.get_by_xy(ax, y) == Square::Blank)
+    } else if ay == by {
+        (ax.min(bx) + 1..ax.max(bx)).all(|x| *board.get_by_xy(x, ay) == Square::Blank)
+    } else {
+        false
+    }
+}
+
+/// Most that the mobility and placement terms of one piece can add up to either way, indexed by `Piece`,
+/// and the bishop pair and connected rooks of one player
+pub fn get_max_piece_terms(params: &EvalParams) -> ([f32; 6], f32) {
+    let mut per_piece = [0.; 6];
+    for piece in [Piece::Rook, Piece::Knight, Piece::Bishop, Piece::Queen].iter() {
+        let i = *piece as usize;
+        let squares = MOBILITY_BASE[i].max(MOBILITY_MAX[i] - MOBILITY_BASE[i]) as f32;
+        per_piece[i] = pst::get_taper_bound(params.get_mobility(*piece)) * squares
+            + pst::get_taper_bound((params.trapped_piece_mg, params.trapped_piece_eg));
+    }
+
+    per_piece[Piece::Rook as usize] += pst::get_taper_bound((params.rook_open_file_mg, params.rook_open_file_eg))
+        .max(pst::get_taper_bound((params.rook_semi_open_file_mg, params.rook_semi_open_file_eg)))
+        + pst::get_taper_bound((params.rook_seventh_rank_mg, params.rook_seventh_rank_eg));
+    per_piece[Piece::Knight as usize] += pst::get_taper_bound((params.knight_outpost_mg, params.knight_outpost_eg));
+
+    let per_player = pst::get_taper_bound((params.bishop_pair_mg, params.bishop_pair_eg))
+        + pst::get_taper_bound((params.connected_rooks_mg, params.connected_rooks_eg));
+    (per_piece, per_player)
+}
@@ -0,0 +1,247 @@
+//! Tactical puzzles, ie. positions where exactly one move mates or wins material and the alternatives don't.
+//! Candidates are searched with MultiPV 2, and every move of the solver along the solution must be the only good one.
+
+use std::fmt::{Display, Formatter, self};
+use super::super::game::entities::*;
+use super::super::game::coords::*;
+use super::super::game::board::*;
+use super::super::game::move_list::*;
+use super::super::game::move_test::*;
+use super::super::game::check_handler::*;
+use super::evaluation::evaluate_piece;
+use super::memo_table::*;
+use super::*;
+
+/// Least eval of the best move for a puzzle which wins material
+const WIN_EVAL: f32 = 2.;
+/// Most eval of the second best move, so that it doesn't also win
+const MAX_SECOND_EVAL: f32 = 0.5;
+/// Later moves of the solver must be this much better than their alternatives
+const UNIQUE_MARGIN: f32 = 1.5;
+/// Longest solution of a puzzle which wins material, in plies
+const MAX_MATERIAL_PLIES: usize = 5;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Theme {
+    /// Moves of the solver to mate
+    MateIn(u8),
+    /// The first move attacks two or more pieces which are the king, worth more than the attacker or undefended
+    Fork,
+    /// The first move captures an undefended piece
+    HangingPiece
+}
+
+/// Names as used by Lichess' puzzle themes, eg. `mateIn2`
+impl Display for Theme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Theme::MateIn(n) => write!(f, "mateIn{}", n),
+            Theme::Fork => write!(f, "fork"),
+            Theme::HangingPiece => write!(f, "hangingPiece")
+        }
+    }
+}
+
+pub struct Puzzle {
+    pub fen: String,
+    /// Starts with the solver's move and alternates with the opponent's replies, ending with the solver's move
+    pub moves: Vec<MoveSnapshot>,
+    /// Of the first move for the solver
+    pub eval: f32,
+    pub themes: Vec<Theme>
+}
+
+impl Puzzle {
+
+    /// `<fen>,<moves>,<themes>,<eval>` with moves in coordinate notation and themes separated by spaces,
+    /// see `CSV_HEADER`
+    pub fn to_csv_line(&self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.fen,
+            self.moves.iter().map(|m| m.to_long_algebraic()).collect::<Vec<String>>().join(" "),
+            self.themes.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(" "),
+            self.eval
+        )
+    }
+}
+
+pub const CSV_HEADER: &str = "FEN,Moves,Themes,Eval";
+
+/// Squares which pieces can capture on, whether or not the piece there is the opponent's
+struct AttackHandler {
+    squares: Vec<Coord>
+}
+
+impl MoveTestHandler for AttackHandler {
+    fn push(
+        &mut self,
+        _moveable: bool,
+        can_capture: bool,
+        _params: &MoveTestParams,
+        dest_x: u8,
+        dest_y: u8,
+        existing_dest_square: &Square,
+        _replacement_piece: Option<Piece>
+    ) -> bool {
+        if can_capture {
+            if let Square::Occupied(_, _) = existing_dest_square {
+                self.squares.push(Coord(dest_x, dest_y));
+            }
+        }
+        false
+    }
+}
+
+/// Occupied squares which the piece at `src` can capture on, or defends if the piece there is its own
+fn get_attacked(board: &Board, src: Coord) -> Vec<Coord> {
+    let mut handler = AttackHandler { squares: Vec::new() };
+    if let Square::Occupied(piece, player) = board.get_by_xy(src.0, src.1) {
+        fill_src(&MoveTestParams {
+            src_x: src.0 as i8,
+            src_y: src.1 as i8,
+            src_piece: *piece,
+            src_player: *player,
+            can_capture_king: true,
+            board
+        }, &mut handler);
+    }
+    handler.squares
+}
+
+/// Whether a piece of `player` other than the one at `sq` could capture on `sq`
+fn is_defended(board: &Board, sq: Coord, player: Player) -> bool {
+    let mut handler = AttackHandler { squares: Vec::new() };
+    fill_player(player, true, board, &mut handler);
+    handler.squares.contains(&sq)
+}
+
+/// `mate_plies` of the solver's forced mate, from the search rather than the solution which can take longer
+fn get_themes(board: &mut Board, moves: &[MoveSnapshot], mate_plies: Option<i32>) -> Vec<Theme> {
+    let mut themes = Vec::new();
+    if let Some(plies) = mate_plies {
+        themes.push(Theme::MateIn(((plies + 1) / 2) as u8));
+    }
+
+    let (_, dest, _) = match moves[0].get_src_dest_promotion() {
+        Some(x) => x,
+        None => return themes
+    };
+    let player = board.get_player_with_turn();
+    let opponent = player.get_other_player();
+
+    if let Square::Occupied(_, _) = board.get_by_xy(dest.0, dest.1) {
+        if !is_defended(board, dest, opponent) {
+            themes.push(Theme::HangingPiece);
+        }
+    }
+
+    board.handle_move(&moves[0], true);
+    if let Square::Occupied(piece, _) = board.get_by_xy(dest.0, dest.1) {
+        let attacker_value = evaluate_piece(*piece);
+        let targets = get_attacked(board, dest).into_iter().filter(|sq| {
+            match board.get_by_xy(sq.0, sq.1) {
+                Square::Occupied(target, target_player) if *target_player == opponent => {
+                    *target == Piece::King || evaluate_piece(*target) > attacker_value || !is_defended(board, *sq, opponent)
+                },
+                _ => false
+            }
+        }).count();
+        if targets >= 2 && mate_plies.is_none() {
+            themes.push(Theme::Fork);
+        }
+    }
+    board.handle_move(&moves[0], false);
+    themes
+}
+
+/// Whether `eval` is a forced mate for the player it is for
+fn is_mate_eval(eval: f32) -> bool {
+    get_mate_plies(eval).map_or(false, |plies| plies > 0)
+}
+
+impl <M: MemoTable + 'static> Ai<M> {
+
+    /// The best line of `board` and the second best if there is another move, `None` if there are no moves
+    fn search_two_best(&mut self, board: &Board, depth: u8) -> Option<(PvLine, Option<PvLine>)> {
+        let mut lines = self.search(depth, board, 2).into_iter();
+        let best = lines.next().filter(|line| !line.moves.is_empty())?;
+        Some((best, lines.next()))
+    }
+
+    /// A puzzle for the player with the turn on `board`, searching each move of the solver to `depth`
+    pub fn find_puzzle(&mut self, board: &Board, depth: u8) -> Option<Puzzle> {
+        let depth = depth.max(1);
+
+        // Most positions have no winning move, which a single line shows more cheaply
+        let line = self.search(depth, board, 1).into_iter().next()?;
+        if line.eval < WIN_EVAL {
+            return None;
+        }
+
+        let (best, second) = match self.search_two_best(board, depth)? {
+            (best, Some(second)) => (best, second),
+            // Nothing to choose from
+            (_, None) => return None
+        };
+        let is_mate = is_mate_eval(best.eval);
+        if (is_mate && is_mate_eval(second.eval)) || (!is_mate && (best.eval < WIN_EVAL || second.eval > MAX_SECOND_EVAL)) {
+            return None;
+        }
+
+        let mut line_board = board.clone();
+        let mut moves: Vec<MoveSnapshot> = Vec::new();
+        let mut temp = MoveList::new(50);
+        let mut legal_moves = MoveList::new(50);
+        let mut pv = best.moves;
+        loop {
+            // The solver's move, then the opponent's reply from the same line
+            line_board.handle_move(&pv[0], true);
+            moves.push(pv[0].clone());
+
+            legal_moves.write_index = 0;
+            line_board.get_moves(&mut temp, &mut legal_moves);
+            if legal_moves.write_index == 0 {
+                let solver = line_board.get_player_with_turn().get_other_player();
+                let is_checkmate = is_checking(&mut line_board, solver);
+                if !is_checkmate { return None; }
+                break;
+            }
+            if is_mate && pv.len() < 2 {
+                // The line stops before mate
+                return None;
+            }
+            if pv.len() < 2 || (!is_mate && moves.len() >= MAX_MATERIAL_PLIES) {
+                break;
+            }
+
+            let reply = pv[1].clone();
+            line_board.handle_move(&reply, true);
+            let (next_best, next_second) = match self.search_two_best(&line_board, depth) {
+                Some(lines) => lines,
+                None => break
+            };
+            // A forced move is the only good one
+            let is_unique = match &next_second {
+                None => true,
+                Some(next_second) if is_mate => is_mate_eval(next_best.eval) && !is_mate_eval(next_second.eval),
+                Some(next_second) => next_best.eval - next_second.eval >= UNIQUE_MARGIN
+            };
+            if !is_unique || (is_mate && !is_mate_eval(next_best.eval)) {
+                if is_mate { return None; }
+                break;
+            }
+
+            moves.push(reply);
+            pv = next_best.moves;
+        }
+
+        let mut board = board.clone();
+        Some(Puzzle {
+            fen: board.to_fen(),
+            themes: get_themes(&mut board, &moves, get_mate_plies(best.eval).filter(|_| is_mate)),
+            moves,
+            eval: best.eval
+        })
+    }
+}
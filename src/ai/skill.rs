@@ -0,0 +1,111 @@
+use super::super::game::move_list::*;
+use super::super::game::board::*;
+use super::super::extern_funcs::random;
+use super::memo_table::*;
+use super::*;
+use crate::{console_log};
+
+/// Handicaps for a weaker opponent. After searching `candidates` MultiPV lines with limited depth and nodes,
+/// picks randomly among lines within `eval_window` of the best, or with `blunder_rate` chance any legal move.
+pub struct SkillLevel {
+    pub depth: u8,
+    pub node_limit: Option<u32>,
+    pub candidates: usize,
+    pub eval_window: f32,
+    pub blunder_rate: f32,
+    /// Rough playing strength, only for display
+    pub elo: u32
+}
+
+pub const MAX_SKILL_LEVEL: u8 = 10;
+
+static SKILL_LEVELS: [SkillLevel; MAX_SKILL_LEVEL as usize + 1] = [
+    SkillLevel { depth: 1, node_limit: Some(200), candidates: 5, eval_window: 3., blunder_rate: 0.3, elo: 400 },
+    SkillLevel { depth: 1, node_limit: Some(500), candidates: 5, eval_window: 2., blunder_rate: 0.2, elo: 500 },
+    SkillLevel { depth: 1, node_limit: Some(1000), candidates: 4, eval_window: 1.5, blunder_rate: 0.15, elo: 600 },
+    SkillLevel { depth: 3, node_limit: Some(2000), candidates: 4, eval_window: 1., blunder_rate: 0.1, elo: 700 },
+    SkillLevel { depth: 3, node_limit: Some(5000), candidates: 4, eval_window: 0.7, blunder_rate: 0.07, elo: 800 },
+    SkillLevel { depth: 3, node_limit: Some(10000), candidates: 3, eval_window: 0.5, blunder_rate: 0.05, elo: 900 },
+    SkillLevel { depth: 3, node_limit: Some(30000), candidates: 3, eval_window: 0.3, blunder_rate: 0.03, elo: 1000 },
+    SkillLevel { depth: 5, node_limit: Some(60000), candidates: 3, eval_window: 0.2, blunder_rate: 0.02, elo: 1100 },
+    SkillLevel { depth: 5, node_limit: Some(150000), candidates: 2, eval_window: 0.1, blunder_rate: 0.01, elo: 1200 },
+    SkillLevel { depth: 5, node_limit: None, candidates: 2, eval_window: 0.05, blunder_rate: 0., elo: 1300 },
+    SkillLevel { depth: 5, node_limit: None, candidates: 1, eval_window: 0., blunder_rate: 0., elo: 1400 }
+];
+
+impl SkillLevel {
+
+    /// Levels above `MAX_SKILL_LEVEL` are the same as the max
+    pub fn get(level: u8) -> &'static SkillLevel {
+        &SKILL_LEVELS[level.min(MAX_SKILL_LEVEL) as usize]
+    }
+
+    /// The level with the closest `elo`
+    pub fn get_level_for_elo(elo: u32) -> u8 {
+        let mut best_level = 0;
+        for (level, skill) in SKILL_LEVELS.iter().enumerate() {
+            if (skill.elo as i64 - elo as i64).abs() < (SKILL_LEVELS[best_level].elo as i64 - elo as i64).abs() {
+                best_level = level;
+            }
+        }
+        best_level as u8
+    }
+
+    /// Whether picking a move needs more than the single best line
+    pub fn is_handicapped(&self) -> bool {
+        self.candidates > 1 || self.blunder_rate > 0.
+    }
+}
+
+impl <M: MemoTable + 'static> Ai<M> {
+
+    /// Searches and picks a move according to `skill`, without making it
+    pub fn choose_move(&mut self, real_board: &Board, skill: &SkillLevel) -> Option<MoveSnapshot> {
+        let prev_node_limit = self.node_limit;
+        self.node_limit = skill.node_limit;
+        let lines = self.search(skill.depth, real_board, skill.candidates);
+        self.node_limit = prev_node_limit;
+        self.pick_move(real_board, &lines, skill)
+    }
+
+    /// Picks a move according to `skill` from `lines`, the result of searching `real_board` with `skill.candidates`
+    /// lines, `skill.depth` and `skill.node_limit`
+    pub fn pick_move(&mut self, real_board: &Board, lines: &[PvLine], skill: &SkillLevel) -> Option<MoveSnapshot> {
+        if skill.blunder_rate > 0. && (random() as f32) < skill.blunder_rate {
+            self.set_test_board(real_board);
+            self.moves_buf.write_index = 0;
+            self.test_board.get_moves(&mut self.temp_moves, &mut self.moves_buf);
+            if self.moves_buf.write_index > 0 {
+                let i = ((random() * self.moves_buf.write_index as f64) as usize).min(self.moves_buf.write_index - 1);
+                let m = self.moves_buf.get_v()[i].clone();
+                console_log!("Skill blunder: {}", m);
+                return Some(m);
+            }
+        }
+
+        Self::pick_line(lines, skill.eval_window)
+    }
+
+    /// Random among lines within `eval_window` of the first (best) line, more likely the better it is
+    fn pick_line(lines: &[PvLine], eval_window: f32) -> Option<MoveSnapshot> {
+        let best_eval = lines.first()?.eval;
+
+        let weights: Vec<f32> = lines.iter().map(|line| {
+            // A line which ran out of nodes early can have a shallower eval above the best
+            let loss = (best_eval - line.eval).max(0.);
+            if loss <= eval_window { eval_window - loss + 0.01 } else { 0. }
+        }).collect();
+        let total: f32 = weights.iter().sum();
+
+        let mut r = random() as f32 * total;
+        for (line, weight) in lines.iter().zip(weights.iter()) {
+            if *weight <= 0. { continue; }
+            if r < *weight {
+                console_log!("Skill pick: {} ({} from best {})", line.moves[0], line.eval, best_eval);
+                return line.moves.first().cloned();
+            }
+            r -= *weight;
+        }
+        lines[0].moves.first().cloned()
+    }
+}
@@ -0,0 +1,1474 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+//
+// Ported from src/syzygy/tbprobe.cpp of Stockfish, a UCI chess playing engine derived from Glaurung 2.1.
+// Copyright (C) 2004-2024 The Stockfish developers (see AUTHORS file of Stockfish)
+// Copyright (c) 2013 Ronald de Man
+//
+// This file is free software: you can redistribute it and/or modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation, either version 3 of the License, or (at your option) any later version.
+// It is distributed WITHOUT ANY WARRANTY, see LICENSES/GPL-3.0-or-later.txt. Builds with the `syzygy` feature,
+// which is the only one to compile this file, are covered by the GPL as a whole.
+
+//! Native only Syzygy tablebase probing, ported from the probing code of Stockfish (tbprobe.cpp).
+//! Reads win/draw/loss (`.rtbw`) and distance to zeroing (`.rtbz`) files from local directories.
+//! Tables are found by `Tablebases::new` but each file is only mapped into memory at its first probe, so the OS
+//! reads just the pages probed. Offsets read from files are bounds checked, so a truncated or corrupt file fails probes.
+//!
+//! Squares here are from a1 = 0 to h8 = 63 and pieces use the file format's codes, 1 to 6 for
+//! white pawn, knight, bishop, rook, queen and king, plus 8 for black.
+//! The engine has no fifty move counter, so probes assume it is 0 and cursed wins are treated as draws.
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{Display, Formatter, self};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use memmap2::Mmap;
+use super::super::game::entities::*;
+use super::super::game::board::*;
+use super::super::game::move_list::*;
+use super::super::game::check_handler::*;
+
+/// Most pieces in any Syzygy table
+const TB_PIECES: usize = 7;
+
+/// Eval of a won tablebase position, in pawns. Above any normal or endgame evaluation.
+pub const TB_WIN_EVAL: f32 = 1000.;
+/// Cursed wins and blessed losses are draws by the fifty move rule, but nudged the right way
+const TB_CURSED_EVAL: f32 = 0.01;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// `PairsData::flags`
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// Win, draw or loss for the player with the turn
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    /// Lost, but drawn by the fifty move rule
+    BlessedLoss = -1,
+    Draw = 0,
+    /// Won, but drawn by the fifty move rule
+    CursedWin = 1,
+    Win = 2
+}
+
+impl Wdl {
+
+    fn from_i32(v: i32) -> Self {
+        match v {
+            i32::MIN..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win
+        }
+    }
+
+    fn negate(self) -> Self {
+        Self::from_i32(-(self as i32))
+    }
+
+    /// For the player with the turn, in pawns
+    pub fn to_eval(self) -> f32 {
+        match self {
+            Wdl::Loss => -TB_WIN_EVAL,
+            Wdl::BlessedLoss => -TB_CURSED_EVAL,
+            Wdl::Draw => 0.,
+            Wdl::CursedWin => TB_CURSED_EVAL,
+            Wdl::Win => TB_WIN_EVAL
+        }
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessedLoss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursedWin",
+            Wdl::Win => "win"
+        }
+    }
+}
+
+impl Display for Wdl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ProbeState {
+    Ok,
+    Fail,
+    /// The best move zeroes the fifty move counter, so the stored DTZ can't be used
+    ZeroingBestMove,
+    /// DTZ tables only store one player with the turn
+    ChangeStm
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum TableType { Wdl, Dtz }
+
+/// Lookup tables for the encoding of positions into table indices
+struct Maps {
+    /// Squares below the a1-h8 diagonal to 0..27
+    b1h1h7: [u64; 64],
+    /// Squares in the a1-d1-d4 triangle to 0..9, diagonal squares last
+    a1d1d4: [u64; 64],
+    /// The 462 placements of two kings with the first in the a1-d1-d4 triangle
+    kk: [[u64; 64]; 10],
+    /// `binomial[k][n]` ways to choose k of n
+    binomial: [[u64; 64]; 6],
+    /// Squares a2-h7 to 0..47, where the leading pawn has the highest
+    pawns: [u64; 64],
+    /// `[lead pawn count][square]`
+    lead_pawn_idx: [[u64; 64]; 6],
+    /// `[lead pawn count][file a to d]`
+    lead_pawns_size: [[u64; 4]; 6]
+}
+
+lazy_static! {
+    static ref MAPS: Maps = Maps::new();
+}
+
+#[inline]
+fn rank_of(sq: usize) -> usize { sq >> 3 }
+#[inline]
+fn file_of(sq: usize) -> usize { sq & 7 }
+#[inline]
+fn off_a1h8(sq: usize) -> i32 { rank_of(sq) as i32 - file_of(sq) as i32 }
+#[inline]
+fn is_king_distance_ok(a: usize, b: usize) -> bool {
+    (file_of(a) as i32 - file_of(b) as i32).abs() > 1 || (rank_of(a) as i32 - rank_of(b) as i32).abs() > 1
+}
+
+impl Maps {
+
+    fn new() -> Self {
+        let mut maps = Self {
+            b1h1h7: [0; 64],
+            a1d1d4: [0; 64],
+            kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6]
+        };
+
+        let mut code = 0;
+        for s in 0..64 {
+            if off_a1h8(s) < 0 {
+                maps.b1h1h7[s] = code;
+                code += 1;
+            }
+        }
+
+        let mut diagonal = Vec::new();
+        code = 0;
+        for s in 0..=27 {
+            if off_a1h8(s) < 0 && file_of(s) <= 3 {
+                maps.a1d1d4[s] = code;
+                code += 1;
+            } else if off_a1h8(s) == 0 && file_of(s) <= 3 {
+                diagonal.push(s);
+            }
+        }
+        for s in diagonal {
+            maps.a1d1d4[s] = code;
+            code += 1;
+        }
+
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for s1 in 0..=27 {
+                // b1 is mapped to 0
+                if maps.a1d1d4[s1] == idx as u64 && (idx != 0 || s1 == 1) {
+                    for s2 in 0..64 {
+                        if !is_king_distance_ok(s1, s2) {
+                            continue;
+                        } else if off_a1h8(s1) == 0 && off_a1h8(s2) > 0 {
+                            continue;
+                        } else if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                            both_on_diagonal.push((idx, s2));
+                        } else {
+                            maps.kk[idx][s2] = code;
+                            code += 1;
+                        }
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            maps.kk[idx][s2] = code;
+            code += 1;
+        }
+
+        maps.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6 {
+                if k > n { break; }
+                maps.binomial[k][n] = if k > 0 { maps.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { maps.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available_squares = 47;
+        for lead_pawns_cnt in 1..=5 {
+            for f in 0..4 {
+                let mut idx = 0;
+                for r in 1..=6 {
+                    let sq = r * 8 + f;
+                    if lead_pawns_cnt == 1 {
+                        maps.pawns[sq] = available_squares;
+                        available_squares -= 1;
+                        maps.pawns[sq ^ 7] = available_squares;
+                        available_squares = available_squares.saturating_sub(1);
+                    }
+                    maps.lead_pawn_idx[lead_pawns_cnt][sq] = idx;
+                    idx += maps.binomial[lead_pawns_cnt - 1][maps.pawns[sq] as usize];
+                }
+                maps.lead_pawns_size[lead_pawns_cnt][f] = idx;
+            }
+        }
+
+        maps
+    }
+}
+
+// Reads are `None` past the end of the file
+#[inline]
+fn read_u8(b: &[u8], i: usize) -> Option<u8> { b.get(i).copied() }
+#[inline]
+fn read_bytes<const N: usize>(b: &[u8], i: usize) -> Option<[u8; N]> {
+    b.get(i..i.checked_add(N)?)?.try_into().ok()
+}
+#[inline]
+fn read_u16_le(b: &[u8], i: usize) -> Option<u16> { Some(u16::from_le_bytes(read_bytes(b, i)?)) }
+#[inline]
+fn read_u32_le(b: &[u8], i: usize) -> Option<u32> { Some(u32::from_le_bytes(read_bytes(b, i)?)) }
+#[inline]
+fn read_u32_be(b: &[u8], i: usize) -> Option<u32> { Some(u32::from_be_bytes(read_bytes(b, i)?)) }
+#[inline]
+fn read_u64_be(b: &[u8], i: usize) -> Option<u64> { Some(u64::from_be_bytes(read_bytes(b, i)?)) }
+#[inline]
+fn shl(v: u64, n: u32) -> u64 {
+    if n >= 64 { 0 } else { v << n }
+}
+
+/// Indexing information for one player with the turn and one leading file.
+/// Offsets are into the bytes of the file, including its magic.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    max_sym_len: u8,
+    /// Also the stored value when `FLAG_SINGLE_VALUE` is set
+    min_sym_len: u8,
+    num_blocks: u32,
+    block_size: usize,
+    /// About every span values there is a sparse index entry
+    span: usize,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: u32,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    /// `base64[l - min_sym_len]` is the lowest symbol of length l, padded to 64 bits
+    base64: Vec<u64>,
+    /// Number of values, minus one, which each symbol expands to
+    symlen: Vec<u8>,
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    /// For DTZ, where each WDL's values start in the map
+    map_idx: [u16; 4]
+}
+
+impl PairsData {
+
+    #[inline]
+    fn get_left(&self, b: &[u8], sym: usize) -> Option<usize> {
+        let [b0, b1, _] = read_bytes::<3>(b, self.btree + sym * 3)?;
+        Some((((b1 & 0xF) as usize) << 8) | b0 as usize)
+    }
+
+    #[inline]
+    fn get_right(&self, b: &[u8], sym: usize) -> Option<usize> {
+        let [_, b1, b2] = read_bytes::<3>(b, self.btree + sym * 3)?;
+        Some(((b2 as usize) << 4) | (b1 >> 4) as usize)
+    }
+}
+
+/// A file mapped into memory with its parsed headers
+struct TableData {
+    bytes: Mmap,
+    /// `[player with the turn * 4 + file]`
+    items: Vec<PairsData>,
+    /// Offset of the DTZ value maps
+    map: usize
+}
+
+struct Table {
+    /// Piece counts indexed by `Player` then `Piece`, with white as in the file name
+    key: [[u8; 6]; 2],
+    /// With the colours swapped
+    key2: [[u8; 6]; 2],
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    /// Of the leading colour, which has fewer pawns but some, then of the other colour
+    pawn_count: [u8; 2],
+    wdl_path: PathBuf,
+    dtz_path: PathBuf,
+    wdl: OnceLock<Option<TableData>>,
+    dtz: OnceLock<Option<TableData>>
+}
+
+impl Table {
+
+    /// `name` like `KRPvKR`
+    fn new(name: &str, dir: &Path) -> Option<Self> {
+        let mut sides = name.split('v');
+        let (white, black) = (sides.next()?, sides.next()?);
+        if sides.next().is_some() || !white.starts_with('K') || !black.starts_with('K') {
+            return None;
+        }
+
+        let mut key = [[0u8; 6]; 2];
+        for (i, side) in [white, black].iter().enumerate() {
+            for c in side.chars() {
+                let piece = match c {
+                    'K' => Piece::King,
+                    'Q' => Piece::Queen,
+                    'R' => Piece::Rook,
+                    'B' => Piece::Bishop,
+                    'N' => Piece::Knight,
+                    'P' => Piece::Pawn,
+                    _ => return None
+                };
+                key[i][piece as usize] += 1;
+            }
+        }
+        if key[0][Piece::King as usize] != 1 || key[1][Piece::King as usize] != 1 {
+            return None;
+        }
+
+        let piece_count = white.len() + black.len();
+        if piece_count > TB_PIECES {
+            return None;
+        }
+        let pawns = [key[0][Piece::Pawn as usize], key[1][Piece::Pawn as usize]];
+        let has_unique_pieces = key.iter().any(|side| {
+            [Piece::Pawn, Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen].iter().any(|p| side[*p as usize] == 1)
+        });
+
+        // The leading colour has fewer pawns, for better compression
+        let white_leads = pawns[1] == 0 || (pawns[0] > 0 && pawns[1] >= pawns[0]);
+        let pawn_count = if white_leads { pawns } else { [pawns[1], pawns[0]] };
+
+        Some(Self {
+            key,
+            key2: [key[1], key[0]],
+            piece_count,
+            has_pawns: pawns[0] + pawns[1] > 0,
+            has_unique_pieces,
+            pawn_count,
+            wdl_path: dir.join(format!("{}.rtbw", name)),
+            dtz_path: dir.join(format!("{}.rtbz", name)),
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new()
+        })
+    }
+
+    #[inline]
+    fn is_symmetric(&self) -> bool {
+        self.key == self.key2
+    }
+
+    fn get_data(&self, t: TableType) -> Option<&TableData> {
+        let (cell, path) = match t {
+            TableType::Wdl => (&self.wdl, &self.wdl_path),
+            TableType::Dtz => (&self.dtz, &self.dtz_path)
+        };
+        cell.get_or_init(|| {
+            let file = File::open(path).ok()?;
+            // Safe while nothing truncates the file, which tablebases never are once written
+            let bytes = unsafe { Mmap::map(&file) }.ok()?;
+            let data = self.parse(t, bytes);
+            if data.is_none() {
+                eprintln!("Bad tablebase file {}", path.display());
+            }
+            data
+        }).as_ref()
+    }
+
+    #[inline]
+    fn get_sides(&self, t: TableType) -> usize {
+        if t == TableType::Wdl && !self.is_symmetric() { 2 } else { 1 }
+    }
+
+    /// Reads the headers of a whole file, `None` if any section is out of its bounds
+    fn parse(&self, t: TableType, bytes: Mmap) -> Option<TableData> {
+        let magic = if t == TableType::Wdl { WDL_MAGIC } else { DTZ_MAGIC };
+        if bytes.len() < 6 || bytes[0..4] != magic {
+            return None;
+        }
+        // Split, then has pawns
+        let flags = bytes[4];
+        if (flags & 2 != 0) != self.has_pawns || (t == TableType::Wdl && (flags & 1 != 0) == self.is_symmetric()) {
+            return None;
+        }
+
+        let sides = self.get_sides(t);
+        let max_file = if self.has_pawns { 3 } else { 0 };
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        let mut items = vec![PairsData::default(); 2 * 4];
+        let mut data = 5;
+
+        for f in 0..=max_file {
+            let (order0, order1) = (read_u8(&bytes, data)?, if pp { read_u8(&bytes, data + 1)? } else { 0xFF });
+            let order = [[order0 & 0xF, order1 & 0xF], [order0 >> 4, order1 >> 4]];
+            data += 1 + pp as usize;
+
+            for k in 0..self.piece_count {
+                let piece = read_u8(&bytes, data)?;
+                for i in 0..sides {
+                    items[i * 4 + f].pieces[k] = if i == 1 { piece >> 4 } else { piece & 0xF };
+                }
+                data += 1;
+            }
+            for i in 0..sides {
+                let d = &mut items[i * 4 + f];
+                // Probing trusts the pieces to be the table's, which either colour may lead
+                let mut pieces = d.pieces[..self.piece_count].to_vec();
+                pieces.sort_unstable();
+                if pieces != self.get_codes(false) && pieces != self.get_codes(true) {
+                    return None;
+                }
+                self.set_groups(d, order[i], f)?;
+            }
+        }
+        data += data & 1;
+
+        for f in 0..=max_file {
+            for i in 0..sides {
+                data = set_sizes(&mut items[i * 4 + f], &bytes, data)?;
+            }
+        }
+
+        let map = data;
+        if t == TableType::Dtz {
+            for f in 0..=max_file {
+                let d = &mut items[f];
+                if d.flags & FLAG_MAPPED != 0 {
+                    if d.flags & FLAG_WIDE != 0 {
+                        data += data & 1;
+                        for i in 0..4 {
+                            d.map_idx[i] = u16::try_from((data - map) / 2 + 1).ok()?;
+                            data += 2 * read_u16_le(&bytes, data)? as usize + 2;
+                        }
+                    } else {
+                        for i in 0..4 {
+                            d.map_idx[i] = u16::try_from(data - map + 1).ok()?;
+                            data += read_u8(&bytes, data)? as usize + 1;
+                        }
+                    }
+                }
+            }
+            data += data & 1;
+        }
+
+        for f in 0..=max_file {
+            for i in 0..sides {
+                let d = &mut items[i * 4 + f];
+                d.sparse_index = data;
+                data = data.checked_add(d.sparse_index_size.checked_mul(6)?)?;
+            }
+        }
+        for f in 0..=max_file {
+            for i in 0..sides {
+                let d = &mut items[i * 4 + f];
+                d.block_length = data;
+                data = data.checked_add(d.block_length_size as usize * 2)?;
+            }
+        }
+        for f in 0..=max_file {
+            for i in 0..sides {
+                let d = &mut items[i * 4 + f];
+                data = data.checked_add(0x3F)? & !0x3F;
+                d.data = data;
+                data = data.checked_add((d.num_blocks as usize).checked_mul(d.block_size)?)?;
+            }
+        }
+
+        if data > bytes.len() {
+            return None;
+        }
+        Some(TableData { bytes, items, map })
+    }
+
+    /// Pieces of the same type and colour are grouped and encoded together, except for the leading group
+    /// which without pawns is 3 unique pieces, or the two kings when there are no unique pieces
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], f: usize) -> Option<()> {
+        let maps = &*MAPS;
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns { 0 } else if self.has_unique_pieces { 3 } else { 2 };
+        d.group_len[0] = 1;
+
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        // The order of the groups in the encoding is per table. The leading group is at `order[0]`
+        // and the remaining pawns, when both players have pawns, at `order[1]`.
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        if order[0] as usize >= n || (pp && order[1] as usize >= n) {
+            return None;
+        }
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    maps.lead_pawns_size[d.group_len[0]][f]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= maps.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= maps.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+        Some(())
+    }
+
+    /// Index of the pieces on `squares` in the order of `d`, after the `lead_pawns_cnt` leading pawns when there are pawns,
+    /// with the squares flipped as the table needs
+    fn get_index(&self, d: &PairsData, squares: &mut [usize; TB_PIECES], size: usize, lead_pawns_cnt: usize) -> u64 {
+        let maps = &*MAPS;
+
+        // The leading piece goes in the a1-d1-d4 triangle
+        if file_of(squares[0]) > 3 {
+            for sq in squares[..size].iter_mut() {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx: u64;
+        if self.has_pawns {
+            idx = maps.lead_pawn_idx[lead_pawns_cnt][squares[0]];
+            squares[1..lead_pawns_cnt].sort_by_key(|sq| maps.pawns[*sq]);
+            for i in 1..lead_pawns_cnt {
+                idx += maps.binomial[i][maps.pawns[squares[i]] as usize];
+            }
+        } else {
+            if rank_of(squares[0]) > 3 {
+                for sq in squares[..size].iter_mut() {
+                    *sq ^= 56;
+                }
+            }
+
+            // The first piece of the leading group off the a1-h8 diagonal goes below it
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 { continue; }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in squares[i..size].iter_mut() {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if self.has_unique_pieces {
+                let adjust1 = (squares[1] > squares[0]) as u64;
+                let adjust2 = (squares[2] > squares[0]) as u64 + (squares[2] > squares[1]) as u64;
+                let (s0, s1, s2) = (squares[0] as u64, squares[1] as u64, squares[2] as u64);
+
+                idx = if off_a1h8(squares[0]) != 0 {
+                    (maps.a1d1d4[squares[0]] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+                } else if off_a1h8(squares[1]) != 0 {
+                    (6 * 63 + (s0 >> 3) * 28 + maps.b1h1h7[squares[1]]) * 62 + s2 - adjust2
+                } else if off_a1h8(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + (s0 >> 3) * 7 * 28 + ((s1 >> 3) - adjust1) * 28 + maps.b1h1h7[squares[2]]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + (s0 >> 3) * 7 * 6 + ((s1 >> 3) - adjust1) * 6 + ((s2 >> 3) - adjust2)
+                };
+            } else {
+                idx = maps.kk[maps.a1d1d4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        // Remaining groups, each sorted and encoded by squares not taken by earlier groups
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[group_start..group_start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|s| sq > **s).count();
+                n += maps.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start += len;
+            next += 1;
+        }
+        idx
+    }
+
+    /// Codes of the pieces in `key`, sorted, with the colours swapped when `swap`
+    fn get_codes(&self, swap: bool) -> Vec<u8> {
+        let mut codes = Vec::with_capacity(self.piece_count);
+        for (i, player) in [Player::White, Player::Black].iter().enumerate() {
+            for piece in PIECES.iter() {
+                let player = if swap { player.get_other_player() } else { *player };
+                codes.extend((0..self.key[i][*piece as usize]).map(|_| get_code(*piece, player)));
+            }
+        }
+        codes.sort_unstable();
+        codes
+    }
+}
+
+/// Reads the Huffman and block layout of `d` from `data`, returning where the next section starts
+fn set_sizes(d: &mut PairsData, b: &[u8], mut data: usize) -> Option<usize> {
+    d.flags = read_u8(b, data)?;
+    data += 1;
+
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        d.min_sym_len = read_u8(b, data)?;
+        return Some(data + 1);
+    }
+
+    let group_count = d.group_len.iter().position(|len| *len == 0)?;
+    let tb_size = d.group_idx[group_count];
+
+    d.block_size = 1usize.checked_shl(read_u8(b, data)? as u32)?;
+    d.span = 1usize.checked_shl(read_u8(b, data + 1)? as u32)?;
+    d.sparse_index_size = usize::try_from((tb_size + d.span as u64 - 1) / d.span as u64).ok()?;
+    let padding = read_u8(b, data + 2)?;
+    d.num_blocks = read_u32_le(b, data + 3)?;
+    data += 7;
+    d.block_length_size = d.num_blocks.checked_add(padding as u32)?;
+    d.max_sym_len = read_u8(b, data)?;
+    d.min_sym_len = read_u8(b, data + 1)?;
+    data += 2;
+    d.lowest_sym = data;
+    // Codes are read from a 64 bit buffer
+    if d.max_sym_len < d.min_sym_len || d.max_sym_len > 64 {
+        return None;
+    }
+
+    // Canonical Huffman codes, where longer symbols have lower values
+    let len = (d.max_sym_len - d.min_sym_len) as usize + 1;
+    d.base64 = vec![0; len];
+    for i in (0..len.saturating_sub(1)).rev() {
+        let lowest = read_u16_le(b, d.lowest_sym + i * 2)? as u64;
+        let next_lowest = read_u16_le(b, d.lowest_sym + (i + 1) * 2)? as u64;
+        d.base64[i] = d.base64[i + 1].wrapping_add(lowest).wrapping_sub(next_lowest) / 2;
+    }
+    for i in 0..len {
+        d.base64[i] = shl(d.base64[i], 64 - i as u32 - d.min_sym_len as u32);
+    }
+
+    data += len * 2;
+    let symlen_size = read_u16_le(b, data)? as usize;
+    data += 2;
+    d.btree = data;
+    d.symlen = vec![0; symlen_size];
+    let end = data + symlen_size * 3 + (symlen_size & 1);
+    if end > b.len() {
+        return None;
+    }
+
+    // Recursive pairing, each symbol is a pair of other symbols down to the values
+    let mut visited = vec![false; symlen_size];
+    for sym in 0..symlen_size {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(d, b, sym, &mut visited)?;
+        }
+    }
+
+    Some(end)
+}
+
+/// `None` when a symbol pairs with one past the end
+fn set_symlen(d: &mut PairsData, b: &[u8], sym: usize, visited: &mut Vec<bool>) -> Option<u8> {
+    visited[sym] = true;
+    let right = d.get_right(b, sym)?;
+    if right == 0xFFF {
+        return Some(0);
+    }
+    let left = d.get_left(b, sym)?;
+    if left >= visited.len() || right >= visited.len() {
+        return None;
+    }
+    if !visited[left] {
+        d.symlen[left] = set_symlen(d, b, left, visited)?;
+    }
+    if !visited[right] {
+        d.symlen[right] = set_symlen(d, b, right, visited)?;
+    }
+    Some((d.symlen[left] as u32 + d.symlen[right] as u32 + 1) as u8)
+}
+
+/// The value at `idx`, by finding its block through the sparse index then expanding symbols.
+/// `None` when the file points outside itself.
+fn decompress_pairs(d: &PairsData, b: &[u8], idx: u64) -> Option<i32> {
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        return Some(d.min_sym_len as i32);
+    }
+
+    // Sparse entry k points at the value with index k * span + span / 2
+    let k = usize::try_from(idx / d.span as u64).ok()?;
+    let entry = d.sparse_index.checked_add(k.checked_mul(6)?)?;
+    let mut block = read_u32_le(b, entry)? as usize;
+    let mut offset = read_u16_le(b, entry + 4)? as i64;
+    offset += (idx % d.span as u64) as i64 - (d.span / 2) as i64;
+
+    let block_length = |i: usize| Some(read_u16_le(b, d.block_length.checked_add(i.checked_mul(2)?)?)? as i64);
+    while offset < 0 {
+        block = block.checked_sub(1)?;
+        offset += block_length(block)? + 1;
+    }
+    while offset > block_length(block)? {
+        offset -= block_length(block)? + 1;
+        block += 1;
+    }
+
+    let mut ptr = d.data.checked_add(block.checked_mul(d.block_size)?)?;
+    let mut buf64 = read_u64_be(b, ptr)?;
+    ptr += 8;
+    let mut buf64_size = 64;
+    let mut sym;
+
+    loop {
+        let mut len = 0;
+        while buf64 < *d.base64.get(len)? {
+            len += 1;
+        }
+
+        sym = (buf64 - d.base64[len]).checked_shr((64 - len - d.min_sym_len as usize) as u32).unwrap_or(0) as usize;
+        sym += read_u16_le(b, d.lowest_sym + len * 2)? as usize;
+
+        let sym_len = *d.symlen.get(sym)? as i64;
+        if offset < sym_len + 1 {
+            break;
+        }
+
+        offset -= sym_len + 1;
+        len += d.min_sym_len as usize;
+        buf64 = shl(buf64, len as u32);
+        buf64_size -= len as i32;
+
+        if buf64_size <= 32 {
+            buf64_size += 32;
+            buf64 |= shl(read_u32_be(b, ptr)? as u64, (64 - buf64_size) as u32);
+            ptr += 4;
+        }
+    }
+
+    // Children of a pair are adjacent, so find the side which holds the offset down to a leaf.
+    // `set_symlen` checked every pair is of known symbols.
+    while d.symlen[sym] != 0 {
+        let left = d.get_left(b, sym)?;
+        if offset < d.symlen[left] as i64 + 1 {
+            sym = left;
+        } else {
+            offset -= d.symlen[left] as i64 + 1;
+            sym = d.get_right(b, sym)?;
+        }
+    }
+
+    Some(d.get_left(b, sym)? as i32)
+}
+
+fn get_code(piece: Piece, player: Player) -> u8 {
+    let code = match piece {
+        Piece::Pawn => 1,
+        Piece::Knight => 2,
+        Piece::Bishop => 3,
+        Piece::Rook => 4,
+        Piece::Queen => 5,
+        Piece::King => 6
+    };
+    code | if player == Player::Black { 8 } else { 0 }
+}
+
+/// Pieces on the board by square from a1 = 0, in file codes
+fn get_tb_pieces(board: &Board) -> Vec<(usize, u8)> {
+    let mut pieces = Vec::with_capacity(TB_PIECES);
+    for sq in 0..64 {
+        if let Square::Occupied(piece, player) = board.get_by_xy(file_of(sq) as u8, 7 - rank_of(sq) as u8) {
+            pieces.push((sq, get_code(*piece, *player)));
+        }
+    }
+    pieces
+}
+
+fn get_key(board: &Board) -> [[u8; 6]; 2] {
+    let score = board.get_score();
+    let mut key = [[0; 6]; 2];
+    for player in [Player::White, Player::Black].iter() {
+        for piece in PIECES.iter() {
+            key[*player as usize][*piece as usize] = score.get_count(*player, *piece);
+        }
+    }
+    key
+}
+
+pub fn get_piece_count(board: &Board) -> usize {
+    get_key(board).iter().map(|side| side.iter().map(|n| *n as usize).sum::<usize>()).sum()
+}
+
+#[inline]
+fn is_zeroing(m: &MoveSnapshot) -> bool {
+    match m.get_description() {
+        MoveDescription::Capture(_, _, _) => true,
+        _ => matches!(m.get_src_sq(), Some((_, BeforeAfterSquares(Square::Occupied(Piece::Pawn, _), _))))
+    }
+}
+
+#[inline]
+fn is_capture(m: &MoveSnapshot) -> bool {
+    matches!(m.get_description(), MoveDescription::Capture(_, _, _))
+}
+
+fn get_legal_moves(board: &mut Board) -> Vec<MoveSnapshot> {
+    let mut temp = MoveList::new(50);
+    let mut moves = MoveList::new(50);
+    board.get_moves(&mut temp, &mut moves);
+    moves.get_v()[..moves.write_index].to_vec()
+}
+
+fn is_mate(board: &mut Board) -> bool {
+    is_checking(board, board.get_player_with_turn().get_other_player()) && get_legal_moves(board).is_empty()
+}
+
+/// The DTZ of the move before a zeroing move into a position with `wdl` for the player who made it
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0
+    }
+}
+
+/// Tablebase files of a set of directories
+pub struct Tablebases {
+    tables: Vec<Table>,
+    /// Both colourings of each table's material to its index
+    index: HashMap<[[u8; 6]; 2], usize>,
+    max_pieces: usize
+}
+
+/// One root move with the distance to zeroing after it, see `Tablebases::probe_root`
+pub struct RootMove {
+    pub m: MoveSnapshot,
+    pub wdl: Wdl,
+    pub dtz: i32
+}
+
+impl Tablebases {
+
+    /// `paths` are directories separated by `:`, or `;` on Windows, like the `SyzygyPath` UCI option.
+    /// Tables are found by their `.rtbw` files.
+    pub fn new(paths: &str) -> Self {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let mut tablebases = Self { tables: Vec::new(), index: HashMap::new(), max_pieces: 0 };
+
+        for dir in paths.split(separator).filter(|p| !p.is_empty()) {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("Can't read tablebase directory {} - {}", dir, e);
+                    continue;
+                }
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                if path.extension().map_or(true, |ext| ext != "rtbw") { continue; }
+                let name = match path.file_stem().and_then(|s| s.to_str()) {
+                    Some(name) => name,
+                    None => continue
+                };
+                if let Some(table) = Table::new(name, Path::new(dir)) {
+                    if tablebases.index.contains_key(&table.key) { continue; }
+                    let i = tablebases.tables.len();
+                    tablebases.index.insert(table.key, i);
+                    tablebases.index.insert(table.key2, i);
+                    tablebases.max_pieces = tablebases.max_pieces.max(table.piece_count);
+                    tablebases.tables.push(table);
+                }
+            }
+        }
+        tablebases
+    }
+
+    pub fn get_table_count(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Most pieces, kings included, in any found table
+    pub fn get_max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether `board` has few enough pieces and no castling, which tables don't have
+    pub fn can_probe(&self, board: &Board) -> bool {
+        let count = get_piece_count(board);
+        count <= self.max_pieces && count > 0 && !board.has_castling_rights()
+    }
+
+    /// For the player with the turn. `None` if a needed table is missing.
+    pub fn probe_wdl(&self, board: &mut Board) -> Option<Wdl> {
+        let mut state = ProbeState::Ok;
+        let wdl = self.search(board, &mut state, false);
+        if state == ProbeState::Fail { None } else { Some(wdl) }
+    }
+
+    /// Plies to the next capture or pawn move with best play, positive when winning, 0 for a draw.
+    /// Around 100 more for cursed wins and blessed losses. `None` if a needed table is missing.
+    pub fn probe_dtz(&self, board: &mut Board) -> Option<i32> {
+        let mut state = ProbeState::Ok;
+        let dtz = self.probe_dtz_state(board, &mut state);
+        if state == ProbeState::Fail { None } else { Some(dtz) }
+    }
+
+    /// Every legal move with its outcome, best first: the fastest wins by DTZ, then draws, then the slowest losses.
+    /// `None` if `board` can't be probed or a needed table is missing.
+    pub fn probe_root(&self, board: &mut Board) -> Option<Vec<RootMove>> {
+        if !self.can_probe(board) {
+            return None;
+        }
+
+        let mut root_moves = Vec::new();
+        for m in get_legal_moves(board) {
+            board.handle_move(&m, true);
+
+            let mut state = ProbeState::Ok;
+            let mut dtz = if is_zeroing(&m) {
+                dtz_before_zeroing(self.search(board, &mut state, false).negate())
+            } else {
+                let dtz = -self.probe_dtz_state(board, &mut state);
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && is_mate(board) {
+                dtz = 1;
+            }
+
+            board.handle_move(&m, false);
+            if state == ProbeState::Fail {
+                return None;
+            }
+
+            let wdl = match dtz {
+                d if d > 100 => Wdl::CursedWin,
+                d if d > 0 => Wdl::Win,
+                d if d < -100 => Wdl::BlessedLoss,
+                d if d < 0 => Wdl::Loss,
+                _ => Wdl::Draw
+            };
+            root_moves.push(RootMove { m, wdl, dtz });
+        }
+
+        // Lower DTZ is better both for faster wins and slower losses
+        root_moves.sort_by_key(|r| (std::cmp::Reverse(r.wdl), r.dtz));
+        Some(root_moves)
+    }
+
+    /// Tables may store any value where the player with the turn has a winning capture, or a worse value
+    /// where a capture draws, so captures are searched and the best of them and the stored value is the result.
+    /// For DTZ, pawn moves are searched too since DTZ tables don't store values before zeroing moves.
+    fn search(&self, board: &mut Board, state: &mut ProbeState, check_zeroing_moves: bool) -> Wdl {
+        let mut best = Wdl::Loss;
+        let moves = get_legal_moves(board);
+        let mut move_count = 0;
+
+        for m in moves.iter() {
+            if !is_capture(m) && (!check_zeroing_moves || !is_zeroing(m)) {
+                continue;
+            }
+            move_count += 1;
+
+            board.handle_move(m, true);
+            let value = self.search(board, state, false).negate();
+            board.handle_move(m, false);
+
+            if *state == ProbeState::Fail {
+                return Wdl::Draw;
+            }
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    *state = ProbeState::ZeroingBestMove;
+                    return value;
+                }
+            }
+        }
+
+        // With every move searched, the stored value can't be trusted
+        let no_more_moves = move_count > 0 && move_count == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            let value = self.probe_table(board, state, TableType::Wdl, Wdl::Draw);
+            if *state == ProbeState::Fail {
+                return Wdl::Draw;
+            }
+            Wdl::from_i32(value)
+        };
+
+        if best >= value {
+            *state = if best > Wdl::Draw || no_more_moves { ProbeState::ZeroingBestMove } else { ProbeState::Ok };
+            return best;
+        }
+        *state = ProbeState::Ok;
+        value
+    }
+
+    fn probe_dtz_state(&self, board: &mut Board, state: &mut ProbeState) -> i32 {
+        let wdl = self.search(board, state, true);
+        if *state == ProbeState::Fail || wdl == Wdl::Draw {
+            return 0;
+        }
+        if *state == ProbeState::ZeroingBestMove {
+            return dtz_before_zeroing(wdl);
+        }
+
+        let dtz = self.probe_table(board, state, TableType::Dtz, wdl);
+        if *state == ProbeState::Fail {
+            return 0;
+        }
+        if *state != ProbeState::ChangeStm {
+            let cursed = wdl == Wdl::BlessedLoss || wdl == Wdl::CursedWin;
+            return (dtz + if cursed { 100 } else { 0 }) * (wdl as i32).signum();
+        }
+
+        // The table is for the other player with the turn, so find the move which is best by their DTZ
+        let mut min_dtz = 0xFFFF;
+        for m in get_legal_moves(board) {
+            let zeroing = is_zeroing(&m);
+            board.handle_move(&m, true);
+
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(board, state, false))
+            } else {
+                -self.probe_dtz_state(board, state)
+            };
+            if dtz == 1 && is_mate(board) {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == (wdl as i32).signum() {
+                min_dtz = dtz;
+            }
+
+            board.handle_move(&m, false);
+            if *state == ProbeState::Fail {
+                return 0;
+            }
+        }
+
+        // No legal moves means mate
+        if min_dtz == 0xFFFF { -1 } else { min_dtz }
+    }
+
+    /// The raw table value, a WDL from -2 to 2 or a DTZ in plies
+    fn probe_table(&self, board: &Board, state: &mut ProbeState, t: TableType, wdl: Wdl) -> i32 {
+        let key = get_key(board);
+        if get_piece_count(board) == 2 {
+            return 0;
+        }
+
+        let entry = match self.index.get(&key) {
+            Some(i) => &self.tables[*i],
+            None => {
+                *state = ProbeState::Fail;
+                return 0;
+            }
+        };
+        let data = match entry.get_data(t) {
+            Some(data) => data,
+            None => {
+                *state = ProbeState::Fail;
+                return 0;
+            }
+        };
+
+        let maps = &*MAPS;
+        let b = &data.bytes;
+        let pieces_on_board = get_tb_pieces(board);
+        let black_to_move = board.get_player_with_turn() == Player::Black;
+
+        // Symmetric tables only store white to move, and others store white as the stronger side
+        // as in the file name, so otherwise the colours are switched and the squares flipped
+        let symmetric_black_to_move = entry.is_symmetric() && black_to_move;
+        let black_stronger = key != entry.key;
+        let flip = symmetric_black_to_move || black_stronger;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip ^ black_to_move) as usize;
+
+        let sides = entry.get_sides(t);
+        let get = |stm: usize, f: usize| &data.items[(stm % sides) * 4 + if entry.has_pawns { f } else { 0 }];
+
+        let mut squares = [0usize; TB_PIECES];
+        let mut pieces = [0u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_pawns_cnt = 0;
+        let mut tb_file = 0;
+        let mut lead_pawn_color = None;
+
+        // Tables with pawns are split by the file of the leading pawn, the one with the highest `maps.pawns`
+        if entry.has_pawns {
+            let pc = get(0, 0).pieces[0] ^ flip_color;
+            lead_pawn_color = Some(pc);
+            for (sq, code) in pieces_on_board.iter() {
+                if *code == pc {
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead_pawns_cnt = size;
+
+            let mut lead = 0;
+            for i in 1..lead_pawns_cnt {
+                if maps.pawns[squares[i]] > maps.pawns[squares[lead]] {
+                    lead = i;
+                }
+            }
+            squares.swap(0, lead);
+            let file = file_of(squares[0]);
+            tb_file = file.min(7 - file);
+        }
+
+        if t == TableType::Dtz {
+            let flags = get(stm, tb_file).flags;
+            if (flags & FLAG_STM) as usize != stm && !(entry.is_symmetric() && !entry.has_pawns) {
+                *state = ProbeState::ChangeStm;
+                return 0;
+            }
+        }
+
+        for (sq, code) in pieces_on_board.iter() {
+            if Some(*code) == lead_pawn_color { continue; }
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = code ^ flip_color;
+            size += 1;
+        }
+
+        let d = get(stm, tb_file);
+
+        // Same order as the table's pieces
+        for i in lead_pawns_cnt..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        let idx = entry.get_index(d, &mut squares, size, lead_pawns_cnt);
+
+        let value = decompress_pairs(d, b, idx).and_then(|value| match t {
+            TableType::Wdl => Some(value - 2),
+            TableType::Dtz => self.map_dtz(entry, data, tb_file, value, wdl)
+        });
+        match value {
+            Some(value) => value,
+            None => {
+                *state = ProbeState::Fail;
+                0
+            }
+        }
+    }
+
+    /// DTZ values are stored by frequency for each WDL, in moves or plies
+    fn map_dtz(&self, entry: &Table, data: &TableData, f: usize, mut value: i32, wdl: Wdl) -> Option<i32> {
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+        let d = &data.items[if entry.has_pawns { f } else { 0 }];
+        let map_idx = d.map_idx[WDL_MAP[(wdl as i32 + 2) as usize]] as usize;
+
+        if d.flags & FLAG_MAPPED != 0 {
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16_le(&data.bytes, data.map + 2 * (map_idx + value as usize))? as i32
+            } else {
+                read_u8(&data.bytes, data.map + map_idx + value as usize)? as i32
+            };
+        }
+
+        if (wdl == Wdl::Win && d.flags & FLAG_WIN_PLIES == 0)
+            || (wdl == Wdl::Loss && d.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == Wdl::CursedWin
+            || wdl == Wdl::BlessedLoss {
+            value *= 2;
+        }
+        Some(value + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dtm::{self, Dtm, DtmTables, Material};
+
+    /// Bytes per block and values per sparse index entry of written tables, as powers of 2
+    const BLOCK_SIZE_LOG2: u8 = 10;
+    const SPAN_LOG2: u8 = 10;
+
+    /// Sections of one player with the turn, in the order they go in a file
+    struct Pairs {
+        sizes: Vec<u8>,
+        sparse_index: Vec<u8>,
+        block_length: Vec<u8>,
+        data: Vec<u8>
+    }
+
+    /// Encodes `values` with one symbol per value, all of the same length, so there is no pairing to do
+    fn write_pairs(flags: u8, values: &[u16]) -> Pairs {
+        let mut syms = values.to_vec();
+        syms.sort_unstable();
+        syms.dedup();
+        let sym_len = (64 - (syms.len() as u64 - 1).leading_zeros()).max(1) as usize;
+        let block_size = 1usize << BLOCK_SIZE_LOG2;
+        let span = 1usize << SPAN_LOG2;
+        // Room for the 64 bits the decoder reads ahead
+        let per_block = (block_size * 8 - 64) / sym_len;
+        let num_blocks = (values.len() + per_block - 1) / per_block;
+        let sparse_index_size = (values.len() + span - 1) / span;
+        // The last sparse entry can be in a block past the values, which still needs a length
+        let last_block = ((sparse_index_size - 1) * span + span / 2) / per_block;
+        let padding = (last_block + 1).saturating_sub(num_blocks);
+
+        let mut sizes = vec![flags, BLOCK_SIZE_LOG2, SPAN_LOG2, padding as u8];
+        sizes.extend(&(num_blocks as u32).to_le_bytes());
+        sizes.extend(&[sym_len as u8, sym_len as u8]);
+        sizes.extend(&0u16.to_le_bytes());
+        sizes.extend(&(syms.len() as u16).to_le_bytes());
+        for sym in syms.iter() {
+            // Leaves have a right of 0xFFF and the value on the left
+            sizes.extend(&[*sym as u8, (*sym >> 8) as u8 | 0xF0, 0xFF]);
+        }
+        if syms.len() & 1 != 0 {
+            sizes.push(0);
+        }
+
+        let mut sparse_index = Vec::new();
+        for k in 0..sparse_index_size {
+            let i = k * span + span / 2;
+            sparse_index.extend(&((i / per_block) as u32).to_le_bytes());
+            sparse_index.extend(&((i % per_block) as u16).to_le_bytes());
+        }
+
+        let mut block_length = Vec::new();
+        for _ in 0..num_blocks + padding {
+            block_length.extend(&((per_block - 1) as u16).to_le_bytes());
+        }
+
+        let mut data = vec![0u8; num_blocks * block_size];
+        for (i, value) in values.iter().enumerate() {
+            let code = syms.binary_search(value).unwrap();
+            let start = (i / per_block) * block_size * 8 + (i % per_block) * sym_len;
+            for bit in 0..sym_len {
+                if code >> (sym_len - 1 - bit) & 1 != 0 {
+                    let pos = start + bit;
+                    data[pos / 8] |= 0x80 >> (pos % 8);
+                }
+            }
+        }
+
+        Pairs { sizes, sparse_index, block_length, data }
+    }
+
+    /// A table without pawns whose pieces all go in the leading group, with one `Pairs` per player with the turn
+    fn write_table(path: &Path, magic: [u8; 4], pieces: &[u8], sides: &[Pairs]) {
+        let mut b = magic.to_vec();
+        b.push(if magic == WDL_MAGIC && sides.len() == 2 { 1 } else { 0 });
+        b.push(0);
+        b.extend(pieces.iter().map(|code| code | code << 4));
+        b.resize(b.len() + (b.len() & 1), 0);
+
+        for side in sides.iter() {
+            b.extend(&side.sizes);
+        }
+        for side in sides.iter() {
+            b.extend(&side.sparse_index);
+        }
+        for side in sides.iter() {
+            b.extend(&side.block_length);
+        }
+        for side in sides.iter() {
+            b.resize((b.len() + 0x3F) & !0x3F, 0);
+            b.extend(&side.data);
+        }
+        fs::write(path, b).unwrap();
+    }
+
+    /// FEN of `pieces` on squares from a1 = 0
+    fn get_fen(pieces: &[(usize, char)], black_to_move: bool) -> String {
+        let mut rows = Vec::new();
+        for rank in (0..8).rev() {
+            let mut row = String::new();
+            let mut blanks = 0;
+            for file in 0..8 {
+                match pieces.iter().find(|(sq, _)| *sq == rank * 8 + file) {
+                    Some((_, c)) => {
+                        if blanks > 0 {
+                            row.push_str(&blanks.to_string());
+                            blanks = 0;
+                        }
+                        row.push(*c);
+                    },
+                    None => blanks += 1
+                }
+            }
+            if blanks > 0 {
+                row.push_str(&blanks.to_string());
+            }
+            rows.push(row);
+        }
+        format!("{} {} - - 0 1", rows.join("/"), if black_to_move { 'b' } else { 'w' })
+    }
+
+    fn are_adjacent(a: usize, b: usize) -> bool {
+        (file_of(a) as i32 - file_of(b) as i32).abs() <= 1 && (rank_of(a) as i32 - rank_of(b) as i32).abs() <= 1
+    }
+
+    /// Writes KRvK from depth to mate tables, where without pawns the distance to zeroing is the distance to mate
+    fn write_krvk(dir: &Path, oracle: &DtmTables) {
+        let table = Table::new("KRvK", dir).unwrap();
+        let mut d = PairsData::default();
+        d.pieces[..3].copy_from_slice(&[get_code(Piece::King, Player::White), get_code(Piece::Rook, Player::White), get_code(Piece::King, Player::Black)]);
+        table.set_groups(&mut d, [0, 0xF], 0).unwrap();
+
+        let size = d.group_idx[1] as usize;
+        let mut wdl = [vec![2u16; size], vec![2u16; size]];
+        let mut dtz = vec![0u16; size];
+        let mut done = vec![false; size];
+        for wk in 0..64 {
+            for wr in (0..64).filter(|sq| *sq != wk) {
+                for bk in (0..64).filter(|sq| *sq != wk && *sq != wr && !are_adjacent(*sq, wk)) {
+                    let mut squares = [0; TB_PIECES];
+                    squares[..3].copy_from_slice(&[wk, wr, bk]);
+                    let idx = table.get_index(&d, &mut squares, 3, 0) as usize;
+                    if done[idx] { continue; }
+                    done[idx] = true;
+
+                    let pieces = [(wk, 'K'), (wr, 'R'), (bk, 'k')];
+                    for black_to_move in [false, true].iter() {
+                        let board = Board::from_fen(&get_fen(&pieces, *black_to_move)).unwrap();
+                        match oracle.probe(&board) {
+                            Some(Dtm::Win(plies)) => {
+                                wdl[*black_to_move as usize][idx] = 4;
+                                if !black_to_move {
+                                    dtz[idx] = plies as u16 - 1;
+                                }
+                            },
+                            Some(Dtm::Loss(_)) => wdl[*black_to_move as usize][idx] = 0,
+                            _ => ()
+                        }
+                    }
+                }
+            }
+        }
+
+        write_table(&dir.join("KRvK.rtbw"), WDL_MAGIC, &d.pieces[..3], &[write_pairs(0, &wdl[0]), write_pairs(0, &wdl[1])]);
+        write_table(&dir.join("KRvK.rtbz"), DTZ_MAGIC, &d.pieces[..3], &[write_pairs(FLAG_WIN_PLIES | FLAG_LOSS_PLIES, &dtz)]);
+    }
+
+    fn probe(tablebases: &Tablebases, fen: &str) -> (Wdl, i32) {
+        let mut board = Board::from_fen(fen).unwrap();
+        (tablebases.probe_wdl(&mut board).unwrap(), tablebases.probe_dtz(&mut board).unwrap())
+    }
+
+    #[test]
+    fn krvk() {
+        let mut oracle = DtmTables::new();
+        dtm::generate(&Material::from_name("KRvK").unwrap(), 1, &mut oracle);
+        let dir = std::env::temp_dir().join(format!("chess_bs_syzygy_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        write_krvk(&dir, &oracle);
+        let tablebases = Tablebases::new(dir.to_str().unwrap());
+        assert_eq!(tablebases.get_table_count(), 1);
+
+        // Mate in 1, mated, stalemate, and a hanging rook
+        assert_eq!(probe(&tablebases, "7k/8/6K1/8/8/8/8/R7 w - - 0 1"), (Wdl::Win, 1));
+        assert_eq!(probe(&tablebases, "R6k/8/6K1/8/8/8/8/8 b - - 0 1"), (Wdl::Loss, -1));
+        assert_eq!(probe(&tablebases, "k1K5/7R/8/8/8/8/8/8 b - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe(&tablebases, "8/8/8/8/8/8/6kR/K7 b - - 0 1"), (Wdl::Draw, 0));
+        // Black has the rook, and white is mated
+        assert_eq!(probe(&tablebases, "r6K/8/6k1/8/8/8/8/8 w - - 0 1"), (Wdl::Loss, -1));
+
+        for wk in 0..64 {
+            for wr in (0..64).filter(|sq| *sq != wk) {
+                for bk in (0..64).filter(|sq| *sq != wk && *sq != wr && !are_adjacent(*sq, wk)) {
+                    // A sample, with either colour having the rook
+                    if ((wk * 64 + wr) * 64 + bk) % 499 != 0 { continue; }
+                    for (pieces, black_to_move) in [
+                        ([(wk, 'K'), (wr, 'R'), (bk, 'k')], false),
+                        ([(wk, 'K'), (wr, 'R'), (bk, 'k')], true),
+                        ([(wk, 'k'), (wr, 'r'), (bk, 'K')], false),
+                        ([(wk, 'k'), (wr, 'r'), (bk, 'K')], true)
+                    ].iter() {
+                        let fen = get_fen(pieces, *black_to_move);
+                        let mut board = Board::from_fen(&fen).unwrap();
+                        // Can't be reached with the player without the turn in check
+                        let player = board.get_player_with_turn();
+                        if is_checking(&mut board, player) { continue; }
+                        let expected = match oracle.probe(&board).unwrap() {
+                            Dtm::Win(plies) => (Wdl::Win, plies as i32),
+                            Dtm::Draw => (Wdl::Draw, 0),
+                            Dtm::Loss(plies) => (Wdl::Loss, -(plies as i32).max(1))
+                        };
+                        assert_eq!(probe(&tablebases, &fen), expected, "{}", fen);
+                    }
+                }
+            }
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
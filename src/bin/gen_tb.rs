@@ -0,0 +1,86 @@
+//! Generates depth to mate tables for the `dtm` module, eg. `gen_tb KBNK`, along with the tables
+//! it reaches by captures and promotions. Up to 4 pieces, where a table takes up to 11MB and a few minutes in release.
+//!
+//! Usage: gen_tb <material> [--out <dir>] [--threads <n>] [--all]
+//! Writes `<dir>/<material>.cbtb`, and with `--all` also the tables it reaches.
+
+use std::env;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Instant;
+use chess_bs::ai::dtm::*;
+
+struct Options {
+    material: String,
+    out_dir: String,
+    threads: usize,
+    all: bool
+}
+
+fn parse_options() -> Result<Options, String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = Options {
+        material: String::new(),
+        out_dir: String::from("."),
+        threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        all: false
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned();
+        let missing = || format!("Missing value for {}", args[i]);
+        match args[i].as_str() {
+            "--out" => options.out_dir = value.ok_or_else(missing)?,
+            "--threads" => options.threads = value.ok_or_else(missing)?.parse::<usize>().map_err(|_| "Bad --threads")?.max(1),
+            "--all" => {
+                options.all = true;
+                i += 1;
+                continue;
+            },
+            material if options.material.is_empty() && !material.starts_with("--") => {
+                options.material = String::from(material);
+                i += 1;
+                continue;
+            },
+            other => return Err(format!("Unknown argument {}", other))
+        };
+        i += 2;
+    }
+
+    if options.material.is_empty() {
+        return Err(String::from("Usage: gen_tb <material> [--out <dir>] [--threads <n>] [--all]"));
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let material = Material::from_name(&options.material).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let start = Instant::now();
+    let mut tables = DtmTables::new();
+    generate(&material, options.threads, &mut tables);
+    println!("Generated {} tables in {}s", tables.get_table_count(), start.elapsed().as_secs_f32());
+
+    for table in tables.get_tables() {
+        let name = table.get_material().to_string();
+        if !options.all && name != material.to_string() { continue; }
+
+        let (wins, draws, losses, longest) = table.get_stats();
+        println!("{} - {} wins, {} draws, {} losses for the player with the turn, longest mate {} plies", name, wins, draws, losses, longest);
+
+        let path = Path::new(&options.out_dir).join(format!("{}.cbtb", name));
+        if let Err(e) = table.save(&path.to_string_lossy()) {
+            eprintln!("Can't save {} - {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
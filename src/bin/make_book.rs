@@ -0,0 +1,143 @@
+//! Builds a Polyglot opening book from PGN files by replaying every game and counting the moves played
+//! from each position, with wins, draws and losses from the view of the player moving.
+//! Moves are weighted by 2 per win and 1 per draw, scaled per position to fit the 16 bit weights.
+//!
+//! Usage: make_book <pgn>... [--out <bin>] [--max-ply <n>] [--min-count <n>]
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+use chess_bs::ai::book::*;
+use chess_bs::game::entities::*;
+use chess_bs::game::pgn::*;
+
+const DEFAULT_OUT: &str = "book.bin";
+const DEFAULT_MAX_PLY: usize = 20;
+const DEFAULT_MIN_COUNT: u32 = 3;
+
+struct Options {
+    pgn_paths: Vec<String>,
+    out_path: String,
+    max_ply: usize,
+    min_count: u32
+}
+
+#[derive(Default)]
+struct MoveStats {
+    count: u32,
+    wins: u32,
+    draws: u32,
+    losses: u32
+}
+
+impl MoveStats {
+    fn get_score(&self) -> u32 {
+        self.wins * 2 + self.draws
+    }
+}
+
+fn parse_options() -> Result<Options, String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = Options {
+        pgn_paths: Vec::new(),
+        out_path: String::from(DEFAULT_OUT),
+        max_ply: DEFAULT_MAX_PLY,
+        min_count: DEFAULT_MIN_COUNT
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned();
+        let missing = || format!("Missing value for {}", args[i]);
+        match args[i].as_str() {
+            "--out" => options.out_path = value.ok_or_else(missing)?,
+            "--max-ply" => options.max_ply = value.ok_or_else(missing)?.parse::<usize>().map_err(|_| "Bad --max-ply")?,
+            "--min-count" => options.min_count = value.ok_or_else(missing)?.parse::<u32>().map_err(|_| "Bad --min-count")?.max(1),
+            path if !path.starts_with("--") => {
+                options.pgn_paths.push(String::from(path));
+                i += 1;
+                continue;
+            },
+            other => return Err(format!("Unknown argument {}", other))
+        };
+        i += 2;
+    }
+
+    if options.pgn_paths.is_empty() {
+        return Err(String::from("Usage: make_book <pgn>... [--out <bin>] [--max-ply <n>] [--min-count <n>]"));
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let mut stats: HashMap<u64, HashMap<u16, MoveStats>> = HashMap::new();
+    let (mut game_count, mut incomplete_count) = (0, 0);
+
+    for path in options.pgn_paths.iter() {
+        let text = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Can't read {} - {}", path, e);
+            process::exit(1);
+        });
+
+        for game in parse_pgn(&text).iter() {
+            game_count += 1;
+            let mut ply = 0;
+            let complete = game.replay(|board, m| {
+                ply += 1;
+                if ply > options.max_ply { return; }
+                let mv = match encode_move(m) {
+                    Some(mv) => mv,
+                    None => return
+                };
+
+                let s = stats.entry(get_key(board)).or_default().entry(mv).or_default();
+                s.count += 1;
+                match (game.result, board.get_player_with_turn()) {
+                    (GameResult::Draw, _) => s.draws += 1,
+                    (GameResult::WhiteWins, Player::White) | (GameResult::BlackWins, Player::Black) => s.wins += 1,
+                    (GameResult::WhiteWins, Player::Black) | (GameResult::BlackWins, Player::White) => s.losses += 1,
+                    (GameResult::Unknown, _) => ()
+                };
+            });
+            if !complete {
+                incomplete_count += 1;
+            }
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (key, moves) in stats.iter() {
+        let moves: Vec<(&u16, &MoveStats)> = moves.iter().filter(|(_, s)| s.count >= options.min_count).collect();
+        let max_score = moves.iter().map(|(_, s)| s.get_score()).max().unwrap_or(0);
+        for (mv, s) in moves.iter() {
+            // Moves which only lost keep the lowest weight, so they are still known but never picked
+            let weight = if max_score > u16::MAX as u32 {
+                (s.get_score() as u64 * u16::MAX as u64 / max_score as u64) as u16
+            } else {
+                s.get_score() as u16
+            };
+            entries.push(BookEntry { key: *key, mv: **mv, weight, learn: 0 });
+        }
+    }
+    // Best moves first within a position as Polyglot tools expect, and the same book for the same games
+    entries.sort_by_key(|e| (e.key, Reverse(e.weight), e.mv));
+
+    let (wins, draws, losses) = stats.values().flat_map(|moves| moves.values())
+        .fold((0, 0, 0), |(w, d, l), s| (w + s.wins, d + s.draws, l + s.losses));
+    println!("Read {} games, {} stopped early at an unreadable or unsupported move", game_count, incomplete_count);
+    println!("Counted {} positions, {} wins, {} draws, {} losses for the player moving", stats.len(), wins, draws, losses);
+
+    let book = Book::new(entries);
+    println!("Writing {} entries to {}", book.get_entries().len(), options.out_path);
+    if let Err(e) = book.save(&options.out_path) {
+        eprintln!("Can't save {} - {}", options.out_path, e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,186 @@
+//! Mines tactical puzzles from PGN games or engine self-play, see `ai::puzzles`. Every position from `--min-ply` on
+//! is searched, and puzzles are written as CSV lines of the FEN, solution moves in coordinate notation and themes.
+//! Self-play games are played at `--skill`, whose mistakes are what leave tactics to find.
+//!
+//! Usage: make_puzzles [<pgn>...] [--self-play <games>] [--skill <level>] [--depth <n>] [--min-ply <n>] [--max-puzzles <n>] [--out <csv>]
+
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::process;
+use std::time::Instant;
+use chess_bs::ai::*;
+use chess_bs::ai::skill::*;
+use chess_bs::ai::puzzles::*;
+use chess_bs::game::board::*;
+use chess_bs::game::move_list::*;
+use chess_bs::game::pgn::*;
+
+const DEFAULT_OUT: &str = "puzzles.csv";
+const DEFAULT_DEPTH: u8 = 5;
+const DEFAULT_MIN_PLY: usize = 8;
+const DEFAULT_SKILL: u8 = 6;
+/// Self-play games are cut off here, since repetitions aren't detected
+const MAX_SELF_PLAY_PLIES: usize = 200;
+
+struct Options {
+    pgn_paths: Vec<String>,
+    self_play_games: usize,
+    skill: u8,
+    depth: u8,
+    min_ply: usize,
+    max_puzzles: Option<usize>,
+    out_path: String
+}
+
+fn parse_options() -> Result<Options, String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = Options {
+        pgn_paths: Vec::new(),
+        self_play_games: 0,
+        skill: DEFAULT_SKILL,
+        depth: DEFAULT_DEPTH,
+        min_ply: DEFAULT_MIN_PLY,
+        max_puzzles: None,
+        out_path: String::from(DEFAULT_OUT)
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned();
+        let missing = || format!("Missing value for {}", args[i]);
+        match args[i].as_str() {
+            "--self-play" => options.self_play_games = value.ok_or_else(missing)?.parse::<usize>().map_err(|_| "Bad --self-play")?,
+            "--skill" => options.skill = value.ok_or_else(missing)?.parse::<u8>().map_err(|_| "Bad --skill")?.min(MAX_SKILL_LEVEL),
+            "--depth" => options.depth = value.ok_or_else(missing)?.parse::<u8>().map_err(|_| "Bad --depth")?.max(1),
+            "--min-ply" => options.min_ply = value.ok_or_else(missing)?.parse::<usize>().map_err(|_| "Bad --min-ply")?,
+            "--max-puzzles" => options.max_puzzles = Some(value.ok_or_else(missing)?.parse::<usize>().map_err(|_| "Bad --max-puzzles")?),
+            "--out" => options.out_path = value.ok_or_else(missing)?,
+            path if !path.starts_with("--") => {
+                options.pgn_paths.push(String::from(path));
+                i += 1;
+                continue;
+            },
+            other => return Err(format!("Unknown argument {}", other))
+        };
+        i += 2;
+    }
+
+    if options.pgn_paths.is_empty() && options.self_play_games == 0 {
+        return Err(String::from("Usage: make_puzzles [<pgn>...] [--self-play <games>] [--skill <level>] [--depth <n>] [--min-ply <n>] [--max-puzzles <n>] [--out <csv>]"));
+    }
+    Ok(options)
+}
+
+struct Miner<'a> {
+    options: &'a Options,
+    ai: Ai,
+    out: BufWriter<File>,
+    seen_fens: HashSet<String>,
+    positions: usize,
+    puzzles: usize
+}
+
+impl <'a> Miner<'a> {
+
+    fn is_done(&self) -> bool {
+        self.options.max_puzzles.map_or(false, |max| self.puzzles >= max)
+    }
+
+    /// Searches the positions of one game, skipping positions inside a found puzzle's solution
+    fn scan_game(&mut self, boards: &[Board]) {
+        let mut ply = self.options.min_ply;
+        while ply < boards.len() && !self.is_done() {
+            let board = &boards[ply];
+            self.positions += 1;
+            ply += 1;
+
+            let puzzle = match self.ai.find_puzzle(board, self.options.depth) {
+                Some(puzzle) => puzzle,
+                None => continue
+            };
+            if !self.seen_fens.insert(puzzle.fen.clone()) { continue; }
+
+            println!("{}", puzzle.to_csv_line());
+            writeln!(self.out, "{}", puzzle.to_csv_line()).unwrap_or_else(|e| {
+                eprintln!("Can't write {} - {}", self.options.out_path, e);
+                process::exit(1);
+            });
+            self.puzzles += 1;
+            ply += puzzle.moves.len();
+        }
+    }
+}
+
+/// Boards before each move of a self-play game, and the final board
+fn play_game(ai: &mut Ai, skill: u8) -> Vec<Board> {
+    let mut board = Board::new();
+    let mut boards = vec![board.clone()];
+    let mut temp = MoveList::new(50);
+    let mut moves = MoveList::new(50);
+
+    while boards.len() < MAX_SELF_PLAY_PLIES {
+        moves.write_index = 0;
+        board.get_moves(&mut temp, &mut moves);
+        if moves.write_index == 0 { break; }
+
+        // No move is chosen when every move is mated, but the game still has to be played out
+        let m = ai.choose_move(&board, SkillLevel::get(skill)).unwrap_or_else(|| moves.get_v()[0].clone());
+        board.handle_move(&m, true);
+        boards.push(board.clone());
+    }
+    boards
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let out = File::create(&options.out_path).unwrap_or_else(|e| {
+        eprintln!("Can't create {} - {}", options.out_path, e);
+        process::exit(1);
+    });
+
+    let start = Instant::now();
+    let mut miner = Miner {
+        options: &options,
+        ai: Ai::new(),
+        out: BufWriter::new(out),
+        seen_fens: HashSet::new(),
+        positions: 0,
+        puzzles: 0
+    };
+    writeln!(miner.out, "{}", CSV_HEADER).expect("Can't write header");
+
+    for path in options.pgn_paths.iter() {
+        let text = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Can't read {} - {}", path, e);
+            process::exit(1);
+        });
+        for game in parse_pgn(&text).iter() {
+            if miner.is_done() { break; }
+            let mut boards = Vec::new();
+            let mut last = None;
+            game.replay(|board, m| {
+                boards.push(board.clone());
+                let mut after = board.clone();
+                after.handle_move(m, true);
+                last = Some(after);
+            });
+            boards.extend(last);
+            miner.scan_game(&boards);
+        }
+    }
+
+    for i in 0..options.self_play_games {
+        if miner.is_done() { break; }
+        let boards = play_game(&mut miner.ai, options.skill);
+        println!("Self-play game {} - {} plies", i + 1, boards.len() - 1);
+        miner.scan_game(&boards);
+    }
+
+    miner.out.flush().expect("Can't write puzzles");
+    println!("Found {} puzzles in {} positions in {}s", miner.puzzles, miner.positions, start.elapsed().as_secs_f32());
+}
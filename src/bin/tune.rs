@@ -0,0 +1,225 @@
+//! Texel style tuner for `EvalParams`. Reads quiet positions labelled with game results,
+//! one per line as a FEN followed by the result, eg. `<fen> 1-0`, `<fen> c9 "1/2-1/2";` or `<fen> [0.0]`.
+//! Minimizes the squared error between the results and the static evals mapped through a sigmoid,
+//! by local search one param at a time, writing the params after every improving pass.
+//!
+//! Usage: tune <positions> [--params <json>] [--out <json>] [--passes <n>] [--step <pawns>] [--threads <n>]
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process;
+use std::thread;
+use chess_bs::ai::*;
+use chess_bs::ai::eval_params::*;
+use chess_bs::game::board::*;
+
+const DEFAULT_OUT: &str = "tuned_params.json";
+const DEFAULT_PASSES: usize = 100;
+const DEFAULT_STEP: f32 = 0.05;
+/// Steps are halved after failing, down to this fraction of the initial step
+const MIN_STEP_FRACTION: f32 = 1. / 8.;
+
+struct Options {
+    positions_path: String,
+    params_path: Option<String>,
+    out_path: String,
+    passes: usize,
+    step: f32,
+    threads: usize
+}
+
+/// Result from white's point of view, 1 for a win, 0.5 for a draw
+struct Position(Board, f32);
+
+fn parse_options() -> Result<Options, String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = Options {
+        positions_path: String::new(),
+        params_path: None,
+        out_path: String::from(DEFAULT_OUT),
+        passes: DEFAULT_PASSES,
+        step: DEFAULT_STEP,
+        threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned();
+        let missing = || format!("Missing value for {}", args[i]);
+        match args[i].as_str() {
+            "--params" => options.params_path = Some(value.ok_or_else(missing)?),
+            "--out" => options.out_path = value.ok_or_else(missing)?,
+            "--passes" => options.passes = value.ok_or_else(missing)?.parse().map_err(|_| "Bad --passes")?,
+            "--step" => options.step = value.ok_or_else(missing)?.parse().map_err(|_| "Bad --step")?,
+            "--threads" => options.threads = value.ok_or_else(missing)?.parse::<usize>().map_err(|_| "Bad --threads")?.max(1),
+            path if options.positions_path.is_empty() && !path.starts_with("--") => {
+                options.positions_path = String::from(path);
+                i += 1;
+                continue;
+            },
+            other => return Err(format!("Unknown argument {}", other))
+        };
+        i += 2;
+    }
+
+    if options.positions_path.is_empty() {
+        return Err(String::from("Usage: tune <positions> [--params <json>] [--out <json>] [--passes <n>] [--step <pawns>] [--threads <n>]"));
+    }
+    Ok(options)
+}
+
+fn parse_result(token: &str) -> Option<f32> {
+    match token.trim_matches(|c| c == '"' || c == ';' || c == '[' || c == ']' || c == '(' || c == ')') {
+        "1-0" | "1.0" | "1" => Some(1.),
+        "0-1" | "0.0" | "0" => Some(0.),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        _ => None
+    }
+}
+
+fn parse_position(line: &str) -> Option<Position> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let result = parse_result(tokens.last()?)?;
+    let board = Board::from_fen(&tokens[..tokens.len() - 1].join(" ")).ok()?;
+    Some(Position(board, result))
+}
+
+fn read_positions(path: &str) -> io::Result<Vec<Position>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut positions = Vec::new();
+    let mut skipped = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() { continue; }
+        match parse_position(&line) {
+            Some(position) => positions.push(position),
+            None => skipped += 1
+        }
+    }
+    if skipped > 0 {
+        eprintln!("Skipped {} unreadable lines", skipped);
+    }
+    Ok(positions)
+}
+
+/// Expected score for white from an eval in pawns
+#[inline]
+fn sigmoid(k: f32, eval: f32) -> f32 {
+    1. / (1. + 10f32.powf(-k * eval * 100. / 400.))
+}
+
+/// Static evals by white's point of view, split across threads
+fn evaluate_all(positions: &[Position], params: &EvalParams, threads: usize) -> Vec<f32> {
+    let chunk_len = (positions.len() + threads - 1) / threads.max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = positions.chunks(chunk_len.max(1)).map(|chunk| {
+            let params = params.clone();
+            scope.spawn(move || {
+                let mut evaluator = StaticEvaluator::new(params);
+                chunk.iter().map(|Position(board, _)| evaluator.evaluate(board)).collect::<Vec<f32>>()
+            })
+        }).collect();
+        handles.into_iter().flat_map(|h| h.join().expect("Eval thread panicked")).collect()
+    })
+}
+
+fn get_error(positions: &[Position], evals: &[f32], k: f32) -> f64 {
+    let total: f64 = positions.iter().zip(evals.iter())
+        .map(|(Position(_, result), eval)| (*result - sigmoid(k, *eval)) as f64)
+        .map(|e| e * e)
+        .sum();
+    total / positions.len() as f64
+}
+
+/// The sigmoid scale which best fits the untuned evals, so tuning changes the evals rather than the scale
+fn find_k(positions: &[Position], evals: &[f32]) -> f32 {
+    let mut best_k = 1.;
+    let mut best_error = get_error(positions, evals, best_k);
+    let mut step = 0.1;
+    for _ in 0..4 {
+        let start = best_k;
+        for i in -10..=10 {
+            let k = start + i as f32 * step;
+            if k <= 0. { continue; }
+            let error = get_error(positions, evals, k);
+            if error < best_error {
+                best_error = error;
+                best_k = k;
+            }
+        }
+        step /= 10.;
+    }
+    best_k
+}
+
+fn tune(options: &Options, positions: &[Position], mut params: EvalParams) -> io::Result<EvalParams> {
+    let evals = evaluate_all(positions, &params, options.threads);
+    let k = find_k(positions, &evals);
+    let mut best_error = get_error(positions, &evals, k);
+    eprintln!("K - {}, initial error - {}", k, best_error);
+
+    let min_step = options.step * MIN_STEP_FRACTION;
+    let mut steps = vec![options.step; EvalParams::NAMES.len()];
+
+    for pass in 0..options.passes {
+        let mut improved = false;
+
+        for (i, name) in EvalParams::NAMES.iter().enumerate() {
+            let original = params.get(name).unwrap_or(0.);
+            let mut param_improved = false;
+
+            for direction in [1., -1.].iter() {
+                params.set(name, original + direction * steps[i]);
+                let error = get_error(positions, &evaluate_all(positions, &params, options.threads), k);
+                if error < best_error {
+                    best_error = error;
+                    param_improved = true;
+                    break;
+                }
+            }
+
+            if param_improved {
+                improved = true;
+            } else {
+                params.set(name, original);
+                steps[i] = (steps[i] / 2.).max(min_step);
+            }
+        }
+
+        eprintln!("Pass {} - error {}", pass + 1, best_error);
+        if improved {
+            params.save(&options.out_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        } else if steps.iter().all(|step| *step <= min_step) {
+            break;
+        }
+    }
+    Ok(params)
+}
+
+fn main() -> io::Result<()> {
+    let options = parse_options().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let params = match &options.params_path {
+        Some(path) => EvalParams::load(path).unwrap_or_else(|e| {
+            eprintln!("Can't load {} - {}", path, e);
+            process::exit(1);
+        }),
+        None => EvalParams::default()
+    };
+
+    let positions = read_positions(&options.positions_path)?;
+    if positions.is_empty() {
+        eprintln!("No positions in {}", options.positions_path);
+        process::exit(1);
+    }
+    eprintln!("{} positions, {} params, {} threads", positions.len(), EvalParams::NAMES.len(), options.threads);
+
+    let params = tune(&options, &positions, params)?;
+    params.save(&options.out_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    eprintln!("Wrote {}", options.out_path);
+    Ok(())
+}
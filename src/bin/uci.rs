@@ -0,0 +1,514 @@
+//! Minimal UCI front end for native builds, eg. to plug the engine into a chess GUI.
+//! Debug logs from the engine go to stderr.
+//! Commands run in order on a worker thread, while the main thread keeps reading so `stop` and `isready` are answered during a search.
+
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use chess_bs::ai::*;
+use chess_bs::ai::eval_params::*;
+use chess_bs::ai::evaluator::*;
+use chess_bs::ai::nnue::*;
+use chess_bs::ai::dtm::*;
+use chess_bs::ai::book::*;
+use chess_bs::ai::skill::*;
+#[cfg(feature = "lazy_smp")]
+use chess_bs::ai::lazy_smp::*;
+#[cfg(feature = "syzygy")]
+use chess_bs::ai::syzygy::*;
+use chess_bs::game::board::*;
+use chess_bs::game::entities::*;
+use chess_bs::game::move_list::*;
+
+const DEFAULT_DEPTH: u8 = 5;
+/// Effectively unlimited, for when only a time limit is given
+const MAX_DEPTH: u8 = 99;
+const MAX_MULTI_PV: usize = 50;
+#[cfg(feature = "lazy_smp")]
+const MAX_THREADS: usize = 256;
+/// Hash sizes the shared memo of Lazy SMP, the single threaded memo grows as needed
+#[cfg(feature = "lazy_smp")]
+const DEFAULT_HASH_MB: u32 = 64;
+#[cfg(feature = "lazy_smp")]
+const MAX_HASH_MB: u32 = 4096;
+/// Eval params are spin options in thousandths of a pawn, since spins are integers
+const PARAM_SPIN_SCALE: f32 = 1000.;
+const MAX_PARAM_SPIN: i32 = 100000;
+/// Moves the remaining clock time is shared between when `go` has no `movestogo`
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+/// Kept back from the clock for the GUI to receive the move
+const MOVE_OVERHEAD_MS: u64 = 50;
+/// How often an infinite search which has run out of depth checks whether it was stopped
+const INFINITE_POLL_MS: u64 = 10;
+
+struct Uci {
+    board: Board,
+    ai: Ai,
+    multi_pv: usize,
+    skill_level: u8,
+    #[cfg(feature = "lazy_smp")]
+    threads: usize,
+    #[cfg(feature = "lazy_smp")]
+    hash_mb: u32,
+    /// Consulted by `go` before searching
+    book: Option<Book>,
+    #[cfg(feature = "lazy_smp")]
+    lazy_smp: Option<LazySmp>,
+    /// Kept to hand to `LazySmp` when it is created after the network is loaded
+    #[cfg(feature = "lazy_smp")]
+    nnue: Option<Arc<Network>>,
+    #[cfg(feature = "lazy_smp")]
+    dtm_tables: Option<Arc<DtmTables>>,
+    #[cfg(all(feature = "lazy_smp", feature = "syzygy"))]
+    tablebases: Option<Arc<Tablebases>>,
+    /// Shared with the main thread, which sets it on `stop` and clears it before each `go`
+    stop: Arc<AtomicBool>,
+    temp: MoveList,
+    move_list: MoveList
+}
+
+/// Sets `stop` after `ms` unless the returned sender is dropped first
+fn start_timer(stop: Arc<AtomicBool>, ms: u64) -> Sender<()> {
+    let (sender, receiver) = mpsc::channel::<()>();
+    thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(Duration::from_millis(ms)) {
+            stop.store(true, Ordering::Relaxed);
+        }
+    });
+    sender
+}
+
+/// For the player with the turn, from their clock time and increment
+fn get_clock_move_time(time_ms: u64, inc_ms: u64, moves_to_go: u64) -> u64 {
+    let ms = time_ms / moves_to_go.max(1) + inc_ms * 3 / 4;
+    ms.min(time_ms.saturating_sub(MOVE_OVERHEAD_MS)).max(1)
+}
+
+/// `cp <centipawns>`, or `mate <moves>` with negative moves when being mated
+fn format_score(eval: f32) -> String {
+    match get_mate_plies(eval) {
+        Some(plies) if plies > 0 => format!("mate {}", (plies + 1) / 2),
+        Some(plies) => format!("mate {}", plies / 2),
+        None => format!("cp {}", (eval * 100.).round() as i32)
+    }
+}
+
+impl Uci {
+
+    fn new() -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut ai = Ai::new();
+        ai.set_stop_handle(stop.clone());
+        Self {
+            board: Board::new(),
+            ai,
+            multi_pv: 1,
+            skill_level: MAX_SKILL_LEVEL,
+            #[cfg(feature = "lazy_smp")]
+            threads: 1,
+            #[cfg(feature = "lazy_smp")]
+            hash_mb: DEFAULT_HASH_MB,
+            book: None,
+            #[cfg(feature = "lazy_smp")]
+            lazy_smp: None,
+            #[cfg(feature = "lazy_smp")]
+            nnue: None,
+            #[cfg(feature = "lazy_smp")]
+            dtm_tables: None,
+            #[cfg(all(feature = "lazy_smp", feature = "syzygy"))]
+            tablebases: None,
+            stop,
+            temp: MoveList::new(50),
+            move_list: MoveList::new(50)
+        }
+    }
+
+    /// Returns false to quit
+    fn handle_line(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first() {
+            Some(&"uci") => {
+                writeln!(out, "id name chess_bs")?;
+                writeln!(out, "id author starqi")?;
+                writeln!(out, "option name MultiPV type spin default 1 min 1 max {}", MAX_MULTI_PV)?;
+                writeln!(out, "option name Skill Level type spin default {} min 0 max {}", MAX_SKILL_LEVEL, MAX_SKILL_LEVEL)?;
+                #[cfg(feature = "lazy_smp")]
+                writeln!(out, "option name Threads type spin default 1 min 1 max {}", MAX_THREADS)?;
+                #[cfg(feature = "lazy_smp")]
+                writeln!(out, "option name Hash type spin default {} min 1 max {}", DEFAULT_HASH_MB, MAX_HASH_MB)?;
+                writeln!(out, "option name EvalFile type string default <empty>")?;
+                writeln!(out, "option name NnueFile type string default <empty>")?;
+                writeln!(out, "option name DtmPath type string default <empty>")?;
+                writeln!(out, "option name BookFile type string default <empty>")?;
+                #[cfg(feature = "syzygy")]
+                writeln!(out, "option name SyzygyPath type string default <empty>")?;
+                let params = self.ai.get_eval_params();
+                for name in EvalParams::NAMES {
+                    writeln!(
+                        out,
+                        "option name {} type spin default {} min {} max {}",
+                        name, (params.get(name).unwrap_or(0.) * PARAM_SPIN_SCALE).round() as i32, -MAX_PARAM_SPIN, MAX_PARAM_SPIN
+                    )?;
+                }
+                writeln!(out, "uciok")?;
+            },
+            Some(&"isready") => writeln!(out, "readyok")?,
+            Some(&"ucinewgame") => self.board = Board::new(),
+            Some(&"setoption") => self.set_option(&tokens[1..]),
+            Some(&"position") => self.set_position(&tokens[1..], out)?,
+            Some(&"go") => self.go(&tokens[1..], out)?,
+            Some(&"d") => writeln!(out, "{}", self.board)?,
+            Some(&"eval") => writeln!(out, "{}", self.ai.trace_eval(&self.board))?,
+            Some(&"saveparams") => self.save_params(&tokens[1..].join(" ")),
+            Some(&"quit") => return Ok(false),
+            _ => ()
+        };
+        Ok(true)
+    }
+
+    /// `name <name> value <value>`
+    fn set_option(&mut self, args: &[&str]) {
+        let name_i = args.iter().position(|t| *t == "name");
+        let value_i = args.iter().position(|t| *t == "value");
+        if let (Some(name_i), Some(value_i)) = (name_i, value_i) {
+            if name_i >= value_i { return; }
+            let name = args[name_i + 1..value_i].join(" ");
+            let value = args[value_i + 1..].join(" ");
+
+            if name.eq_ignore_ascii_case("MultiPV") {
+                if let Ok(n) = value.parse::<usize>() {
+                    self.multi_pv = n.max(1).min(MAX_MULTI_PV);
+                }
+            } else if name.eq_ignore_ascii_case("Skill Level") {
+                if let Ok(level) = value.parse::<u8>() {
+                    self.skill_level = level.min(MAX_SKILL_LEVEL);
+                }
+            } else if name.eq_ignore_ascii_case("EvalFile") {
+                match EvalParams::load(&value) {
+                    Ok(params) => self.set_eval_params(params),
+                    Err(e) => eprintln!("Can't load {} - {}", value, e)
+                }
+            } else if name.eq_ignore_ascii_case("NnueFile") {
+                if value.is_empty() || value == "<empty>" {
+                    self.set_nnue(None);
+                } else {
+                    match Network::load(&value) {
+                        Ok(net) => self.set_nnue(Some(Arc::new(net))),
+                        Err(e) => eprintln!("Can't load {} - {}", value, e)
+                    }
+                }
+            } else if name.eq_ignore_ascii_case("DtmPath") {
+                if value.is_empty() || value == "<empty>" {
+                    self.set_dtm_tables(None);
+                } else {
+                    match DtmTables::load_dir(&value) {
+                        Ok(tables) => {
+                            eprintln!("Loaded {} depth to mate tables", tables.get_table_count());
+                            self.set_dtm_tables(Some(Arc::new(tables)));
+                        },
+                        Err(e) => eprintln!("Can't load {} - {}", value, e)
+                    }
+                }
+            } else if name.eq_ignore_ascii_case("BookFile") {
+                if value.is_empty() || value == "<empty>" {
+                    self.book = None;
+                } else {
+                    match Book::load(&value) {
+                        Ok(book) => self.book = Some(book),
+                        Err(e) => eprintln!("Can't load {} - {}", value, e)
+                    }
+                }
+            } else if EvalParams::NAMES.contains(&name.as_str()) {
+                let mut params = self.ai.get_eval_params().clone();
+                match value.parse::<i32>() {
+                    Ok(v) => {
+                        params.set(&name, v.max(-MAX_PARAM_SPIN).min(MAX_PARAM_SPIN) as f32 / PARAM_SPIN_SCALE);
+                        self.set_eval_params(params);
+                    },
+                    Err(_) => eprintln!("Bad value {} for {}", value, name)
+                }
+            }
+
+            #[cfg(feature = "lazy_smp")]
+            {
+                if name.eq_ignore_ascii_case("Threads") {
+                    if let Ok(n) = value.parse::<usize>() {
+                        self.threads = n.max(1).min(MAX_THREADS);
+                        self.reset_lazy_smp();
+                    }
+                } else if name.eq_ignore_ascii_case("Hash") {
+                    if let Ok(mb) = value.parse::<u32>() {
+                        self.hash_mb = mb.max(1).min(MAX_HASH_MB);
+                        self.reset_lazy_smp();
+                    }
+                }
+            }
+
+            #[cfg(feature = "syzygy")]
+            {
+                if name.eq_ignore_ascii_case("SyzygyPath") {
+                    if value.is_empty() || value == "<empty>" {
+                        self.set_tablebases(None);
+                    } else {
+                        let tablebases = Tablebases::new(&value);
+                        eprintln!("Found {} tablebases, up to {} pieces", tablebases.get_table_count(), tablebases.get_max_pieces());
+                        self.set_tablebases(Some(Arc::new(tablebases)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Recreates the Lazy SMP search, for a new thread count or memo size
+    #[cfg(feature = "lazy_smp")]
+    fn reset_lazy_smp(&mut self) {
+        let threads = self.threads;
+        // 16 byte entries
+        let memo_size_log2 = 31 - self.hash_mb.leading_zeros() as u8 + 16;
+        self.lazy_smp = if threads > 1 { Some(LazySmp::new(threads, memo_size_log2)) } else { None };
+        if let Some(lazy_smp) = self.lazy_smp.as_mut() {
+            lazy_smp.set_stop_handle(self.stop.clone());
+            lazy_smp.set_eval_params(self.ai.get_eval_params().clone());
+            lazy_smp.set_nnue(self.nnue.clone());
+            lazy_smp.set_dtm_tables(self.dtm_tables.clone());
+            #[cfg(feature = "syzygy")]
+            lazy_smp.set_tablebases(self.tablebases.clone());
+        }
+    }
+
+    fn set_eval_params(&mut self, params: EvalParams) {
+        #[cfg(feature = "lazy_smp")]
+        {
+            if let Some(lazy_smp) = self.lazy_smp.as_mut() {
+                lazy_smp.set_eval_params(params.clone());
+            }
+        }
+        self.ai.set_eval_params(params);
+    }
+
+    fn set_nnue(&mut self, net: Option<Arc<Network>>) {
+        self.ai.set_evaluator(net.clone().map(|net| Box::new(NnueEvaluator::new(net)) as Box<dyn Evaluator + Send>));
+        #[cfg(feature = "lazy_smp")]
+        {
+            if let Some(lazy_smp) = self.lazy_smp.as_mut() {
+                lazy_smp.set_nnue(net.clone());
+            }
+            self.nnue = net;
+        }
+    }
+
+    fn set_dtm_tables(&mut self, dtm_tables: Option<Arc<DtmTables>>) {
+        self.ai.set_dtm_tables(dtm_tables.clone());
+        #[cfg(feature = "lazy_smp")]
+        {
+            if let Some(lazy_smp) = self.lazy_smp.as_mut() {
+                lazy_smp.set_dtm_tables(dtm_tables.clone());
+            }
+            self.dtm_tables = dtm_tables;
+        }
+    }
+
+    #[cfg(feature = "syzygy")]
+    fn set_tablebases(&mut self, tablebases: Option<Arc<Tablebases>>) {
+        self.ai.set_tablebases(tablebases.clone());
+        #[cfg(feature = "lazy_smp")]
+        {
+            if let Some(lazy_smp) = self.lazy_smp.as_mut() {
+                lazy_smp.set_tablebases(tablebases.clone());
+            }
+            self.tablebases = tablebases;
+        }
+    }
+
+    /// Not part of UCI, writes the current eval params as JSON for `EvalFile`
+    fn save_params(&self, path: &str) {
+        if let Err(e) = self.ai.get_eval_params().save(path) {
+            eprintln!("Can't save {} - {}", path, e);
+        }
+    }
+
+    /// `startpos | fen <fen> [moves ...]`, leaving the position unchanged if the FEN or any move is bad
+    fn set_position(&mut self, args: &[&str], out: &mut impl Write) -> io::Result<()> {
+        let moves_i = args.iter().position(|t| *t == "moves");
+        let mut board = match args.first() {
+            Some(&"startpos") => Board::new(),
+            Some(&"fen") => {
+                let fen = args[1..moves_i.unwrap_or(args.len())].join(" ");
+                match Board::from_fen(&fen) {
+                    Ok(board) => board,
+                    Err(e) => return writeln!(out, "info string Bad FEN {} - {:?}", fen, e)
+                }
+            },
+            _ => return writeln!(out, "info string Unsupported position command")
+        };
+
+        if let Some(moves_i) = moves_i {
+            for s in &args[moves_i + 1..] {
+                if !self.apply_long_algebraic(&mut board, s) {
+                    return writeln!(out, "info string Illegal move {} in {}", s, board.to_fen());
+                }
+            }
+        }
+        self.board = board;
+        Ok(())
+    }
+
+    fn apply_long_algebraic(&mut self, board: &mut Board, s: &str) -> bool {
+        self.move_list.write_index = 0;
+        board.get_moves(&mut self.temp, &mut self.move_list);
+        for i in 0..self.move_list.write_index {
+            let m = &self.move_list.get_v()[i];
+            if m.to_long_algebraic() == s {
+                board.handle_move(m, true);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Any legal move, for when a search is stopped before it finishes its first iteration
+    fn get_any_move(&mut self) -> Option<MoveSnapshot> {
+        self.move_list.write_index = 0;
+        self.board.get_moves(&mut self.temp, &mut self.move_list);
+        self.move_list.get_v()[..self.move_list.write_index].first().cloned()
+    }
+
+    /// `[depth <n>] [movetime <ms>] [wtime <ms>] [btime <ms>] [winc <ms>] [binc <ms>] [movestogo <n>] [infinite]`.
+    /// Searches until done or stopped, `stop` having been cleared by the main thread.
+    fn go(&mut self, args: &[&str], out: &mut impl Write) -> io::Result<()> {
+        let get_arg = |name: &str| -> Option<u64> {
+            let i = args.iter().position(|t| *t == name)?;
+            args.get(i + 1)?.parse::<u64>().ok()
+        };
+        let infinite = args.contains(&"infinite");
+        let (time, inc) = if self.board.get_player_with_turn() == Player::White { ("wtime", "winc") } else { ("btime", "binc") };
+        let movetime = if infinite {
+            None
+        } else {
+            get_arg("movetime").or_else(|| get_arg(time).map(|time_ms| {
+                get_clock_move_time(time_ms, get_arg(inc).unwrap_or(0), get_arg("movestogo").unwrap_or(DEFAULT_MOVES_TO_GO))
+            }))
+        };
+        let depth = match get_arg("depth") {
+            Some(d) => d.max(1).min(MAX_DEPTH as u64) as u8,
+            None => if movetime.is_some() || infinite { MAX_DEPTH } else { DEFAULT_DEPTH }
+        };
+
+        let board = &mut self.board;
+        if let Some(m) = self.book.as_ref().and_then(|book| book.pick_move(board)) {
+            writeln!(out, "info string book move")?;
+            self.wait_if_infinite(infinite);
+            writeln!(out, "bestmove {}", m.to_long_algebraic())?;
+            return Ok(());
+        }
+
+        let skill = SkillLevel::get(self.skill_level);
+        let is_handicapped = skill.is_handicapped();
+
+        #[cfg(feature = "lazy_smp")]
+        {
+            if let (Some(lazy_smp), 1, false) = (self.lazy_smp.as_mut(), self.multi_pv, is_handicapped) {
+                let stop = self.stop.clone();
+                let _timer = movetime.map(|ms| start_timer(stop, ms));
+                let best_move = match lazy_smp.search(depth, &self.board) {
+                    Some((m, eval)) => {
+                        writeln!(out, "info score {} pv {}", format_score(eval), m.to_long_algebraic())?;
+                        Some(m)
+                    },
+                    None => None
+                };
+                return self.write_best_move(best_move, infinite, out);
+            }
+        }
+
+        let _timer = movetime.map(|ms| start_timer(self.stop.clone(), ms));
+
+        // A handicapped skill level searches its own candidate lines within its limits, then picks among them
+        let lines = if is_handicapped {
+            self.ai.set_node_limit(skill.node_limit);
+            let lines = self.ai.search(depth.min(skill.depth), &self.board, self.multi_pv.max(skill.candidates));
+            self.ai.set_node_limit(None);
+            lines
+        } else {
+            self.ai.search(depth, &self.board, self.multi_pv)
+        };
+        for (i, line) in lines.iter().enumerate() {
+            let pv = line.moves.iter().map(|m| m.to_long_algebraic()).collect::<Vec<String>>().join(" ");
+            writeln!(out, "info depth {} multipv {} score {} pv {}", line.depth, i + 1, format_score(line.eval), pv)?;
+        }
+
+        let best_move = if is_handicapped {
+            self.ai.pick_move(&self.board, &lines, skill)
+        } else {
+            lines.first().and_then(|line| line.moves.first()).cloned()
+        };
+        self.write_best_move(best_move, infinite, out)
+    }
+
+    fn write_best_move(&mut self, best_move: Option<MoveSnapshot>, infinite: bool, out: &mut impl Write) -> io::Result<()> {
+        self.wait_if_infinite(infinite);
+        match best_move.or_else(|| self.get_any_move()) {
+            Some(m) => writeln!(out, "bestmove {}", m.to_long_algebraic()),
+            None => writeln!(out, "bestmove 0000")
+        }
+    }
+
+    /// An infinite search only reports its move once stopped, even when it runs out of depth first
+    fn wait_if_infinite(&self, infinite: bool) {
+        while infinite && !self.stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(INFINITE_POLL_MS));
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut uci = Uci::new();
+    let stop = uci.stop.clone();
+    // Searches sent to the worker and not yet finished
+    let searches = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel::<String>();
+
+    let worker = {
+        let searches = searches.clone();
+        thread::spawn(move || -> io::Result<()> {
+            // Not locked while running a command, so the main thread can write during a search
+            let mut out = io::stdout();
+            for line in receiver {
+                let keep_going = uci.handle_line(&line, &mut out)?;
+                if line.split_whitespace().next() == Some("go") {
+                    searches.fetch_sub(1, Ordering::SeqCst);
+                }
+                out.flush()?;
+                if !keep_going { break; }
+            }
+            Ok(())
+        })
+    };
+
+    // The end of input quits like `quit`
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        match line.split_whitespace().next() {
+            Some("stop") => stop.store(true, Ordering::Relaxed),
+            Some("isready") if searches.load(Ordering::SeqCst) > 0 => {
+                let mut out = io::stdout();
+                writeln!(out, "readyok")?;
+                out.flush()?;
+            },
+            Some("go") => {
+                stop.store(false, Ordering::Relaxed);
+                searches.fetch_add(1, Ordering::SeqCst);
+                if sender.send(line).is_err() { break; }
+            },
+            Some("quit") => break,
+            _ => if sender.send(line).is_err() { break; }
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    drop(sender);
+    worker.join().unwrap_or(Ok(()))
+}
@@ -1,6 +1,8 @@
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 // Use `js_namespace` here to bind `console.log(..)` instead of just `log(..)`
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
 
@@ -16,3 +18,50 @@ extern "C" {
     #[wasm_bindgen(js_namespace = Date)]
     pub fn now() -> u32;
 }
+
+// Native stand-ins so the engine can run outside the browser, eg. behind the UCI binary.
+// Logs go to stderr since stdout belongs to the protocol.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn log(s: &str) {
+    eprintln!("{}", s);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(dead_code)]
+pub fn error(s: &str) {
+    eprintln!("{}", s);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn random() -> f64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static STATE: AtomicU64 = AtomicU64::new(0);
+
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0) | 1;
+    }
+
+    // xorshift64
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+lazy_static! {
+    static ref START: std::time::Instant = std::time::Instant::now();
+}
+
+/// Milliseconds since first call, only meaningful for differences like `Date.now`
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now() -> u32 {
+    START.elapsed().as_millis() as u32
+}
@@ -1,6 +1,7 @@
 
 use std::collections::HashSet;
 use std::fmt::{Display, Formatter, self};
+use std::sync::Arc;
 use super::coords::*;
 use super::entities::*;
 use super::move_list::*;
@@ -8,6 +9,8 @@ use super::move_test::*;
 use super::check_handler::*;
 use super::push_moves_handler::*;
 use super::super::*;
+use super::super::ai::incremental::*;
+use super::super::ai::nnue::*;
 
 #[derive(Clone)]
 pub struct PlayerState {
@@ -33,11 +36,30 @@ impl PlayerState {
     }
 }
 
+#[derive(Copy, Clone, Debug)]
+pub enum FenError {
+    MissingPlacement,
+    /// Row index from the top, or 8 if the row count is wrong
+    BadRow(u8),
+    BadPiece(char),
+    /// Row index from the top of a pawn on the first or last rank
+    PawnOnBackRank(u8),
+    BadPlayer,
+    BadCastling(char)
+}
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
 #[derive(Clone)]
 pub struct Board {
     player_with_turn: Player,
     d: [Square; 64],
     hash: u64,
+    /// Zobrist hash of only the pawns, for caching pawn structure evaluation
+    pawn_hash: u64,
+    score: IncrementalScore,
+    /// Only kept up to date while a network is attached, see `set_nnue`
+    nnue: Option<Accumulator>,
     player_state: [PlayerState; 2]
 }
 
@@ -58,14 +80,122 @@ impl Board {
         let mut board = Self {
             d: [Square::Blank; 64],
             hash: 0,
+            pawn_hash: 0,
+            score: IncrementalScore::default(),
+            nnue: None,
             player_with_turn: Player::White,
             player_state: [PlayerState::new(), PlayerState::new()]
         };
         board.set_standard_rows();
         board.hash = board.calculate_hash();
+        board.pawn_hash = board.calculate_pawn_hash();
         board
     }
 
+    /// En passant and move counters are not supported and ignored
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let mut board = Self {
+            d: [Square::Blank; 64],
+            hash: 0,
+            pawn_hash: 0,
+            score: IncrementalScore::default(),
+            nnue: None,
+            player_with_turn: Player::White,
+            player_state: [PlayerState::new(), PlayerState::new()]
+        };
+
+        let mut fields = fen.split_whitespace();
+        let rows: Vec<&str> = fields.next().ok_or(FenError::MissingPlacement)?.split('/').collect();
+        if rows.len() != 8 {
+            return Err(FenError::BadRow(8));
+        }
+
+        for (y, row) in rows.iter().enumerate() {
+            let mut x = 0u8;
+            for c in row.chars() {
+                if let Some(n) = c.to_digit(10) {
+                    if n < 1 || n > 8 { return Err(FenError::BadRow(y as u8)); }
+                    x += n as u8;
+                    continue;
+                }
+                if x > 7 { return Err(FenError::BadRow(y as u8)); }
+
+                let player = if c.is_ascii_uppercase() { Player::White } else { Player::Black };
+                let piece = match c.to_ascii_lowercase() {
+                    'p' => Piece::Pawn,
+                    'r' => Piece::Rook,
+                    'n' => Piece::Knight,
+                    'b' => Piece::Bishop,
+                    'q' => Piece::Queen,
+                    'k' => Piece::King,
+                    _ => return Err(FenError::BadPiece(c))
+                };
+                if piece == Piece::Pawn && (y == 0 || y == 7) { return Err(FenError::PawnOnBackRank(y as u8)); }
+                board.set_by_xy(x, y as u8, Square::Occupied(piece, player));
+                x += 1;
+            }
+            if x != 8 { return Err(FenError::BadRow(y as u8)); }
+        }
+
+        board.player_with_turn = match fields.next() {
+            Some("w") | None => Player::White,
+            Some("b") => Player::Black,
+            _ => return Err(FenError::BadPlayer)
+        };
+
+        for ps in board.player_state.iter_mut() {
+            ps.moved_oo_piece = true;
+            ps.moved_ooo_piece = true;
+        }
+        for c in fields.next().unwrap_or("-").chars() {
+            match c {
+                'K' => board.player_state[Player::White as usize].moved_oo_piece = false,
+                'Q' => board.player_state[Player::White as usize].moved_ooo_piece = false,
+                'k' => board.player_state[Player::Black as usize].moved_oo_piece = false,
+                'q' => board.player_state[Player::Black as usize].moved_ooo_piece = false,
+                '-' => (),
+                _ => return Err(FenError::BadCastling(c))
+            };
+        }
+
+        board.hash = board.calculate_hash();
+        board.pawn_hash = board.calculate_pawn_hash();
+        Ok(board)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for y in 0..8 {
+            let mut blanks = 0;
+            for x in 0..8 {
+                if let Square::Occupied(piece, player) = self.get_by_xy(x, y) {
+                    if blanks > 0 {
+                        fen.push_str(&blanks.to_string());
+                        blanks = 0;
+                    }
+                    let letter = piece.to_string();
+                    fen.push_str(&if *player == Player::White { letter.to_uppercase() } else { letter });
+                } else {
+                    blanks += 1;
+                }
+            }
+            if blanks > 0 { fen.push_str(&blanks.to_string()); }
+            if y != 7 { fen.push('/'); }
+        }
+
+        fen.push_str(if self.player_with_turn == Player::White { " w " } else { " b " });
+
+        let ws = self.get_player_state(Player::White);
+        let bs = self.get_player_state(Player::Black);
+        let castling: String = [
+            (ws.moved_oo_piece, 'K'), (ws.moved_ooo_piece, 'Q'), (bs.moved_oo_piece, 'k'), (bs.moved_ooo_piece, 'q')
+        ].iter().filter(|(moved, _)| !moved).map(|(_, c)| *c).collect();
+        fen.push_str(if castling.is_empty() { "-" } else { &castling });
+
+        fen.push_str(" - 0 1");
+        fen
+    }
+
     #[inline]
     pub fn get_square_hash(i: usize, piece: Piece, player: Player) -> u64 {
         RANDOM_NUMBER_KEYS.squares[i * PER_SQUARE_LEN + (piece as usize) + (player as usize) * PIECE_LEN]
@@ -99,6 +229,37 @@ impl Board {
         self.hash
     }
 
+    /// Uses the same keys as `calculate_hash` for pawn squares only
+    pub fn calculate_pawn_hash(&self) -> u64 {
+        let mut h: u64 = 0;
+        for (i, sq) in self.d.iter().enumerate() {
+            if let Square::Occupied(Piece::Pawn, player) = sq {
+                h ^= Self::get_square_hash(i, Piece::Pawn, *player);
+            }
+        }
+        h
+    }
+
+    #[inline]
+    pub fn get_pawn_hash(&self) -> u64 {
+        self.pawn_hash
+    }
+
+    #[inline]
+    pub fn get_score(&self) -> &IncrementalScore {
+        &self.score
+    }
+
+    /// Attaches a network whose accumulator is then updated on every square change, or detaches it
+    pub fn set_nnue(&mut self, net: Option<Arc<Network>>) {
+        self.nnue = net.map(|net| Accumulator::new(net, self));
+    }
+
+    #[inline]
+    pub fn get_nnue(&self) -> Option<&Accumulator> {
+        self.nnue.as_ref()
+    }
+
     //////////////////////////////////////////////////
     // Player state
 
@@ -112,6 +273,23 @@ impl Board {
         &self.player_state[player as usize]
     }
 
+    /// Whether `player` could still castle king side and queen side at some point, ie. has its king and
+    /// the rook at home with neither moved. Castling may be blocked now.
+    pub fn get_castling_rights(&self, player: Player) -> (bool, bool) {
+        let ps = self.get_player_state(player);
+        let y = player.get_first_row();
+        let has = |x: u8, piece: Piece| *self.get_by_xy(x, y) == Square::Occupied(piece, player);
+        let has_king = has(4, Piece::King);
+        (has_king && !ps.moved_oo_piece && has(7, Piece::Rook), has_king && !ps.moved_ooo_piece && has(0, Piece::Rook))
+    }
+
+    pub fn has_castling_rights(&self) -> bool {
+        [Player::White, Player::Black].iter().any(|player| {
+            let (oo, ooo) = self.get_castling_rights(*player);
+            oo || ooo
+        })
+    }
+
     #[inline]
     fn get_player_state_mut(&mut self, player: Player) -> &mut PlayerState {
         &mut self.player_state[player as usize]
@@ -135,15 +313,24 @@ impl Board {
         return &self.d[y as usize * 8 + x as usize];
     }
 
+    /// Also updates the incremental score and any attached network, but not the hashes which `handle_move` updates
     pub fn set_by_xy(&mut self, x: u8, y: u8, s: Square) {
-        if let Square::Occupied(_, occupied_player) = self.get_by_xy(x, y) {
-            let piece_list = &mut self.get_player_state_mut(*occupied_player).piece_locs;
+        if let Square::Occupied(occupied_piece, occupied_player) = *self.get_by_xy(x, y) {
+            let piece_list = &mut self.get_player_state_mut(occupied_player).piece_locs;
             piece_list.remove(&Coord(x, y));
+            self.score.remove(occupied_piece, occupied_player, x, y);
+            if let Some(accumulator) = self.nnue.as_mut() {
+                accumulator.remove(occupied_piece, occupied_player, x, y);
+            }
         }
 
-        if let Square::Occupied(_, new_player) = s {
+        if let Square::Occupied(new_piece, new_player) = s {
             let piece_list = &mut self.get_player_state_mut(new_player).piece_locs;
             piece_list.insert(Coord(x, y));
+            self.score.add(new_piece, new_player, x, y);
+            if let Some(accumulator) = self.nnue.as_mut() {
+                accumulator.add(new_piece, new_player, x, y);
+            }
         }
 
         self.d[y as usize * 8 + x as usize] = s;
@@ -163,18 +350,27 @@ impl Board {
                 self.set_by_xy(*x, *y, if apply_or_undo { *after } else { *before });
 
                 if let Square::Occupied(before_piece, before_player) = before {
-                    self.hash ^= Self::get_square_hash(*y as usize * 8 + *x as usize, *before_piece, *before_player);
+                    let sq_hash = Self::get_square_hash(*y as usize * 8 + *x as usize, *before_piece, *before_player);
+                    self.hash ^= sq_hash;
+                    if *before_piece == Piece::Pawn { self.pawn_hash ^= sq_hash; }
                 }
                 if let Square::Occupied(after_piece, after_player) = after {
                     debug_assert!(apply_or_undo == (*after_player == self.get_player_with_turn()), "Applying move for wrong player - {}", m);
-                    self.hash ^= Self::get_square_hash(*y as usize * 8 + *x as usize, *after_piece, *after_player);
+                    let sq_hash = Self::get_square_hash(*y as usize * 8 + *x as usize, *after_piece, *after_player);
+                    self.hash ^= sq_hash;
+                    if *after_piece == Piece::Pawn { self.pawn_hash ^= sq_hash; }
                 }
             }
         }
 
         {
             let mut hash = self.get_hash();
-            let old_player = self.get_player_with_turn().get_other_player();
+            // The player who made `m`, whose turn it still is when applying but not when undoing
+            let old_player = if apply_or_undo {
+                self.get_player_with_turn()
+            } else {
+                self.get_player_with_turn().get_other_player()
+            };
             let old_player_state = self.get_player_state_mut(old_player);
 
             let oo_key = RANDOM_NUMBER_KEYS.moved_oo_piece[old_player as usize];
@@ -186,10 +382,15 @@ impl Board {
 
             // Change the actual flags to whatever they should be
             match m.2 {
-                MoveDescription::Ooo | MoveDescription::Oo => {
+                MoveDescription::Oo(first_to_prevent_ooo) => {
                     old_player_state.castled_somewhere = apply_or_undo;
                     old_player_state.moved_oo_piece = apply_or_undo;
+                    if first_to_prevent_ooo { old_player_state.moved_ooo_piece = apply_or_undo; }
+                },
+                MoveDescription::Ooo(first_to_prevent_oo) => {
+                    old_player_state.castled_somewhere = apply_or_undo;
                     old_player_state.moved_ooo_piece = apply_or_undo;
+                    if first_to_prevent_oo { old_player_state.moved_oo_piece = apply_or_undo; }
                 },
                 _ => {
                     match m.2 {
@@ -262,6 +463,65 @@ impl Board {
         }
     }
 
+    /// Rebuilds a move packed by `MoveSnapshot::to_compact` for the player with the turn.
+    /// Checks that the move is pseudo-legal, since a shared memo can hand back a move of another position,
+    /// but not that it leaves the king safe.
+    pub fn get_move_from_compact(&self, compact: u16) -> Option<MoveSnapshot> {
+        let src_i = (compact & 63) as u8;
+        let dest_i = ((compact >> 6) & 63) as u8;
+        let promotion = match (compact >> 12) & 7 {
+            0 => None,
+            p @ 1..=6 => Some(PIECES[p as usize - 1]),
+            _ => return None
+        };
+        if src_i == dest_i { return None; }
+
+        let (src_x, src_y, dest_x, dest_y) = (src_i % 8, src_i / 8, dest_i % 8, dest_i / 8);
+        let player = self.get_player_with_turn();
+
+        let src_piece = match self.get_by_xy(src_x, src_y) {
+            Square::Occupied(piece, p) if *p == player => *piece,
+            _ => return None
+        };
+        let existing_dest_square = *self.get_by_xy(dest_x, dest_y);
+        if let Square::Occupied(_, p) = existing_dest_square {
+            if p == player { return None; }
+        }
+
+        if src_piece == Piece::King && (src_x as i8 - dest_x as i8).abs() == 2 {
+            let ps = self.get_player_state(player);
+            let (moved, castle) = if dest_x > src_x {
+                (ps.moved_oo_piece, &CASTLE_UTILS.oo_move_snapshots[player as usize])
+            } else {
+                (ps.moved_ooo_piece, &CASTLE_UTILS.ooo_move_snapshots[player as usize])
+            };
+            if moved { return None; }
+            for sq_holder in castle.get_squares() {
+                if let Some((Coord(x, y), BeforeAfterSquares(before_sq, _))) = sq_holder {
+                    if *self.get_by_xy(*x, *y) != *before_sq {
+                        return None;
+                    }
+                }
+            }
+            return Some(self.get_castle_move(castle, player));
+        }
+
+        if promotion.is_some() && src_piece != Piece::Pawn { return None; }
+
+        let mut handler = ReachHandler { dest_x, dest_y, promotion, found: false };
+        fill_src(&MoveTestParams {
+            src_x: src_x as i8,
+            src_y: src_y as i8,
+            src_piece,
+            src_player: player,
+            can_capture_king: false,
+            board: self
+        }, &mut handler);
+        if !handler.found { return None; }
+
+        Some(make_basic_move(self, src_x, src_y, src_piece, player, dest_x, dest_y, &existing_dest_square, promotion))
+    }
+
     /// Only does piece checks, not state checks, ie. does it visually look like we can castle (but maybe the rook is not the original rook)
     fn try_push_castle(
         &mut self,
@@ -287,10 +547,22 @@ impl Board {
         }
 
         if can_castle {
-            result.clone_and_write(move_snapshot);
+            result.write(self.get_castle_move(move_snapshot, player_with_turn));
         }
     }
 
+    /// Copies one of the `CastleUtils` snapshots, noting whether it also takes away castling on the other side
+    fn get_castle_move(&self, castle: &MoveSnapshot, player: Player) -> MoveSnapshot {
+        let ps = self.get_player_state(player);
+        let mut m = castle.clone();
+        m.2 = match m.2 {
+            MoveDescription::Oo(_) => MoveDescription::Oo(!ps.moved_ooo_piece),
+            MoveDescription::Ooo(_) => MoveDescription::Ooo(!ps.moved_oo_piece),
+            description => description
+        };
+        m
+    }
+
     //////////////////////////////////////////////////
 
     fn set_uniform_row(&mut self, rank: u8, player: Player, piece: Piece) {
@@ -317,3 +589,102 @@ impl Board {
         self.set_uniform_row(7, Player::Black, Piece::Pawn);
     }
 }
+
+/// Whether the piece can move to the destination, with the promotion if any
+struct ReachHandler {
+    dest_x: u8,
+    dest_y: u8,
+    promotion: Option<Piece>,
+    found: bool
+}
+
+impl MoveTestHandler for ReachHandler {
+    fn push(
+        &mut self,
+        moveable: bool,
+        _can_capture: bool,
+        _params: &MoveTestParams,
+        dest_x: u8,
+        dest_y: u8,
+        _existing_dest_square: &Square,
+        replacement_piece: Option<Piece>
+    ) -> bool {
+        if moveable && dest_x == self.dest_x && dest_y == self.dest_y && replacement_piece == self.promotion {
+            self.found = true;
+        }
+        self.found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Positions with partial castling rights and castles, captures and promotions a couple of plies away
+    const FENS: [&str; 5] = [
+        STARTING_FEN,
+        "r3k2r/8/8/8/8/8/8/R3K2R w K - 0 1",
+        "r3k2r/8/8/8/8/8/8/R3K2R b Qk - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k3/1P6/8/8/8/8/6p1/4K2R w Kq - 0 1"
+    ];
+
+    fn check_make_unmake(board: &mut Board, depth: u8) {
+        if depth == 0 { return; }
+        let mut temp = MoveList::new(50);
+        let mut moves = MoveList::new(50);
+        board.get_moves(&mut temp, &mut moves);
+
+        let fen = board.to_fen();
+        let hash = board.get_hash();
+        for i in 0..moves.write_index {
+            let m = moves.get_v()[i].clone();
+            board.handle_move(&m, true);
+            assert_eq!(board.get_hash(), board.calculate_hash(), "{} after {}", fen, m);
+            assert_eq!(board.get_pawn_hash(), board.calculate_pawn_hash(), "{} after {}", fen, m);
+            check_make_unmake(board, depth - 1);
+            board.handle_move(&m, false);
+            assert_eq!(board.to_fen(), fen, "undoing {}", m);
+            assert_eq!(board.get_hash(), hash, "undoing {}", m);
+        }
+    }
+
+    #[test]
+    fn make_unmake_keeps_fen_and_hash() {
+        for fen in FENS.iter() {
+            check_make_unmake(&mut Board::from_fen(fen).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in FENS.iter() {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(board.to_fen(), *fen);
+            assert_eq!(board.get_hash(), board.calculate_hash());
+        }
+        assert_eq!(Board::from_fen(STARTING_FEN).unwrap().get_hash(), Board::new().get_hash());
+        assert_eq!(Board::new().to_fen(), STARTING_FEN);
+    }
+
+    #[test]
+    fn fen_ignores_en_passant_and_counters() {
+        let board = Board::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2").unwrap();
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(Board::from_fen("4k3/8/8/8/8/8/8/4K3").unwrap().to_fen(), "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn fen_errors() {
+        assert!(matches!(Board::from_fen(""), Err(FenError::MissingPlacement)));
+        assert!(matches!(Board::from_fen("4k3/8/8/8/8/8/4K3 w - - 0 1"), Err(FenError::BadRow(8))));
+        assert!(matches!(Board::from_fen("4k3/8/8/8/8/8/8/4K4 w - - 0 1"), Err(FenError::BadRow(7))));
+        assert!(matches!(Board::from_fen("4k3/8/9/8/8/8/8/4K3 w - - 0 1"), Err(FenError::BadRow(2))));
+        assert!(matches!(Board::from_fen("4k3/8/8/8/8/8/8/4K2 w - - 0 1"), Err(FenError::BadRow(7))));
+        assert!(matches!(Board::from_fen("4k3/8/8/3x4/8/8/8/4K3 w - - 0 1"), Err(FenError::BadPiece('x'))));
+        assert!(matches!(Board::from_fen("4k2P/8/8/8/8/8/8/4K3 w - - 0 1"), Err(FenError::PawnOnBackRank(0))));
+        assert!(matches!(Board::from_fen("4k3/8/8/8/8/8/8/p3K3 w - - 0 1"), Err(FenError::PawnOnBackRank(7))));
+        assert!(matches!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 x - - 0 1"), Err(FenError::BadPlayer)));
+        assert!(matches!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 w KX - 0 1"), Err(FenError::BadCastling('X'))));
+    }
+}
@@ -21,7 +21,7 @@ impl CastleUtils {
             Some((Coord(6, row), BeforeAfterSquares(Square::Blank, Square::Occupied(Piece::King, player)))),
             Some((Coord(7, row), BeforeAfterSquares(Square::Occupied(Piece::Rook, player), Square::Blank))),
             None
-        ], 0., MoveDescription::Oo);
+        ], 0., MoveDescription::Oo(true));
     }
 
     fn get_ooo_move_snapshot_for_row(player: Player) -> MoveSnapshot {
@@ -32,7 +32,7 @@ impl CastleUtils {
             Some((Coord(2, row), BeforeAfterSquares(Square::Blank, Square::Occupied(Piece::King, player)))),
             Some((Coord(3, row), BeforeAfterSquares(Square::Blank, Square::Occupied(Piece::Rook, player)))),
             Some((Coord(4, row), BeforeAfterSquares(Square::Occupied(Piece::King, player), Square::Blank)))
-        ], 0., MoveDescription::Ooo);
+        ], 0., MoveDescription::Ooo(true));
     }
 
     pub fn new() -> CastleUtils {
@@ -6,6 +6,8 @@ pub enum Piece {
     Pawn = 0, Rook, Knight, Bishop, Queen, King
 }
 
+pub static PIECES: [Piece; 6] = [Piece::Pawn, Piece::Rook, Piece::Knight, Piece::Bishop, Piece::Queen, Piece::King];
+
 impl Piece {
     fn custom_fmt(&self, f: &mut Formatter<'_>, is_lower: bool) -> Result<(), FmtError> {
         let s = match self {
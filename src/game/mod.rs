@@ -8,3 +8,4 @@ pub mod move_test;
 pub mod searchable_moves;
 pub mod push_moves_handler;
 pub mod check_handler;
+pub mod pgn;
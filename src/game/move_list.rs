@@ -6,12 +6,13 @@ use super::entities::*;
 use crate::{console_log};
 
 /// (bool, bool, u8) = (first to prevent oo, first to prevent ooo, dest sq index)
+/// Castling's bool = first to prevent castling on the other side, so that undoing restores it
 #[derive(Copy, Clone)]
 pub enum MoveDescription {
     Capture(bool, bool, u8),
     Move(bool, bool, u8),
-    Oo,
-    Ooo,
+    Oo(bool),
+    Ooo(bool),
     Special
 }
 
@@ -62,6 +63,57 @@ impl MoveSnapshot {
             None
         }
     }
+
+    /// Source and destination of the moving piece (the king for castling), and the promotion piece if any
+    pub fn get_src_dest_promotion(&self) -> Option<(Coord, Coord, Option<Piece>)> {
+        match self.get_description() {
+            MoveDescription::Oo(_) | MoveDescription::Ooo(_) => {
+                // See `CastleUtils`, the king is at 4 for oo and at 2 in both cases after castling
+                let sqs = self.get_squares();
+                let (src, dest) = if let MoveDescription::Oo(_) = self.get_description() {
+                    (sqs[0], sqs[2])
+                } else {
+                    (sqs[4], sqs[2])
+                };
+                match (src, dest) {
+                    (Some((src_coord, _)), Some((dest_coord, _))) => Some((src_coord, dest_coord, None)),
+                    _ => None
+                }
+            },
+            _ => {
+                match (self.get_src_sq(), self.get_dest_sq()) {
+                    (
+                        Some((src_coord, BeforeAfterSquares(Square::Occupied(src_piece, _), _))),
+                        Some((dest_coord, BeforeAfterSquares(_, Square::Occupied(dest_piece, _))))
+                    ) => {
+                        Some((*src_coord, *dest_coord, if src_piece != dest_piece { Some(*dest_piece) } else { None }))
+                    },
+                    _ => None
+                }
+            }
+        }
+    }
+
+    /// Coordinate notation as used by UCI, eg. e2e4, e7e8q, e1g1 for castling
+    pub fn to_long_algebraic(&self) -> String {
+        match self.get_src_dest_promotion() {
+            Some((src, dest, Some(promotion))) => format!("{}{}{}", src, dest, promotion),
+            Some((src, dest, None)) => format!("{}{}", src, dest),
+            None => String::from("0000")
+        }
+    }
+
+    /// Packs the move into 15 bits - source index, destination index, promotion piece + 1 or 0.
+    /// Unpack with `Board::get_move_from_compact`.
+    pub fn to_compact(&self) -> u16 {
+        match self.get_src_dest_promotion() {
+            Some((Coord(src_x, src_y), Coord(dest_x, dest_y), promotion)) => {
+                let promotion_bits = promotion.map(|p| p as u16 + 1).unwrap_or(0);
+                (src_y as u16 * 8 + src_x as u16) | ((dest_y as u16 * 8 + dest_x as u16) << 6) | (promotion_bits << 12)
+            },
+            None => 0
+        }
+    }
 }
 
 impl Deref for MoveSnapshot {
@@ -96,10 +148,10 @@ impl Display for MoveSnapshot {
 
                 write!(f, "Error... ({})", self.get_eval())
             },
-            MoveDescription::Oo => {
+            MoveDescription::Oo(_) => {
                 write!(f, "oo ({})", self.get_eval())
             },
-            MoveDescription::Ooo => {
+            MoveDescription::Ooo(_) => {
                 write!(f, "ooo ({})", self.get_eval())
             },
             _ => {
@@ -0,0 +1,440 @@
+//! Standard algebraic notation (SAN) for moves, eg. `Nbd2`, `exd5`, `e8=Q+` or `O-O`, and reading PGN game collections.
+//! Variations, comments and NAGs are skipped. En passant isn't supported by `Board`, so games stop being read at one.
+
+use super::entities::*;
+use super::coords::*;
+use super::board::*;
+use super::move_list::*;
+use super::check_handler::*;
+
+fn get_piece_letter(piece: Piece) -> &'static str {
+    match piece {
+        Piece::Pawn => "",
+        Piece::Rook => "R",
+        Piece::Knight => "N",
+        Piece::Bishop => "B",
+        Piece::Queen => "Q",
+        Piece::King => "K"
+    }
+}
+
+fn get_legal_moves(board: &mut Board) -> Vec<MoveSnapshot> {
+    let mut temp = MoveList::new(50);
+    let mut moves = MoveList::new(50);
+    board.get_moves(&mut temp, &mut moves);
+    moves.get_v()[..moves.write_index].to_vec()
+}
+
+/// Source square, moving piece, destination square and promotion
+fn get_parts(board: &Board, m: &MoveSnapshot) -> Option<(Coord, Piece, Coord, Option<Piece>)> {
+    let (src, dest, promotion) = m.get_src_dest_promotion()?;
+    match board.get_by_xy(src.0, src.1) {
+        Square::Occupied(piece, _) => Some((src, *piece, dest, promotion)),
+        Square::Blank => None
+    }
+}
+
+/// SAN of legal move `m` on `board`, with `+` or `#` when it checks or mates
+pub fn to_san(board: &mut Board, m: &MoveSnapshot) -> String {
+    let mut san = match m.get_description() {
+        MoveDescription::Oo(_) => String::from("O-O"),
+        MoveDescription::Ooo(_) => String::from("O-O-O"),
+        _ => match get_parts(board, m) {
+            Some((src, piece, dest, promotion)) => {
+                let is_capture = matches!(m.get_description(), MoveDescription::Capture(_, _, _));
+                let mut san = String::from(get_piece_letter(piece));
+
+                if piece == Piece::Pawn {
+                    if is_capture {
+                        san.push_str(&src.to_string()[..1]);
+                    }
+                } else {
+                    // Other pieces of the same type which could also go to `dest`
+                    let others: Vec<Coord> = get_legal_moves(board).iter()
+                        .filter_map(|other| get_parts(board, other))
+                        .filter(|(other_src, other_piece, other_dest, _)| *other_piece == piece && *other_dest == dest && *other_src != src)
+                        .map(|(other_src, _, _, _)| other_src)
+                        .collect();
+                    if !others.is_empty() {
+                        let src_s = src.to_string();
+                        if others.iter().all(|o| o.0 != src.0) {
+                            san.push_str(&src_s[..1]);
+                        } else if others.iter().all(|o| o.1 != src.1) {
+                            san.push_str(&src_s[1..]);
+                        } else {
+                            san.push_str(&src_s);
+                        }
+                    }
+                }
+
+                if is_capture {
+                    san.push('x');
+                }
+                san.push_str(&dest.to_string());
+                if let Some(promotion) = promotion {
+                    san.push('=');
+                    san.push_str(get_piece_letter(promotion));
+                }
+                san
+            },
+            None => String::from("--")
+        }
+    };
+
+    let player = board.get_player_with_turn();
+    board.handle_move(m, true);
+    if is_checking(board, player) {
+        san.push(if get_legal_moves(board).is_empty() { '#' } else { '+' });
+    }
+    board.handle_move(m, false);
+    san
+}
+
+/// The legal move of `board` written as `san`. Accepts missing or extra disambiguation, `0-0` castling
+/// and annotations like `+`, `#`, `!` or `?`.
+pub fn parse_san(board: &mut Board, san: &str) -> Option<MoveSnapshot> {
+    let san = san.trim_end_matches(|c| c == '+' || c == '#' || c == '!' || c == '?');
+    let legal_moves = get_legal_moves(board);
+
+    match san {
+        "O-O" | "0-0" => return legal_moves.into_iter().find(|m| matches!(m.get_description(), MoveDescription::Oo(_))),
+        "O-O-O" | "0-0-0" => return legal_moves.into_iter().find(|m| matches!(m.get_description(), MoveDescription::Ooo(_))),
+        _ => ()
+    };
+
+    let mut chars: Vec<char> = san.chars().filter(|c| *c != 'x' && *c != '=' && *c != '-' && *c != ':').collect();
+    let piece = match chars.first() {
+        Some('K') => Piece::King,
+        Some('Q') => Piece::Queen,
+        Some('R') => Piece::Rook,
+        Some('B') => Piece::Bishop,
+        Some('N') => Piece::Knight,
+        Some(_) => Piece::Pawn,
+        None => return None
+    };
+    if piece != Piece::Pawn {
+        chars.remove(0);
+    }
+
+    let promotion = match chars.last() {
+        Some('Q') | Some('q') => Some(Piece::Queen),
+        Some('R') | Some('r') => Some(Piece::Rook),
+        Some('B') => Some(Piece::Bishop),
+        Some('N') | Some('n') => Some(Piece::Knight),
+        _ => None
+    };
+    if promotion.is_some() {
+        chars.pop();
+    }
+
+    if chars.len() < 2 {
+        return None;
+    }
+    let dest_chars = chars.split_off(chars.len() - 2);
+    let dest = file_rank_to_xy_safe(dest_chars[0], dest_chars[1].to_digit(10)? as u8).ok()?;
+    // Whatever is left is the source file, rank or both
+    let src_file = chars.iter().find(|c| c.is_ascii_lowercase()).map(|c| *c as u8 - b'a');
+    let src_rank = chars.iter().find(|c| c.is_ascii_digit()).map(|c| 8 - c.to_digit(10).unwrap_or(0) as u8);
+
+    let mut found = legal_moves.into_iter().filter(|m| {
+        match get_parts(board, m) {
+            Some((src, move_piece, move_dest, move_promotion)) => {
+                move_piece == piece && move_dest == dest && move_promotion == promotion
+                    && src_file.map_or(true, |x| x == src.0) && src_rank.map_or(true, |y| y == src.1)
+                    && !matches!(m.get_description(), MoveDescription::Oo(_) | MoveDescription::Ooo(_))
+            },
+            None => false
+        }
+    });
+    let m = found.next()?;
+    if found.next().is_some() { None } else { Some(m) }
+}
+
+/// Game result from the PGN result token or `Result` tag
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unknown
+}
+
+impl GameResult {
+
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "1-0" => Some(GameResult::WhiteWins),
+            "0-1" => Some(GameResult::BlackWins),
+            "1/2-1/2" => Some(GameResult::Draw),
+            "*" => Some(GameResult::Unknown),
+            _ => None
+        }
+    }
+
+    pub fn to_token(self) -> &'static str {
+        match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Unknown => "*"
+        }
+    }
+}
+
+pub struct PgnGame {
+    /// Tag pairs in the order read, eg. `("White", "Carlsen, Magnus")`
+    pub tags: Vec<(String, String)>,
+    /// SAN of the main line
+    pub moves: Vec<String>,
+    pub result: GameResult
+}
+
+impl PgnGame {
+
+    fn new() -> Self {
+        Self { tags: Vec::new(), moves: Vec::new(), result: GameResult::Unknown }
+    }
+
+    pub fn get_tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// From the `FEN` tag, or the standard starting position
+    pub fn get_start_board(&self) -> Result<Board, FenError> {
+        match self.get_tag("FEN") {
+            Some(fen) => Board::from_fen(fen),
+            None => Ok(Board::new())
+        }
+    }
+
+    /// Plays the main line from the start, stopping at the first move which can't be read or isn't legal.
+    /// Calls `on_move` with the board before each move. Returns whether every move was played.
+    pub fn replay<F: FnMut(&mut Board, &MoveSnapshot)>(&self, mut on_move: F) -> bool {
+        let mut board = match self.get_start_board() {
+            Ok(board) => board,
+            Err(_) => return false
+        };
+        for san in self.moves.iter() {
+            match parse_san(&mut board, san) {
+                Some(m) => {
+                    on_move(&mut board, &m);
+                    board.handle_move(&m, true);
+                },
+                None => return false
+            };
+        }
+        true
+    }
+}
+
+/// Every game in `text`, a PGN collection. Games without moves or tags are skipped.
+pub fn parse_pgn(text: &str) -> Vec<PgnGame> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::new();
+    let mut chars = text.chars().peekable();
+    let mut variation_depth = 0;
+
+    let mut finish = |game: &mut PgnGame| {
+        let done = std::mem::replace(game, PgnGame::new());
+        if !done.moves.is_empty() || !done.tags.is_empty() {
+            games.push(done);
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                while let Some(c) = chars.next() {
+                    if c == '}' { break; }
+                }
+            },
+            ';' => {
+                while let Some(c) = chars.next() {
+                    if c == '\n' { break; }
+                }
+            },
+            '(' => variation_depth += 1,
+            ')' => variation_depth = (variation_depth - 1).max(0),
+            '[' if variation_depth == 0 => {
+                let mut tag = String::new();
+                while let Some(c) = chars.next() {
+                    if c == ']' { break; }
+                    tag.push(c);
+                }
+                // Tags after moves start the next game, eg. when a result token is missing
+                if !game.moves.is_empty() {
+                    finish(&mut game);
+                }
+                let tag = tag.trim();
+                if let Some(space) = tag.find(char::is_whitespace) {
+                    let value = tag[space..].trim();
+                    // Only the enclosing quotes, an escaped one can end the value
+                    let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value).replace("\\\"", "\"");
+                    game.tags.push((String::from(&tag[..space]), value));
+                }
+            },
+            c if c.is_whitespace() => (),
+            c => {
+                let mut token = String::new();
+                token.push(c);
+                while let Some(next) = chars.peek() {
+                    if next.is_whitespace() || "{}();[".contains(*next) { break; }
+                    token.push(*next);
+                    chars.next();
+                }
+                if variation_depth > 0 || token.starts_with('$') {
+                    continue;
+                }
+                if let Some(result) = GameResult::from_token(&token) {
+                    game.result = result;
+                    finish(&mut game);
+                    continue;
+                }
+
+                // Move numbers may be attached to the move, eg. `1.e4` or `12...Nf6`, but digits without dots are
+                // part of the move, eg. `0-0`
+                let number_len = token.find(|c: char| !c.is_ascii_digit()).unwrap_or(token.len());
+                let san = if number_len > 0 && token[number_len..].starts_with('.') {
+                    token[number_len..].trim_start_matches('.')
+                } else {
+                    &token
+                };
+                if !san.is_empty() {
+                    game.moves.push(String::from(san));
+                }
+            }
+        };
+    }
+    finish(&mut game);
+
+    for game in games.iter_mut() {
+        if game.result == GameResult::Unknown {
+            if let Some(result) = game.get_tag("Result").and_then(GameResult::from_token) {
+                game.result = result;
+            }
+        }
+    }
+    games
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERA_GAME: [&str; 33] = [
+        "e4", "e5", "Nf3", "d6", "d4", "Bg4", "dxe5", "Bxf3", "Qxf3", "dxe5", "Bc4", "Nf6", "Qb3", "Qe7", "Nc3", "c6", "Bg5",
+        "b5", "Nxb5", "cxb5", "Bxb5+", "Nbd7", "O-O-O", "Rd8", "Rxd7", "Rxd7", "Rd1", "Qe6", "Bxd7+", "Nxd7", "Qb8+", "Nxb8", "Rd8#"
+    ];
+
+    fn get_long_algebraic(fen: &str, san: &str) -> Option<String> {
+        let mut board = Board::from_fen(fen).unwrap();
+        parse_san(&mut board, san).map(|m| m.to_long_algebraic())
+    }
+
+    fn get_san(fen: &str, long_algebraic: &str) -> String {
+        let mut board = Board::from_fen(fen).unwrap();
+        let m = get_legal_moves(&mut board).into_iter().find(|m| m.to_long_algebraic() == long_algebraic).unwrap();
+        to_san(&mut board, &m)
+    }
+
+    #[test]
+    fn skips_comments_variations_and_nags() {
+        let text = r#"[Event "Paris \"Opera\""]
+[Result "1-0"]
+
+1. e4 {A comment (with brackets)} e5 2. Nf3 $1 ; to the end of the line 2. Nc3
+d6 (2... Nc6 3. Bb5 (3. Bc4 Bc5) a6) 3.d4!? Bg4?! 1-0
+"#;
+        let games = parse_pgn(text);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].get_tag("Event"), Some("Paris \"Opera\""));
+        assert_eq!(games[0].moves, vec!["e4", "e5", "Nf3", "d6", "d4!?", "Bg4?!"]);
+        assert_eq!(games[0].result, GameResult::WhiteWins);
+        assert!(games[0].replay(|_, _| ()));
+    }
+
+    #[test]
+    fn reads_results() {
+        let text = "[White \"a\"]\n1. e4 e5 1/2-1/2\n\n[Result \"0-1\"]\n1. d4 d5\n\n[Result \"1-0\"]\n1. c4 *\n1. f4 0-1";
+        let games = parse_pgn(text);
+        let results: Vec<GameResult> = games.iter().map(|game| game.result).collect();
+        // Without a result token, or with `*`, the `Result` tag is used
+        assert_eq!(results, vec![GameResult::Draw, GameResult::BlackWins, GameResult::WhiteWins, GameResult::BlackWins]);
+        assert_eq!(games[1].moves, vec!["d4", "d5"]);
+        assert_eq!(games[3].moves, vec!["f4"]);
+        for result in [GameResult::WhiteWins, GameResult::BlackWins, GameResult::Draw, GameResult::Unknown].iter() {
+            assert_eq!(GameResult::from_token(result.to_token()), Some(*result));
+        }
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(get_long_algebraic(fen, "O-O").as_deref(), Some("e1g1"));
+        assert_eq!(get_long_algebraic(fen, "0-0").as_deref(), Some("e1g1"));
+        assert_eq!(get_long_algebraic(fen, "O-O-O").as_deref(), Some("e1c1"));
+        assert_eq!(get_long_algebraic(fen, "0-0-0+").as_deref(), Some("e1c1"));
+        assert_eq!(get_long_algebraic("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "O-O-O").as_deref(), Some("e8c8"));
+        assert_eq!(get_long_algebraic("r3k2r/8/8/8/8/8/8/R3K2R w Qkq - 0 1", "O-O"), None);
+        assert_eq!(get_san(fen, "e1g1"), "O-O");
+        assert_eq!(get_san(fen, "e1c1"), "O-O-O");
+        // The king's move to the castling square isn't written as castling
+        assert_eq!(get_long_algebraic(fen, "Kg1"), None);
+    }
+
+    #[test]
+    fn promotion() {
+        let fen = "3r3k/4P3/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(get_long_algebraic(fen, "e8=Q+").as_deref(), Some("e7e8q"));
+        assert_eq!(get_long_algebraic(fen, "e8Q").as_deref(), Some("e7e8q"));
+        assert_eq!(get_long_algebraic(fen, "e8=N").as_deref(), Some("e7e8n"));
+        assert_eq!(get_long_algebraic(fen, "exd8=R+").as_deref(), Some("e7d8r"));
+        assert_eq!(get_long_algebraic(fen, "e8"), None);
+        assert_eq!(get_san(fen, "e7e8q"), "e8=Q+");
+        assert_eq!(get_san(fen, "e7d8n"), "exd8=N");
+    }
+
+    #[test]
+    fn disambiguation() {
+        let knights = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(get_san(knights, "b1d2"), "Nbd2");
+        assert_eq!(get_san(knights, "b1c3"), "Nc3");
+        assert_eq!(get_long_algebraic(knights, "Nfd2").as_deref(), Some("f3d2"));
+        assert_eq!(get_long_algebraic(knights, "Nf3d2").as_deref(), Some("f3d2"));
+        assert_eq!(get_long_algebraic(knights, "Nd2"), None);
+
+        let rooks = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(get_san(rooks, "a1a3"), "R1a3");
+        assert_eq!(get_long_algebraic(rooks, "R5a3").as_deref(), Some("a5a3"));
+
+        let queens = "1k6/8/K7/8/4Q2Q/8/8/7Q w - - 0 1";
+        assert_eq!(get_san(queens, "h4e1"), "Qh4e1");
+        assert_eq!(get_long_algebraic(queens, "Qh4e1").as_deref(), Some("h4e1"));
+        assert_eq!(get_long_algebraic(queens, "Qhe1"), None);
+    }
+
+    #[test]
+    fn san_round_trip() {
+        let mut board = Board::new();
+        for san in OPERA_GAME.iter() {
+            let m = parse_san(&mut board, san).unwrap_or_else(|| panic!("{} in {}", san, board.to_fen()));
+            assert_eq!(to_san(&mut board, &m), *san);
+            board.handle_move(&m, true);
+        }
+
+        for fen in [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "3r3k/4P3/8/8/8/8/8/4K3 w - - 0 1",
+            "1k6/8/K7/8/4Q2Q/8/8/7Q w - - 0 1"
+        ].iter() {
+            let mut board = Board::from_fen(fen).unwrap();
+            for m in get_legal_moves(&mut board) {
+                let san = to_san(&mut board, &m);
+                let parsed = parse_san(&mut board, &san).map(|m| m.to_long_algebraic());
+                assert_eq!(parsed, Some(m.to_long_algebraic()), "{} in {}", san, fen);
+            }
+        }
+    }
+}
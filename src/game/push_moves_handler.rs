@@ -2,11 +2,58 @@ use super::move_test::*;
 use super::entities::*;
 use super::coords::*;
 use super::move_list::*;
+use super::board::*;
 
 pub struct PushToMoveListHandler<'a> {
     pub move_list: &'a mut MoveList
 }
 
+/// Builds a "basic" move, ie. a capture or move, of `src_piece` to the destination, which is replaced with `replacement_piece` for promotions
+pub fn make_basic_move(
+    board: &Board,
+    src_x: u8,
+    src_y: u8,
+    src_piece: Piece,
+    src_player: Player,
+    dest_x: u8,
+    dest_y: u8,
+    existing_dest_square: &Square,
+    replacement_piece: Option<Piece>
+) -> MoveSnapshot {
+    let mut m = MoveSnapshot::default();
+
+    m.0[0] = Some((Coord(src_x, src_y), BeforeAfterSquares(
+        Square::Occupied(src_piece, src_player),
+        Square::Blank
+    )));
+
+    m.0[1] = Some((Coord(dest_x, dest_y), BeforeAfterSquares(
+        *existing_dest_square,
+        Square::Occupied(replacement_piece.unwrap_or(src_piece), src_player)
+    )));
+
+    let mut first_prevented_oo = false; 
+    let mut first_prevented_ooo = false;
+
+    let player_state = board.get_player_state(src_player);
+    if src_piece == Piece::Rook {
+        first_prevented_oo = src_x == 7 && !player_state.moved_oo_piece;
+        first_prevented_ooo = src_x == 0 && !player_state.moved_ooo_piece;
+    } else if src_piece == Piece::King {
+        first_prevented_oo = !player_state.moved_oo_piece;
+        first_prevented_ooo = !player_state.moved_ooo_piece;
+    }
+
+    // Since we are dealing with "basic" moves, there are only captures and moves
+    m.2 = if let Square::Occupied(_, _) = existing_dest_square {
+        MoveDescription::Capture(first_prevented_oo, first_prevented_ooo, 1)
+    } else {
+        MoveDescription::Move(first_prevented_oo, first_prevented_ooo, 1)
+    };
+
+    m
+}
+
 impl <'a> MoveTestHandler for PushToMoveListHandler<'a> {
 
     fn push(
@@ -21,38 +68,17 @@ impl <'a> MoveTestHandler for PushToMoveListHandler<'a> {
     ) -> bool {
         if !moveable { return false; }
 
-        let mut m = MoveSnapshot::default();
-
-        m.0[0] = Some((Coord(params.src_x as u8, params.src_y as u8), BeforeAfterSquares(
-            Square::Occupied(params.src_piece, params.src_player),
-            Square::Blank
-        )));
-
-        m.0[1] = Some((Coord(dest_x, dest_y), BeforeAfterSquares(
-            *existing_dest_square,
-            Square::Occupied(replacement_piece.unwrap_or(params.src_piece), params.src_player)
-        )));
-
-        let mut first_prevented_oo = false; 
-        let mut first_prevented_ooo = false;
-
-        let player_state = params.board.get_player_state(params.src_player);
-        if params.src_piece == Piece::Rook {
-            first_prevented_oo = params.src_x == 7 && !player_state.moved_oo_piece;
-            first_prevented_ooo = params.src_x == 0 && !player_state.moved_ooo_piece;
-        } else if params.src_piece == Piece::King {
-            first_prevented_oo = !player_state.moved_oo_piece;
-            first_prevented_ooo = !player_state.moved_ooo_piece;
-        }
-
-        // Since we are dealing with "basic" moves, there are only captures and moves
-        m.2 = if let Square::Occupied(_, _) = existing_dest_square {
-            MoveDescription::Capture(first_prevented_oo, first_prevented_ooo, 1)
-        } else {
-            MoveDescription::Move(first_prevented_oo, first_prevented_ooo, 1)
-        };
-
-        self.move_list.write(m);
+        self.move_list.write(make_basic_move(
+            params.board,
+            params.src_x as u8,
+            params.src_y as u8,
+            params.src_piece,
+            params.src_player,
+            dest_x,
+            dest_y,
+            existing_dest_square,
+            replacement_piece
+        ));
         return false;
     }
 }
@@ -30,12 +30,12 @@ impl SearchableMoves {
                 }
             }
 
-            if let MoveDescription::Oo = m.get_description() {
+            if let MoveDescription::Oo(_) = m.get_description() {
                 let sqs = m.get_squares();
                 self.map.insert(SearchableMoveKey(sqs[0].unwrap().0, sqs[3].unwrap().0), m.clone());
                 self.map.insert(SearchableMoveKey(sqs[0].unwrap().0, sqs[2].unwrap().0), m.clone());
                 self.map.insert(SearchableMoveKey(sqs[3].unwrap().0, sqs[0].unwrap().0), m.clone());
-            } else if let MoveDescription::Ooo = m.get_description() {
+            } else if let MoveDescription::Ooo(_) = m.get_description() {
                 let sqs = m.get_squares();
                 self.map.insert(SearchableMoveKey(sqs[0].unwrap().0, sqs[4].unwrap().0), m.clone());
                 self.map.insert(SearchableMoveKey(sqs[4].unwrap().0, sqs[0].unwrap().0), m.clone());
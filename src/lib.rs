@@ -4,10 +4,14 @@ extern crate console_error_panic_hook;
 
 mod extern_funcs;
 mod macros;
-mod game;
-mod ai;
+pub mod game;
+pub mod ai;
 
 use ai::*;
+use ai::skill::*;
+use ai::nnue::*;
+use ai::book::*;
+use ai::game_analysis::*;
 use game::memo::*;
 use game::coords::*;
 use game::entities::*;
@@ -15,6 +19,10 @@ use game::board::*;
 use game::castle_utils::*;
 use game::searchable_moves::*;
 use game::move_list::*;
+use game::check_handler::*;
+use game::pgn::*;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use wasm_bindgen::prelude::*;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -28,6 +36,65 @@ lazy_static! {
     pub static ref RANDOM_NUMBER_KEYS: RandomNumberKeys = RandomNumberKeys::new();
 }
 
+fn load_nnue(ai: &mut Ai, bytes: &[u8]) -> bool {
+    match Network::from_bytes(bytes) {
+        Ok(net) => {
+            ai.set_evaluator(Some(Box::new(NnueEvaluator::new(Arc::new(net)))));
+            true
+        },
+        Err(e) => {
+            console_error!("Bad network - {}", e);
+            false
+        }
+    }
+}
+
+/// Replaces `book` unless `bytes` can't be read
+fn load_book(book: &mut Option<Book>, bytes: &[u8]) -> bool {
+    match Book::from_bytes(bytes) {
+        Ok(loaded) => {
+            *book = Some(loaded);
+            true
+        },
+        Err(e) => {
+            console_error!("Bad book - {}", e);
+            false
+        }
+    }
+}
+
+/// Most moves of a hint's line, see `Main::get_hint`
+const HINT_PV_LEN: usize = 4;
+
+/// Values per move in the arrays from `Main::get_legal_moves`, see `write_move_info`
+const MOVE_INFO_LEN: usize = 6;
+const MOVE_FLAG_CAPTURE: u8 = 1;
+const MOVE_FLAG_CASTLE: u8 = 2;
+const MOVE_FLAG_CHECK: u8 = 4;
+
+/// Appends `[from x, from y, to x, to y, promotion, flags]` for legal move `m` of `board`.
+/// Castling goes from and to the king's squares. Promotion is 0 or the piece as in `Main::get_piece`,
+/// flags are `MOVE_FLAG_*` bits.
+fn write_move_info(board: &mut Board, m: &MoveSnapshot, out: &mut Vec<u8>) {
+    let (src, dest, promotion) = match m.get_src_dest_promotion() {
+        Some(x) => x,
+        None => return
+    };
+
+    let mut flags = 0;
+    match m.get_description() {
+        MoveDescription::Capture(_, _, _) => flags |= MOVE_FLAG_CAPTURE,
+        MoveDescription::Oo(_) | MoveDescription::Ooo(_) => flags |= MOVE_FLAG_CASTLE,
+        _ => ()
+    };
+    let player = board.get_player_with_turn();
+    board.handle_move(m, true);
+    if is_checking(board, player) { flags |= MOVE_FLAG_CHECK; }
+    board.handle_move(m, false);
+
+    out.extend_from_slice(&[src.0, src.1, dest.0, dest.1, promotion.map_or(0, |p| p as u8 + 1), flags]);
+}
+
 #[wasm_bindgen]
 pub struct Main {
     board: Board,
@@ -35,7 +102,39 @@ pub struct Main {
 
     temp: MoveList,
     move_list: MoveList,
-    searchable: SearchableMoves
+    searchable: SearchableMoves,
+    analysis: Vec<PvLine>,
+    skill_level: u8,
+    /// Consulted by `make_ai_move` before searching
+    book: Option<Book>,
+    /// Board from the last `set_fen`, which `history` is played from
+    start_board: Board,
+    /// Moves played since the last `set_fen`, undone with `handle_move(m, false)`
+    history: Vec<MoveSnapshot>,
+    /// Undone moves, the next to redo last. Cleared by any new move.
+    redo_moves: Vec<MoveSnapshot>,
+    game_analysis: Option<GameAnalysis>
+}
+
+impl Main {
+
+    fn get_legal_moves_matching<F: Fn(Coord) -> bool>(&mut self, is_match: F) -> Vec<u8> {
+        self.refresh_player_moves();
+        let mut info = Vec::new();
+        for i in 0..self.move_list.write_index {
+            let m = &self.move_list.get_v()[i];
+            if m.get_src_dest_promotion().map_or(false, |(src, _, _)| is_match(src)) {
+                write_move_info(&mut self.board, m, &mut info);
+            }
+        }
+        info
+    }
+
+    fn play_move(&mut self, m: MoveSnapshot) {
+        self.board.handle_move(&m, true);
+        self.history.push(m);
+        self.redo_moves.clear();
+    }
 }
 
 #[wasm_bindgen]
@@ -50,17 +149,287 @@ impl Main {
 
         let board = Board::new();
         Main {
+            start_board: board.clone(),
             board, 
             ai: Ai::new(),
 
             temp: MoveList::new(50),
             move_list: MoveList::new(50),
-            searchable: SearchableMoves::new()
+            searchable: SearchableMoves::new(),
+            analysis: Vec::new(),
+            skill_level: MAX_SKILL_LEVEL,
+            book: None,
+            history: Vec::new(),
+            redo_moves: Vec::new(),
+            game_analysis: None
         }
     }
 
+    /// Plays a book move if there is one, otherwise searches
     pub fn make_ai_move(&mut self) {
-        self.ai.make_move(5, &mut self.board);
+        let board = &mut self.board;
+        if let Some(m) = self.book.as_ref().and_then(|book| book.pick_move(board)) {
+            console_log!("Book move: {}", m);
+            self.play_move(m);
+            return;
+        }
+        if let Some(m) = self.ai.choose_move(&self.board, SkillLevel::get(self.skill_level)) {
+            self.play_move(m);
+        }
+    }
+
+    /// Takes back the last move. Returns false if there is none.
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop() {
+            Some(m) => {
+                self.board.handle_move(&m, false);
+                self.redo_moves.push(m);
+                self.refresh_player_moves();
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Plays the last undone move again. Returns false if there is none.
+    pub fn redo(&mut self) -> bool {
+        match self.redo_moves.pop() {
+            Some(m) => {
+                self.board.handle_move(&m, true);
+                self.history.push(m);
+                self.refresh_player_moves();
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Undoes the AI's reply and the player's move before it, so that the player is to move again.
+    /// Returns false and changes nothing if fewer than 2 moves were played.
+    pub fn takeback(&mut self) -> bool {
+        if self.history.len() < 2 {
+            return false;
+        }
+        self.undo();
+        self.undo();
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_moves.is_empty()
+    }
+
+    /// Every legal move as a flat array of 6 values per move, `[from x, from y, to x, to y, promotion, flags]`.
+    /// Promotion is 0 or the piece as in `get_piece`. Flags are bits, 1 for captures, 2 for castling and 4 for checks.
+    pub fn get_legal_moves(&mut self) -> Vec<u8> {
+        self.get_legal_moves_matching(|_| true)
+    }
+
+    /// Legal moves of the piece at `x`, `y` in the same layout as `get_legal_moves`, empty if there are none
+    pub fn get_legal_moves_from(&mut self, x: i32, y: i32) -> Vec<u8> {
+        if check_i32_xy(x, y).is_err() { return Vec::new(); }
+        self.get_legal_moves_matching(|src| src == Coord(x as u8, y as u8))
+    }
+
+    /// Last move played in the same layout as `get_legal_moves`, flagged as of when it was played, or empty
+    pub fn get_last_move(&mut self) -> Vec<u8> {
+        let mut info = Vec::with_capacity(MOVE_INFO_LEN);
+        if let Some(m) = self.history.last() {
+            self.board.handle_move(m, false);
+            write_move_info(&mut self.board, m, &mut info);
+            self.board.handle_move(m, true);
+        }
+        info
+    }
+
+    /// From 0 to `get_max_skill_level`, the max being full strength
+    pub fn set_skill_level(&mut self, level: u8) {
+        self.skill_level = level.min(MAX_SKILL_LEVEL);
+    }
+
+    pub fn get_skill_level(&self) -> u8 {
+        self.skill_level
+    }
+
+    pub fn get_max_skill_level(&self) -> u8 {
+        MAX_SKILL_LEVEL
+    }
+
+    /// Picks the level closest to a rough Elo rating
+    pub fn set_skill_elo(&mut self, elo: u32) {
+        self.skill_level = SkillLevel::get_level_for_elo(elo);
+    }
+
+    pub fn get_skill_elo(&self) -> u32 {
+        SkillLevel::get(self.skill_level).elo
+    }
+
+    /// Suggests a move without making it, searching at full strength to `depth`, or until `max_nodes` unless 0.
+    /// Returns `{"move", "san", "eval", "depth", "pv": [...]}` with the line in SAN, or `null` if there are no moves
+    /// or the search stopped before finishing depth 1. The search's memo is kept, so that a following
+    /// `make_ai_move` starts from it. A `Searcher` has its own memo, see `Searcher::keep_memo_once` to do the same in a worker.
+    pub fn get_hint(&mut self, depth: u8, max_nodes: u32) -> String {
+        self.ai.set_node_limit(if max_nodes > 0 { Some(max_nodes) } else { None });
+        self.ai.keep_memo_once();
+        let lines = self.ai.search(depth.max(1), &self.board, 1);
+        self.ai.set_node_limit(None);
+
+        let line = match lines.first() {
+            Some(line) if !line.moves.is_empty() => line,
+            _ => return String::from("null")
+        };
+        let mut board = self.board.clone();
+        let sans: Vec<String> = line.moves.iter().take(HINT_PV_LEN).map(|m| {
+            let san = to_san(&mut board, m);
+            board.handle_move(m, true);
+            san
+        }).collect();
+
+        format!(
+            "{{\"move\":\"{}\",\"san\":\"{}\",\"eval\":{},\"depth\":{},\"pv\":[{}]}}",
+            line.moves[0].to_long_algebraic(),
+            sans[0],
+            line.eval,
+            line.depth,
+            sans.iter().map(|san| format!("\"{}\"", san)).collect::<Vec<String>>().join(",")
+        )
+    }
+
+    /// Analyzes every move played since the last `set_fen`, searching each position to `depth`, or until `max_nodes`
+    /// per iteration unless 0. Returns the report as in `GameAnalysis::to_json`, which is kept for `get_game_analysis_pgn`.
+    pub fn analyze_game(&mut self, depth: u8, max_nodes: u32) -> String {
+        let node_limit = if max_nodes > 0 { Some(max_nodes) } else { None };
+        let analysis = self.ai.analyze_game(&self.start_board, &self.history, depth.max(1), node_limit);
+        let json = analysis.to_json();
+        self.game_analysis = Some(analysis);
+        json
+    }
+
+    /// Annotated PGN of the last `analyze_game`, or empty if there was none
+    pub fn get_game_analysis_pgn(&self) -> String {
+        self.game_analysis.as_ref().map(|analysis| analysis.to_pgn()).unwrap_or_default()
+    }
+
+    /// Searches the current position without making a move, returns the number of lines found.
+    /// Results are read with `get_analysis_eval` and `get_analysis_line`.
+    pub fn analyze(&mut self, depth: u8, multi_pv: usize) -> usize {
+        self.analysis = self.ai.search(depth, &self.board, multi_pv);
+        self.analysis.len()
+    }
+
+    /// From the point of view of the player with the turn, or NaN if out of range
+    pub fn get_analysis_eval(&self, i: usize) -> f32 {
+        self.analysis.get(i).map(|line| line.eval).unwrap_or(f32::NAN)
+    }
+
+    /// Space separated coordinate notation, eg. "e2e4 e7e5"
+    pub fn get_analysis_line(&self, i: usize) -> String {
+        match self.analysis.get(i) {
+            Some(line) => line.moves.iter().map(|m| m.to_long_algebraic()).collect::<Vec<String>>().join(" "),
+            None => String::new()
+        }
+    }
+
+    /// Returns false if there is no such param
+    pub fn set_eval_param(&mut self, name: &str, value: f32) -> bool {
+        let mut params = self.ai.get_eval_params().clone();
+        if !params.set(name, value) {
+            console_error!("Unknown eval param {}", name);
+            return false;
+        }
+        self.ai.set_eval_params(params);
+        true
+    }
+
+    /// NaN if there is no such param
+    pub fn get_eval_param(&self, name: &str) -> f32 {
+        self.ai.get_eval_params().get(name).unwrap_or(f32::NAN)
+    }
+
+    /// Flat object of every param by name
+    pub fn get_eval_params_json(&self) -> String {
+        self.ai.get_eval_params().to_json()
+    }
+
+    /// Params missing from `json` are unchanged. Returns false and changes nothing if `json` can't be read.
+    pub fn set_eval_params_json(&mut self, json: &str) -> bool {
+        let mut params = self.ai.get_eval_params().clone();
+        match params.update_from_json(json) {
+            Ok(()) => {
+                self.ai.set_eval_params(params);
+                true
+            },
+            Err(e) => {
+                console_error!("Bad eval params - {}", e);
+                false
+            }
+        }
+    }
+
+    /// Evaluates with a network in the `nnue` module's format instead of the hand written evaluation.
+    /// Returns false and changes nothing if `bytes` can't be read.
+    pub fn load_nnue(&mut self, bytes: &[u8]) -> bool {
+        load_nnue(&mut self.ai, bytes)
+    }
+
+    /// Goes back to the hand written evaluation after `load_nnue`
+    pub fn unload_nnue(&mut self) {
+        self.ai.set_evaluator(None);
+    }
+
+    /// Plays from a Polyglot book, eg. fetched as an `ArrayBuffer`, before searching.
+    /// Returns false and changes nothing if `bytes` can't be read.
+    pub fn load_book(&mut self, bytes: &[u8]) -> bool {
+        load_book(&mut self.book, bytes)
+    }
+
+    pub fn unload_book(&mut self) {
+        self.book = None;
+    }
+
+    /// Static evaluation of the current position by term and side, see `EvalTrace::to_json`
+    pub fn get_eval_trace_json(&mut self) -> String {
+        self.ai.trace_eval(&self.board).to_json()
+    }
+
+    pub fn get_fen(&self) -> String {
+        self.board.to_fen()
+    }
+
+    /// Returns false and keeps the current board if the FEN can't be read
+    pub fn set_fen(&mut self, fen: &str) -> bool {
+        match Board::from_fen(fen) {
+            Ok(board) => {
+                self.start_board = board.clone();
+                self.board = board;
+                self.history.clear();
+                self.redo_moves.clear();
+                self.refresh_player_moves();
+                true
+            },
+            Err(e) => {
+                console_error!("Bad FEN {} - {:?}", fen, e);
+                false
+            }
+        }
+    }
+
+    /// Makes a legal move given in coordinate notation, eg. the result of a `Searcher`
+    pub fn apply_move(&mut self, long_algebraic: &str) -> bool {
+        self.refresh_player_moves();
+        for i in 0..self.move_list.write_index {
+            let m = &self.move_list.get_v()[i];
+            if m.to_long_algebraic() == long_algebraic {
+                let m = m.clone();
+                self.play_move(m);
+                return true;
+            }
+        }
+        false
     }
 
     pub fn refresh_player_moves(&mut self) {
@@ -74,9 +443,9 @@ impl Main {
         if check_i32_xy(from_x, from_y).is_err() { return false; }
         if check_i32_xy(to_x, to_y).is_err() { return false; }
 
-        let _m = self.searchable.get_move(Coord(from_x as u8, from_y as u8), Coord(to_x as u8, to_y as u8));
+        let _m = self.searchable.get_move(Coord(from_x as u8, from_y as u8), Coord(to_x as u8, to_y as u8)).cloned();
         if let Some(m) = _m {
-            self.board.handle_move(m, true);
+            self.play_move(m);
             true
         } else {
             false
@@ -93,3 +462,235 @@ impl Main {
         }
     }
 }
+
+/// Search which can run inside a Web Worker without blocking it for the whole search.
+/// Call `begin` with a position, then `step` until it returns false, yielding in between
+/// so that `stop` can be called. Progress is read with `get_info_json` after each step.
+#[wasm_bindgen]
+pub struct Searcher {
+    ai: Ai,
+    board: Board,
+    max_depth: u8,
+    next_depth: u8,
+    /// Of the best line
+    info: Option<SearchInfo>,
+    searching: bool,
+    skill_level: u8,
+    /// MultiPV lines to search for the skill level, one iteration per step
+    candidates: usize,
+    /// Finished MultiPV lines
+    lines: Vec<PvLine>,
+    /// Last finished iteration of the line being searched
+    line_info: Option<SearchInfo>,
+    /// Move picked by a handicapped skill level, replacing the best move
+    skill_move: Option<MoveSnapshot>,
+    book: Option<Book>,
+    /// Picked from the book by `begin` instead of searching
+    book_move: Option<MoveSnapshot>
+}
+
+#[wasm_bindgen]
+impl Searcher {
+
+    pub fn new() -> Searcher {
+        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+
+        Searcher {
+            ai: Ai::new(),
+            board: Board::new(),
+            max_depth: 0,
+            next_depth: 1,
+            info: None,
+            searching: false,
+            skill_level: MAX_SKILL_LEVEL,
+            candidates: 1,
+            lines: Vec::new(),
+            line_info: None,
+            skill_move: None,
+            book: None,
+            book_move: None
+        }
+    }
+
+    /// Applies from the next `begin`, see `Main::set_skill_level`
+    pub fn set_skill_level(&mut self, level: u8) {
+        self.skill_level = level.min(MAX_SKILL_LEVEL);
+    }
+
+    /// Applies from the next `begin`, see `Main::set_eval_params_json`
+    pub fn set_eval_params_json(&mut self, json: &str) -> bool {
+        let mut params = self.ai.get_eval_params().clone();
+        match params.update_from_json(json) {
+            Ok(()) => {
+                self.ai.set_eval_params(params);
+                true
+            },
+            Err(e) => {
+                console_error!("Bad eval params - {}", e);
+                false
+            }
+        }
+    }
+
+    /// Applies from the next `begin`, see `Main::load_nnue`
+    pub fn load_nnue(&mut self, bytes: &[u8]) -> bool {
+        load_nnue(&mut self.ai, bytes)
+    }
+
+    pub fn unload_nnue(&mut self) {
+        self.ai.set_evaluator(None);
+    }
+
+    /// Applies from the next `begin`, see `Main::load_book`
+    pub fn load_book(&mut self, bytes: &[u8]) -> bool {
+        load_book(&mut self.book, bytes)
+    }
+
+    pub fn unload_book(&mut self) {
+        self.book = None;
+    }
+
+    /// Returns false if the FEN can't be read. With a book move for the position, `step` returns false
+    /// straight away and `get_best_move` gives the book move.
+    pub fn begin(&mut self, fen: &str, max_depth: u8) -> bool {
+        let mut board = match Board::from_fen(fen) {
+            Ok(board) => board,
+            Err(e) => {
+                console_error!("Bad FEN {} - {:?}", fen, e);
+                return false;
+            }
+        };
+
+        if self.searching {
+            self.ai.end_search();
+            self.searching = false;
+        }
+        self.info = None;
+        self.skill_move = None;
+        self.book_move = self.book.as_ref().and_then(|book| book.pick_move(&mut board));
+        if let Some(m) = &self.book_move {
+            console_log!("Book move: {}", m);
+            self.board = board;
+            return true;
+        }
+
+        let skill = SkillLevel::get(self.skill_level);
+        self.ai.get_stop_handle().store(false, Ordering::Relaxed);
+        self.ai.set_node_limit(skill.node_limit);
+        self.ai.begin_search(&board);
+        self.board = board;
+        self.max_depth = max_depth.min(skill.depth);
+        self.next_depth = 1;
+        self.candidates = if skill.is_handicapped() { skill.candidates } else { 1 };
+        self.lines.clear();
+        self.line_info = None;
+        self.searching = true;
+        true
+    }
+
+    /// Searches one more iteration, of the next MultiPV line once one is finished if the skill level needs them.
+    /// Returns false without searching once finished, stopped or out of moves.
+    pub fn step(&mut self) -> bool {
+        if !self.searching { return false; }
+
+        if self.next_depth <= self.max_depth && !self.ai.get_stop_handle().load(Ordering::Relaxed) {
+            let prev_eval = self.line_info.as_ref().map(|info| info.eval);
+            if let Some(info) = self.ai.search_depth(self.next_depth, prev_eval) {
+                if self.lines.is_empty() {
+                    self.info = Some(info.clone());
+                }
+                self.line_info = Some(info);
+                self.next_depth += 2;
+                return true;
+            }
+        }
+
+        // The line is finished, or out of its nodes, with its last finished iteration
+        let stopped = self.ai.get_stop_handle().load(Ordering::Relaxed);
+        if let Some(info) = self.line_info.take().filter(|info| !info.pv.is_empty()) {
+            self.lines.push(PvLine { eval: info.eval, depth: info.depth, moves: info.pv });
+            let prev_move = self.lines.last().and_then(|line| line.moves.first().cloned());
+            if let (Some(prev_move), false, true) = (prev_move, stopped, self.lines.len() < self.candidates) {
+                self.ai.begin_next_line(&prev_move);
+                self.next_depth = 1;
+                return true;
+            }
+        }
+
+        self.ai.end_search();
+        self.searching = false;
+
+        let skill = SkillLevel::get(self.skill_level);
+        if skill.is_handicapped() && !stopped {
+            self.skill_move = self.ai.pick_move(&self.board, &self.lines, skill);
+        }
+        self.ai.set_node_limit(None);
+        false
+    }
+
+    pub fn stop(&mut self) {
+        self.ai.get_stop_handle().store(true, Ordering::Relaxed);
+    }
+
+    /// Keeps the memo of the search started by the last `begin` for the next one, eg. searching a hint
+    /// and then the reply to the move played, as `Main::get_hint` does
+    pub fn keep_memo_once(&mut self) {
+        if self.searching {
+            self.ai.keep_memo_once();
+        }
+    }
+
+    /// Book move, or best move of the last finished iteration, or the skill level's pick, in coordinate notation, or empty
+    pub fn get_best_move(&self) -> String {
+        if let Some(m) = self.book_move.as_ref().or(self.skill_move.as_ref()) {
+            return m.to_long_algebraic();
+        }
+        self.info.as_ref()
+            .and_then(|info| info.pv.first())
+            .map(|m| m.to_long_algebraic())
+            .unwrap_or_default()
+    }
+
+    /// `{"depth", "eval", "nodes", "memoHits", "fastFoundHits", "reSearches", "elapsedMs", "pv": [...]}` or `null`
+    pub fn get_info_json(&self) -> String {
+        match &self.info {
+            Some(info) => format!(
+                "{{\"depth\":{},\"eval\":{},\"nodes\":{},\"memoHits\":{},\"fastFoundHits\":{},\"reSearches\":{},\"elapsedMs\":{},\"pv\":[{}]}}",
+                info.depth,
+                info.eval,
+                info.nodes,
+                info.memo_hits,
+                info.fast_found_hits,
+                info.aspiration_researches,
+                info.elapsed_ms,
+                info.pv.iter().map(|m| format!("\"{}\"", m.to_long_algebraic())).collect::<Vec<String>>().join(",")
+            ),
+            None => String::from("null")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_undo_restores(fen: &str, long_algebraic: &str) {
+        let mut main = Main::new();
+        assert!(main.set_fen(fen));
+        let hash = main.board.get_hash();
+
+        assert!(main.apply_move(long_algebraic));
+        assert!(main.undo());
+        assert_eq!(main.get_fen(), fen);
+        assert_eq!(main.board.get_hash(), hash);
+        assert_eq!(main.board.get_hash(), Board::from_fen(fen).unwrap().get_hash());
+    }
+
+    #[test]
+    fn undo_castle_keeps_partial_rights() {
+        assert_undo_restores("r3k2r/8/8/8/8/8/8/R3K2R w K - 0 1", "e1g1");
+        assert_undo_restores("r3k2r/8/8/8/8/8/8/R3K2R w Qk - 0 1", "e1c1");
+        assert_undo_restores("r3k2r/8/8/8/8/8/8/R3K2R b KQk - 0 1", "e8g8");
+        assert_undo_restores("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1");
+    }
+}
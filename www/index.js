@@ -1,4 +1,5 @@
 import * as wasm from './node_modules/chess_bs';
+import SearchWorker from 'worker-loader!./search_worker.js';
 
 import bb from './assets/bb.png';
 import bw from './assets/bw.png';
@@ -17,6 +18,9 @@ const imageUrls = {
     bb, bw, kb, kw, nb, nw, pb, pw, qb, qw, rb, rw
 };
 
+// Optional Polyglot opening book served next to the page, eg. written by the make_book tool
+const BOOK_URL = 'book.bin';
+
 class Application {
     constructor() {
 
@@ -27,6 +31,10 @@ class Application {
         this.draggedSqY = 0;
 
         this.main = wasm.Main.new();
+        this.aiDepth = 5;
+        this.searchWorker = new SearchWorker();
+        this.searchWorker.onmessage = this.onSearchWorkerMessage.bind(this);
+        this.loadBook(BOOK_URL);
         this.LEN = (0.9 * Math.min(window.innerWidth, window.innerHeight - document.getElementById('title').getBoundingClientRect().height) / 8) >>> 0;
 
         // Pawn = 0, Rook, Knight, Bishop, Queen, King
@@ -78,11 +86,51 @@ class Application {
         }
 
         this.isPlayerWhite = Math.random() > 0.5;
-        if (!this.isPlayerWhite) {
-            this.main.make_ai_move();
-        }
         this.main.refresh_player_moves();
         this.updateFromWasm();
+        if (!this.isPlayerWhite) {
+            this.requestAiMove();
+        }
+    }
+
+    //////////////////////////////////////////////////
+
+    // Searches requested before the book has arrived play without it
+    loadBook(url) {
+        fetch(url)
+            .then(response => response.ok ? response.arrayBuffer() : Promise.reject(response.status))
+            .then(bytes => this.searchWorker.postMessage({type: 'loadBook', bytes}, [bytes]))
+            .catch(reason => console.log('No opening book at', url, reason));
+    }
+
+    requestAiMove() {
+        this.boardLock = true;
+        console.log('Locked board');
+        this.searchWorker.postMessage({
+            type: 'search',
+            fen: this.main.get_fen(),
+            maxDepth: this.aiDepth,
+            skillLevel: this.main.get_skill_level(),
+            evalParams: this.main.get_eval_params_json()
+        });
+    }
+
+    onSearchWorkerMessage(e) {
+        const data = e.data;
+        if (data.type === 'info') {
+            console.log(`Depth ${data.info.depth}, eval ${data.info.eval}, nodes ${data.info.nodes}, ${data.info.elapsedMs} ms, pv ${data.info.pv.join(' ')}`);
+        } else if (data.type === 'bestmove') {
+            if (data.move && !this.main.apply_move(data.move)) {
+                console.error('AI move was not legal', data.move);
+            }
+            this.updateFromWasm();
+            this.main.refresh_player_moves();
+            this.boardLock = false;
+            console.log('Unlocked board');
+        } else if (data.type === 'error') {
+            console.error('Search worker error', data.message);
+            this.boardLock = false;
+        }
     }
 
     //////////////////////////////////////////////////
@@ -103,6 +151,11 @@ class Application {
         const image = row[sqCoords.x];
         if (image === undefined || image.style.visibility === 'hidden') return;
 
+        // Only pieces with legal moves can be picked up, so there are no fake premoves
+        const fromX = this.isPlayerWhite ? sqCoords.x : 7 - sqCoords.x;
+        const fromY = this.isPlayerWhite ? sqCoords.y : 7 - sqCoords.y;
+        if (this.main.get_legal_moves_from(fromX, fromY).length === 0) return;
+
         image.style.visibility = 'hidden';
         this.draggedImage = image;
         this.dragged.src = image.src;
@@ -145,16 +198,7 @@ class Application {
         }
 
         this.updateFromWasm();
-
-        this.boardLock = true;
-        console.log('Locked board');
-        setTimeout(() => {
-            this.main.make_ai_move();
-            this.updateFromWasm();
-            this.main.refresh_player_moves();
-            this.boardLock = false;
-            console.log('Unlocked board');
-        }, 250);
+        this.requestAiMove();
     }
 
     //////////////////////////////////////////////////
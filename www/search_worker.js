@@ -0,0 +1,68 @@
+// Runs the engine off the main thread, see `Searcher` on the Rust side.
+// In:  {type: 'search', fen, maxDepth, skillLevel, evalParams, keepMemo} | {type: 'stop'}, `evalParams` is optional JSON,
+//      `keepMemo` optionally keeps the search's memo for the next one, eg. for a hint before the reply to the player's move
+//      | {type: 'loadBook', bytes}, a Polyglot book as an `ArrayBuffer` which later searches play from
+// Out: {type: 'info', info} after every finished depth, then {type: 'bestmove', move, info},
+//      or {type: 'error', message}
+
+const wasmPromise = import('./node_modules/chess_bs');
+
+let searcher = null;
+let searchId = 0;
+
+// Lets queued messages such as 'stop' run between iterations
+const yieldToEvents = () => new Promise(resolve => setTimeout(resolve, 0));
+
+async function getSearcher() {
+    const wasm = await wasmPromise;
+    if (searcher === null) searcher = wasm.Searcher.new();
+    return searcher;
+}
+
+// A bad book is logged and leaves the searcher without one, which isn't worth failing a search over
+async function loadBook(bytes) {
+    (await getSearcher()).load_book(new Uint8Array(bytes));
+}
+
+async function search(fen, maxDepth, skillLevel, evalParams, keepMemo) {
+    const id = ++searchId;
+    await getSearcher();
+    if (id !== searchId) return;
+
+    searcher.set_skill_level(skillLevel);
+    if (evalParams && !searcher.set_eval_params_json(evalParams)) {
+        postMessage({type: 'error', message: 'Bad eval params'});
+        return;
+    }
+    if (!searcher.begin(fen, maxDepth)) {
+        postMessage({type: 'error', message: 'Bad FEN ' + fen});
+        return;
+    }
+    if (keepMemo) searcher.keep_memo_once();
+
+    while (searcher.step()) {
+        postMessage({type: 'info', info: JSON.parse(searcher.get_info_json())});
+        await yieldToEvents();
+        // A newer search replaced this one, and will post its own result
+        if (id !== searchId) return;
+    }
+
+    postMessage({
+        type: 'bestmove',
+        move: searcher.get_best_move(),
+        info: JSON.parse(searcher.get_info_json())
+    });
+}
+
+onmessage = e => {
+    const data = e.data;
+    if (data.type === 'search') {
+        search(data.fen, data.maxDepth, data.skillLevel, data.evalParams, data.keepMemo).catch(err => {
+            postMessage({type: 'error', message: String(err)});
+        });
+    } else if (data.type === 'stop') {
+        if (searcher !== null) searcher.stop();
+    } else if (data.type === 'loadBook') {
+        loadBook(data.bytes).catch(err => console.error('Loading book failed', err));
+    }
+};